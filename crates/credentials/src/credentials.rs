@@ -18,6 +18,10 @@ pub enum KeyError {
    AccessKeyTooShort,
    SecretKeyTooShort,
    IoError(String),
+   CorruptStore(String),
+   InvalidMasterKey,
+   MissingMasterKey,
+   DecryptionFailed,
 }
 
 impl std::fmt::Display for KeyError {
@@ -31,10 +35,16 @@ impl std::fmt::Display for KeyError {
            KeyError::AccessKeyTooShort => write!(f, "auth: access key length is too short"),
            KeyError::SecretKeyTooShort => write!(f, "auth: secret key length is too short"),
            KeyError::IoError(msg) => write!(f, "IO error: {}", msg),
+           KeyError::CorruptStore(msg) => write!(f, "credential store is corrupt: {}", msg),
+           KeyError::InvalidMasterKey => write!(f, "master key must be {} hex encoded bytes", crate::envelope::KEY_LEN),
+           KeyError::MissingMasterKey => write!(f, "no master key, pass a key file or set {}", crate::MASTER_KEY_ENV),
+           KeyError::DecryptionFailed => write!(f, "could not decrypt secret, wrong master key?"),
        }
    }
 }
 
+impl std::error::Error for KeyError {}
+
 pub fn generate_access_key(length: usize) -> Result<String, KeyError> {
         if length < MIN_LEG_ACCESS_KEY {
             return Err(KeyError::AccessKeyTooShort)
@@ -66,4 +76,4 @@ pub fn generate_secret_key(length: usize) -> Result<String, KeyError> {
             result.push(ALPHA_NUMERIC_TABLE[idx] as char);
         }
         Ok(result)
-}
\ No newline at end of file
+}
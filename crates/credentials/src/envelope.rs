@@ -0,0 +1,182 @@
+// AES-256-GCM envelope encryption. every sealed value gets its own random data key,
+// and only that data key is encrypted with the master key. rekeying just rewraps the
+// data keys so the payloads never have to be touched
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::credentials::KeyError;
+use crate::MASTER_KEY_ENV;
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct MasterKey([u8; KEY_LEN]);
+
+// dont want the key bytes ending up in logs
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+impl MasterKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        rand::rng().fill_bytes(&mut key);
+        Self(key)
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn from_hex(encoded: &str) -> Result<Self, KeyError> {
+        let bytes = hex::decode(encoded.trim()).map_err(|_| KeyError::InvalidMasterKey)?;
+        let key: [u8; KEY_LEN] = bytes.try_into().map_err(|_| KeyError::InvalidMasterKey)?;
+        Ok(Self(key))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, KeyError> {
+        let encoded = std::fs::read_to_string(path)
+            .map_err(|e| KeyError::IoError(format!("{}: {}", path.display(), e)))?;
+        Self::from_hex(&encoded)
+    }
+
+    /// Key file wins over the `lumi_master_key` env var. `None` means neither was given.
+    pub fn load(path: Option<&Path>) -> Result<Option<Self>, KeyError> {
+        if let Some(path) = path {
+            return Self::from_file(path).map(Some);
+        }
+        match std::env::var(MASTER_KEY_ENV) {
+            Ok(encoded) => Self::from_hex(&encoded).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// data key encrypted with the master key, hex(nonce || ciphertext)
+    pub wrapped_key: String,
+    /// payload encrypted with the data key, hex(nonce || ciphertext)
+    pub ciphertext: String,
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Encrypts `plaintext` under `key` with a fresh random nonce, returns nonce || ciphertext.
+pub fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = random_bytes::<NONCE_LEN>();
+    let mut out = nonce.to_vec();
+    out.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| KeyError::DecryptionFailed)?,
+    );
+    Ok(out)
+}
+
+pub fn decrypt(key: &[u8; KEY_LEN], sealed: &[u8]) -> Result<Vec<u8>, KeyError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(KeyError::DecryptionFailed);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| KeyError::DecryptionFailed)
+}
+
+/// Wraps a raw data key with the master key.
+pub fn wrap_key(master: &MasterKey, data_key: &[u8; KEY_LEN]) -> Result<String, KeyError> {
+    Ok(hex::encode(encrypt(master.as_bytes(), data_key)?))
+}
+
+pub fn unwrap_key(master: &MasterKey, wrapped: &str) -> Result<[u8; KEY_LEN], KeyError> {
+    let sealed = hex::decode(wrapped).map_err(|_| KeyError::DecryptionFailed)?;
+    decrypt(master.as_bytes(), &sealed)?
+        .try_into()
+        .map_err(|_| KeyError::DecryptionFailed)
+}
+
+impl Envelope {
+    pub fn seal(master: &MasterKey, plaintext: &[u8]) -> Result<Self, KeyError> {
+        let data_key = random_bytes::<KEY_LEN>();
+        Ok(Self {
+            wrapped_key: wrap_key(master, &data_key)?,
+            ciphertext: hex::encode(encrypt(&data_key, plaintext)?),
+        })
+    }
+
+    pub fn open(&self, master: &MasterKey) -> Result<Vec<u8>, KeyError> {
+        let data_key = unwrap_key(master, &self.wrapped_key)?;
+        let sealed = hex::decode(&self.ciphertext).map_err(|_| KeyError::DecryptionFailed)?;
+        decrypt(&data_key, &sealed)
+    }
+
+    /// Moves the envelope to a new master key without touching the payload.
+    pub fn rewrap(&self, old: &MasterKey, new: &MasterKey) -> Result<Self, KeyError> {
+        let data_key = unwrap_key(old, &self.wrapped_key)?;
+        Ok(Self {
+            wrapped_key: wrap_key(new, &data_key)?,
+            ciphertext: self.ciphertext.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let master = MasterKey::generate();
+        let envelope = Envelope::seal(&master, b"wJalrXUtnFEMI/K7MDENG").unwrap();
+        assert!(!envelope.ciphertext.contains("wJalr"));
+        assert_eq!(envelope.open(&master).unwrap(), b"wJalrXUtnFEMI/K7MDENG");
+    }
+
+    #[test]
+    fn test_open_with_wrong_master_key() {
+        let envelope = Envelope::seal(&MasterKey::generate(), b"secret").unwrap();
+        let result = envelope.open(&MasterKey::generate());
+        assert_eq!(result, Err(KeyError::DecryptionFailed));
+    }
+
+    #[test]
+    fn test_rewrap_keeps_payload() {
+        let old = MasterKey::generate();
+        let new = MasterKey::generate();
+        let envelope = Envelope::seal(&old, b"secret").unwrap();
+        let rewrapped = envelope.rewrap(&old, &new).unwrap();
+        assert_eq!(rewrapped.ciphertext, envelope.ciphertext);
+        assert_eq!(rewrapped.open(&new).unwrap(), b"secret");
+        assert!(rewrapped.open(&old).is_err());
+    }
+
+    #[test]
+    fn test_master_key_hex() {
+        assert!(MasterKey::from_hex("abcd").is_err());
+        let key = MasterKey::generate();
+        assert_eq!(MasterKey::from_hex(&key.to_hex()).unwrap().as_bytes(), key.as_bytes());
+    }
+}
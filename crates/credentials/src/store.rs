@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::credentials::KeyError;
+use crate::envelope::{Envelope, MasterKey};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredCredential {
+    pub secret: Envelope,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreFile {
+    credentials: BTreeMap<String, StoredCredential>,
+}
+
+/// Persisted access keys. Secrets are only ever on disk as envelopes sealed
+/// with the master key, SigV4 needs the raw secret so we cant just hash them.
+#[derive(Debug)]
+pub struct CredentialStore {
+    path: PathBuf,
+    master: MasterKey,
+    file: StoreFile,
+}
+
+fn io_error(path: &Path, e: impl std::fmt::Display) -> KeyError {
+    KeyError::IoError(format!("{}: {}", path.display(), e))
+}
+
+impl CredentialStore {
+    /// Opens the store at `path`, a missing file is just an empty store.
+    /// Every entry is checked against `master` so a wrong key fails here and not mid-request.
+    pub fn open(path: impl Into<PathBuf>, master: MasterKey) -> Result<Self, KeyError> {
+        let path = path.into();
+        let file = match std::fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw)
+                .map_err(|e| KeyError::CorruptStore(format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoreFile::default(),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        let store = Self { path, master, file };
+        for cred in store.file.credentials.values() {
+            cred.secret.open(&store.master)?;
+        }
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file.credentials.is_empty()
+    }
+
+    pub fn access_keys(&self) -> impl Iterator<Item = &str> {
+        self.file.credentials.keys().map(String::as_str)
+    }
+
+    pub fn insert(&mut self, access_key: &str, secret_key: &str) -> Result<(), KeyError> {
+        if access_key.contains('=') || access_key.contains(',') {
+            return Err(KeyError::ContainsReservedChars);
+        }
+        let secret = Envelope::seal(&self.master, secret_key.as_bytes())?;
+        self.file
+            .credentials
+            .insert(access_key.to_string(), StoredCredential { secret });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, access_key: &str) -> bool {
+        self.file.credentials.remove(access_key).is_some()
+    }
+
+    pub fn secret_key(&self, access_key: &str) -> Result<Option<String>, KeyError> {
+        let Some(cred) = self.file.credentials.get(access_key) else {
+            return Ok(None);
+        };
+        let raw = cred.secret.open(&self.master)?;
+        String::from_utf8(raw)
+            .map(Some)
+            .map_err(|_| KeyError::DecryptionFailed)
+    }
+
+    /// Rewraps every secret under `new_master` and writes the store back.
+    /// Nothing is written unless every entry could be rewrapped.
+    pub fn rekey(&mut self, new_master: MasterKey) -> Result<(), KeyError> {
+        let mut rewrapped = BTreeMap::new();
+        for (access_key, cred) in &self.file.credentials {
+            let secret = cred.secret.rewrap(&self.master, &new_master)?;
+            rewrapped.insert(access_key.clone(), StoredCredential { secret });
+        }
+        let previous = std::mem::replace(&mut self.file.credentials, rewrapped);
+        let previous_master = std::mem::replace(&mut self.master, new_master);
+        if let Err(e) = self.save() {
+            self.file.credentials = previous;
+            self.master = previous_master;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Writes to a temp file and renames it over the store so a crash cant leave half a file.
+    pub fn save(&self) -> Result<(), KeyError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| io_error(parent, e))?;
+        }
+        let raw = serde_json::to_vec_pretty(&self.file).map_err(|e| io_error(&self.path, e))?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, raw).map_err(|e| io_error(&tmp, e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| io_error(&self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lumi-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("credentials.json")
+    }
+
+    #[test]
+    fn test_secret_not_stored_in_plaintext() {
+        let path = temp_store_path("plaintext");
+        let mut store = CredentialStore::open(&path, MasterKey::generate()).unwrap();
+        store.insert("AKIAEXAMPLE", "SUPERSECRETVALUE").unwrap();
+        store.save().unwrap();
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(raw.contains("AKIAEXAMPLE"));
+        assert!(!raw.contains("SUPERSECRETVALUE"));
+    }
+
+    #[test]
+    fn test_reopen_and_rekey() {
+        let path = temp_store_path("rekey");
+        let old = MasterKey::generate();
+        let new = MasterKey::generate();
+        let mut store = CredentialStore::open(&path, old.clone()).unwrap();
+        store.insert("AKIAEXAMPLE", "SUPERSECRETVALUE").unwrap();
+        store.save().unwrap();
+
+        let mut store = CredentialStore::open(&path, old.clone()).unwrap();
+        store.rekey(new.clone()).unwrap();
+
+        assert_eq!(
+            CredentialStore::open(&path, old).unwrap_err(),
+            KeyError::DecryptionFailed
+        );
+        let store = CredentialStore::open(&path, new).unwrap();
+        assert_eq!(
+            store.secret_key("AKIAEXAMPLE").unwrap().as_deref(),
+            Some("SUPERSECRETVALUE")
+        );
+        assert_eq!(store.secret_key("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_unreadable_store_reports_io_error() {
+        let dir = temp_store_path("io").parent().unwrap().to_path_buf();
+        std::fs::create_dir_all(&dir).unwrap();
+        let result = CredentialStore::open(&dir, MasterKey::generate());
+        assert!(matches!(result, Err(KeyError::IoError(_))));
+    }
+
+    #[test]
+    fn test_open_garbled_store_reports_corruption() {
+        let path = temp_store_path("corrupt");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"{\"credentials\": {\"AKIA").unwrap();
+        let result = CredentialStore::open(&path, MasterKey::generate());
+        assert!(matches!(result, Err(KeyError::CorruptStore(_))));
+    }
+}
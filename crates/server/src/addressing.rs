@@ -0,0 +1,89 @@
+// virtual-hosted style addressing. `bucket.{domain}/key` is rewritten to the path style
+// `/bucket/key` the router knows before anything else sees the request, the uri the
+// client actually sent is kept around for SigV4.
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{header, uri::PathAndQuery, Uri},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+use crate::state::AppState;
+
+/// The host without its port, lowercased and without a trailing dot.
+pub fn host_name(host: &str) -> String {
+    // ipv6 literals aside, the port is whatever follows the last colon
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(']') && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The bucket in a `{bucket}.{domain}` host, `None` for the domains themselves and
+/// hosts that aren't under any of them.
+pub fn bucket_from_host(host: &str, domains: &[String]) -> Option<String> {
+    let host = host_name(host);
+    let bucket = domains
+        .iter()
+        .find_map(|domain| host.strip_suffix(&format!(".{}", domain.to_ascii_lowercase())))?;
+    safe_bucket(bucket).then(|| bucket.to_string())
+}
+
+/// A host can say anything, never let it name something outside the data dir.
+pub fn safe_bucket(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// The host a request was sent to, h2 requests carry it in the uri instead.
+pub fn request_host(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| request.uri().host())
+}
+
+fn path_style(uri: &Uri, bucket: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("/{}{}?{}", bucket, uri.path(), query),
+        None => format!("/{}{}", bucket, uri.path()),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Turns virtual-hosted requests into path style ones, path style requests are left alone.
+pub async fn virtual_host(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let bucket = request_host(&request).and_then(|host| bucket_from_host(host, &state.domains));
+    if let Some(bucket) = bucket
+        && let Some(uri) = path_style(request.uri(), &bucket)
+    {
+        let original = OriginalUri(request.uri().clone());
+        request.extensions_mut().insert(original);
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_from_host() {
+        let domains = vec!["s3.lumi.local".to_string()];
+        assert_eq!(bucket_from_host("photos.s3.lumi.local:8080", &domains), Some("photos".into()));
+        assert_eq!(bucket_from_host("my.photos.S3.Lumi.Local.", &domains), Some("my.photos".into()));
+        assert_eq!(bucket_from_host("s3.lumi.local", &domains), None);
+        assert_eq!(bucket_from_host("127.0.0.1:8080", &domains), None);
+        assert_eq!(bucket_from_host("[::1]:8080", &domains), None);
+        assert_eq!(bucket_from_host("..s3.lumi.local", &domains), None);
+
+        let uri: Uri = "/a/b.txt?versionId=1".parse().unwrap();
+        assert_eq!(path_style(&uri, "photos").unwrap(), "/photos/a/b.txt?versionId=1");
+        assert_eq!(path_style(&"/".parse().unwrap(), "photos").unwrap(), "/photos/");
+    }
+}
@@ -0,0 +1,101 @@
+// one line per request on stdout saying who did what to which object and how it went, and
+// running counts per operation for the admin api. sits just outside the auth middleware,
+// which leaves the operation and identity on the response for us.
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use lumi_credentials::aws_sigv4::percent_decode;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::middleware::Identity;
+use crate::operation::Operation;
+use crate::request_id::RequestId;
+use crate::state::AppState;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct OperationCount {
+    pub requests: u64,
+    /// 4xx and 5xx responses
+    pub errors: u64,
+}
+
+/// Requests seen per operation since the server started.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    operations: Mutex<HashMap<Operation, OperationCount>>,
+}
+
+impl Metrics {
+    pub fn record(&self, operation: Operation, failed: bool) {
+        let mut operations = self.operations.lock().unwrap();
+        let count = operations.entry(operation).or_default();
+        count.requests += 1;
+        count.errors += failed as u64;
+    }
+
+    /// By operation name, only the ones that were used.
+    pub fn snapshot(&self) -> BTreeMap<String, OperationCount> {
+        let operations = self.operations.lock().unwrap();
+        operations.iter().map(|(op, count)| (op.to_string(), *count)).collect()
+    }
+}
+
+/// `(bucket, key)` from a path style path, `-` for what isnt there.
+fn resource(path: &str) -> (String, String) {
+    let path = String::from_utf8_lossy(&percent_decode(path)).into_owned();
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    let bucket = parts.next().filter(|b| !b.is_empty()).unwrap_or("-");
+    let key = parts.next().filter(|k| !k.is_empty()).unwrap_or("-");
+    (bucket.to_string(), key.to_string())
+}
+
+pub async fn audit(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let request_id = match request.extensions().get::<RequestId>() {
+        Some(ids) => ids.id.clone(),
+        None => "-".to_string(),
+    };
+    let (bucket, key) = resource(request.uri().path());
+
+    let response = next.run(request).await;
+    let status = response.status();
+    let operation = response.extensions().get::<Operation>().copied();
+    let identity = response.extensions().get::<Identity>().map(|i| i.access_key.as_str());
+    if let Some(operation) = operation {
+        state.metrics.record(operation, status.is_client_error() || status.is_server_error());
+    }
+    println!(
+        "audit request={} operation={} bucket={} key={} identity={} status={}",
+        request_id,
+        operation.map(|op| op.to_string()).unwrap_or_else(|| "-".to_string()),
+        bucket,
+        key,
+        identity.unwrap_or("-"),
+        status.as_u16()
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        assert_eq!(resource("/photos/a%20b/c.jpg"), ("photos".to_string(), "a b/c.jpg".to_string()));
+        assert_eq!(resource("/photos/"), ("photos".to_string(), "-".to_string()));
+        assert_eq!(resource("/"), ("-".to_string(), "-".to_string()));
+
+        let metrics = Metrics::default();
+        metrics.record(Operation::GetObject, false);
+        metrics.record(Operation::GetObject, true);
+        metrics.record(Operation::PutObject, false);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot["GetObject"], OperationCount { requests: 2, errors: 1 });
+        assert_eq!(snapshot["PutObject"], OperationCount { requests: 1, errors: 0 });
+        assert!(!snapshot.contains_key("DeleteObject"));
+    }
+}
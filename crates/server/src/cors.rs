@@ -0,0 +1,225 @@
+// per-bucket CORS. a bucket with a ?cors configuration is checked against its own rules,
+// every other request against the server wide rule built from --allowed-origin.
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use lumi_utils::errors::ErrorCode;
+use std::sync::Arc;
+
+use crate::routes::admin;
+use crate::state::AppState;
+use crate::storage::{self, config};
+use crate::xml::{CorsConfiguration, CorsRule};
+
+pub const MAX_RULES: usize = 100;
+const METHODS: &[&str] = &["GET", "PUT", "HEAD", "POST", "DELETE"];
+
+/// 1 to 100 rules, each with at least one origin and one of the five methods CORS is
+/// allowed for, and no origin or header pattern with more than one `*`.
+pub fn validate(config: &CorsConfiguration) -> Result<(), ErrorCode> {
+    if config.rules.is_empty() || config.rules.len() > MAX_RULES {
+        return Err(ErrorCode::MalformedXml);
+    }
+    for rule in &config.rules {
+        if rule.allowed_methods.is_empty() || rule.allowed_origins.is_empty() {
+            return Err(ErrorCode::MalformedXml);
+        }
+        if rule.allowed_methods.iter().any(|m| !METHODS.contains(&m.as_str())) {
+            return Err(ErrorCode::InvalidRequest);
+        }
+        // one wildcard per pattern, like S3
+        let patterns = rule.allowed_origins.iter().chain(&rule.allowed_headers);
+        if patterns.into_iter().any(|p| p.matches('*').count() > 1) {
+            return Err(ErrorCode::InvalidRequest);
+        }
+    }
+    Ok(())
+}
+
+/// The rule every bucket without its own configuration gets, from `--allowed-origin`
+/// (`*` or a comma separated list).
+pub fn fallback(allowed_origins: &str) -> CorsConfiguration {
+    let allowed_origins: Vec<String> = allowed_origins
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    if allowed_origins.is_empty() {
+        return CorsConfiguration::default();
+    }
+    CorsConfiguration {
+        rules: vec![CorsRule {
+            allowed_methods: METHODS.iter().map(|m| m.to_string()).collect(),
+            allowed_origins,
+            allowed_headers: vec!["*".to_string()],
+            ..Default::default()
+        }],
+    }
+}
+
+/// `pattern` with at most one `*` standing for any run of characters.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            value.len() >= prefix.len() + suffix.len()
+                && value.starts_with(prefix)
+                && value.ends_with(suffix)
+        }
+        None => pattern == value,
+    }
+}
+
+/// First rule allowing the origin, method and every requested header, in document order.
+pub fn find_rule<'a>(
+    rules: &'a [CorsRule],
+    origin: &str,
+    method: &str,
+    request_headers: &[String],
+) -> Option<&'a CorsRule> {
+    rules.iter().find(|rule| {
+        rule.allowed_origins.iter().any(|o| wildcard_match(o, origin))
+            && rule.allowed_methods.iter().any(|m| m == method)
+            && request_headers.iter().all(|requested| {
+                let requested = requested.to_ascii_lowercase();
+                rule.allowed_headers
+                    .iter()
+                    .any(|h| wildcard_match(&h.to_ascii_lowercase(), &requested))
+            })
+    })
+}
+
+/// Rules for the bucket a path is about, the fallback for the service and admin routes.
+async fn rules_for(state: &AppState, path: &str) -> Vec<CorsRule> {
+    let bucket = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    if !bucket.is_empty() && bucket != admin::PREFIX && storage::bucket_exists(bucket).await {
+        match config::load(bucket).await {
+            Ok(config::BucketConfig { cors: Some(cors), .. }) => return cors.rules,
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to load config for bucket '{}': {}", bucket, e),
+        }
+    }
+    state.cors_fallback.rules.clone()
+}
+
+fn allow_origin(rule: &CorsRule, origin: &str) -> HeaderValue {
+    if rule.allowed_origins.iter().any(|o| o == "*") {
+        HeaderValue::from_static("*")
+    } else {
+        HeaderValue::from_str(origin).unwrap()
+    }
+}
+
+fn insert_joined(headers: &mut HeaderMap, name: header::HeaderName, values: &[String]) {
+    if !values.is_empty()
+        && let Ok(value) = HeaderValue::from_str(&values.join(", "))
+    {
+        headers.insert(name, value);
+    }
+}
+
+fn preflight(rules: &[CorsRule], origin: &str, request_headers: &HeaderMap) -> Response {
+    let Some(method) = request_headers
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return ErrorCode::CorsForbidden.into_response();
+    };
+    let requested: Vec<String> = request_headers
+        .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .collect();
+    let Some(rule) = find_rule(rules, origin, method, &requested) else {
+        return ErrorCode::CorsForbidden.into_response();
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin(rule, origin));
+    insert_joined(&mut headers, header::ACCESS_CONTROL_ALLOW_METHODS, &rule.allowed_methods);
+    insert_joined(&mut headers, header::ACCESS_CONTROL_ALLOW_HEADERS, &requested);
+    insert_joined(&mut headers, header::ACCESS_CONTROL_EXPOSE_HEADERS, &rule.expose_headers);
+    if let Some(max_age) = rule.max_age_seconds {
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+    headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    (StatusCode::OK, headers).into_response()
+}
+
+/// Answers preflights itself and adds the CORS headers to the responses of actual
+/// requests, requests without an Origin are not CORS and pass straight through.
+pub async fn cors(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(origin) = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+    else {
+        return next.run(request).await;
+    };
+    let rules = rules_for(&state, request.uri().path()).await;
+
+    if request.method() == Method::OPTIONS {
+        return preflight(&rules, &origin, request.headers());
+    }
+
+    let method = request.method().as_str().to_string();
+    let mut response = next.run(request).await;
+    if let Some(rule) = find_rule(&rules, &origin, &method, &[]) {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin(rule, &origin));
+        insert_joined(headers, header::ACCESS_CONTROL_EXPOSE_HEADERS, &rule.expose_headers);
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(origins: &[&str], methods: &[&str], headers: &[&str]) -> CorsRule {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+        CorsRule {
+            allowed_origins: strings(origins),
+            allowed_methods: strings(methods),
+            allowed_headers: strings(headers),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_rule() {
+        let rules = vec![
+            rule(&["https://*.example.com"], &["GET"], &[]),
+            rule(&["https://app.test"], &["PUT", "GET"], &["x-amz-*", "Content-Type"]),
+        ];
+        let headers = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(find_rule(&rules, "https://www.example.com", "GET", &[]).is_some());
+        assert!(find_rule(&rules, "https://example.com", "GET", &[]).is_none());
+        assert!(find_rule(&rules, "https://www.example.com", "PUT", &[]).is_none());
+        let found = find_rule(&rules, "https://app.test", "PUT", &headers(&["content-type", "X-Amz-Date"]));
+        assert!(std::ptr::eq(found.unwrap(), &rules[1]));
+        assert!(find_rule(&rules, "https://app.test", "PUT", &headers(&["authorization"])).is_none());
+
+        assert!(find_rule(&fallback("*").rules, "http://anything", "DELETE", &headers(&["x"])).is_some());
+        assert!(find_rule(&fallback("http://a, http://b").rules, "http://b", "GET", &[]).is_some());
+        assert!(find_rule(&fallback("").rules, "http://a", "GET", &[]).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |r: CorsRule| CorsConfiguration { rules: vec![r] };
+        assert_eq!(validate(&config(rule(&["*"], &["GET"], &["*"]))), Ok(()));
+        assert_eq!(validate(&config(rule(&["*"], &["PATCH"], &[]))), Err(ErrorCode::InvalidRequest));
+        assert_eq!(validate(&config(rule(&["*.*"], &["GET"], &[]))), Err(ErrorCode::InvalidRequest));
+        assert_eq!(validate(&config(rule(&[], &["GET"], &[]))), Err(ErrorCode::MalformedXml));
+        assert_eq!(validate(&CorsConfiguration::default()), Err(ErrorCode::MalformedXml));
+    }
+}
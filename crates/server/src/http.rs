@@ -1,52 +1,174 @@
-use axum::{middleware::from_fn, Router, routing::any};
-use tower_http::cors::{CorsLayer, Any as AnyOrigin};
-use std::{env, error::Error, net::SocketAddr};
+use axum::{extract::DefaultBodyLimit, middleware::{from_fn, from_fn_with_state}, Router, routing::any};
+use std::{collections::HashMap, env, error::Error, net::SocketAddr, path::{Path, PathBuf}, sync::Arc, time::Duration};
 
+use crate::addressing;
+use crate::audit;
+use crate::cors;
+use crate::kms::Kms;
+use crate::lifecycle;
 use crate::middleware;
-use crate::routes::{bucket, object};
-use lumi_credentials::{DEFAULT_ACCESS_KEY, DEFAULT_SECRET_KEY};
-
-pub async fn start_http_server(
-    host: String,
-    port: u64,
-    allowed_origins: String,
-) -> Result<(), Box<dyn Error>> {  
-    let cors_layer = if allowed_origins == "*" {
-        CorsLayer::new()
-            .allow_origin(AnyOrigin)
-            .allow_methods(AnyOrigin)
-            .allow_headers(AnyOrigin)
-    } else {
-        let origins: Vec<_> = allowed_origins
-            .split(',')
-            .filter_map(|s| s.trim().parse().ok())
-            .collect();
-        CorsLayer::new()
-            .allow_origin(origins)
-            .allow_methods(AnyOrigin)
-            .allow_headers(AnyOrigin)
-    };
-    
+use crate::request_id;
+use crate::routes::{admin, bucket, object};
+use crate::state::AppState;
+use crate::storage::{self, sse};
+use crate::tls::{self, CertReloader, TlsConfig};
+use crate::website;
+use lumi_credentials::{
+    credentials::KeyError,
+    envelope::MasterKey,
+    store::CredentialStore,
+    DEFAULT_ACCESS_KEY, DEFAULT_CREDENTIALS_PATH, DEFAULT_SECRET_KEY,
+};
+
+pub const DEFAULT_REGION: &str = "us-east-1";
+/// largest single PUT / part S3 accepts
+pub(crate) const MAX_BODY_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+/// Opens the persisted credential store. Without a master key we can still run on the
+/// env root key, but only if theres nothing encrypted on disk we'd be silently ignoring.
+fn open_credential_store(master_key_file: Option<&Path>) -> Result<Option<CredentialStore>, KeyError> {
+    match MasterKey::load(master_key_file)? {
+        Some(master) => CredentialStore::open(DEFAULT_CREDENTIALS_PATH, master).map(Some),
+        None if Path::new(DEFAULT_CREDENTIALS_PATH).exists() => Err(KeyError::MissingMasterKey),
+        None => Ok(None),
+    }
+}
+
+/// Reads the client certificate mapping, a json object of certificate name -> access key,
+/// e.g. `{"cn:svc-a": "AKIA..", "dns:svc-b.internal": "AKIB.."}`.
+fn load_cert_identities(path: &Path, state: &AppState) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let raw = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let identities: HashMap<String, String> = serde_json::from_slice(&raw)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    for access_key in identities.values() {
+        if state.secret_key(access_key)?.is_none() {
+            return Err(format!("{}: unknown access key '{}'", path.display(), access_key).into());
+        }
+    }
+    Ok(identities)
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u64,
+    pub region: String,
+    /// see `AppState::debug_signatures`
+    pub debug_signatures: bool,
+    pub allowed_origins: String,
+    pub master_key_file: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    /// only used when `tls` has a client CA
+    pub cert_identity_map: Option<PathBuf>,
+    /// SSE-S3 master key, generated under the data dir if not given
+    pub sse_key_file: Option<PathBuf>,
+    /// access keys besides root allowed to bypass GOVERNANCE retention
+    pub governance_bypass: Vec<String>,
+    /// how often lifecycle rules are applied, `None` never
+    pub lifecycle_interval: Option<Duration>,
+    /// base domains for virtual-hosted style addressing, path style always works
+    pub domains: Vec<String>,
+    /// static website listener, plain http like S3's website endpoints
+    pub website_port: Option<u64>,
+    pub website_domains: Vec<String>,
+}
+
+pub async fn start_http_server(config: ServerConfig) -> Result<(), Box<dyn Error>> {
+    let ServerConfig {
+        host,
+        port,
+        region,
+        debug_signatures,
+        allowed_origins,
+        master_key_file,
+        tls,
+        cert_identity_map,
+        sse_key_file,
+        governance_bypass,
+        lifecycle_interval,
+        domains,
+        website_port,
+        website_domains,
+    } = config;
     let access_key = env::var("lumi_access_key").unwrap_or_else(|_| DEFAULT_ACCESS_KEY.to_string());
     let secret_key = env::var("lumi_secret_key").unwrap_or_else(|_| DEFAULT_SECRET_KEY.to_string());
+    let sse_master = sse::load_or_create_master_key(
+        &sse_key_file.unwrap_or_else(|| storage::sys_dir().join("sse-master.key")),
+    )?;
+    let mut state = AppState {
+        region,
+        debug_signatures,
+        root_access_key: access_key,
+        root_secret_key: secret_key,
+        credentials: open_credential_store(master_key_file.as_deref())?,
+        cert_identities: HashMap::new(),
+        kms: Kms::open(&storage::sys_dir().join("kms").join("keys.json"), sse_master.clone())?,
+        sse_master,
+        governance_bypass: governance_bypass.into_iter().collect(),
+        cors_fallback: cors::fallback(&allowed_origins),
+        domains,
+        website_domains,
+        metrics: Default::default(),
+    };
+    if let Some(path) = &cert_identity_map {
+        state.cert_identities = load_cert_identities(path, &state)?;
+    }
+    let state = Arc::new(state);
+    if let Some(interval) = lifecycle_interval {
+        lifecycle::spawn(interval);
+    }
 
-    let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
-    println!("S3 API started on http://{}:{}", host, port);
+    if let Some(website_port) = website_port {
+        let addr: SocketAddr = format!("{}:{}", host, website_port).parse()?;
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let app = Router::new()
+            .fallback(website::handler)
+            .layer(from_fn(request_id::request_id))
+            .with_state(state.clone());
+        println!("Website endpoint started on http://{}:{}", host, website_port);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                eprintln!("Website endpoint stopped: {}", e);
+            }
+        });
+    }
 
+    let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
     let listener = tokio::net::TcpListener::bind(addr).await?;
 
     let app = Router::new()
         .route("/", any(bucket::handler))
+        .route("/_lumi/admin/kms/keys", any(admin::kms::handler))
+        .route("/_lumi/admin/kms/keys/{key_id}", any(admin::kms::handler))
+        .route("/_lumi/admin/kms/keys/{key_id}/{action}", any(admin::kms::handler))
+        .route("/_lumi/admin/metrics", any(admin::metrics::handler))
         .route("/{bucket}/", any(bucket::handler))
         .route("/{bucket}", any(bucket::handler))
         .route("/{bucket}/{*key}", any(object::handler))
-        .layer(from_fn(move |req, next| {
-            let ak = access_key.clone();
-            let sk = secret_key.clone();
-            async move { middleware::auth(req, next, &ak, &sk).await }
-        }))
-        .layer(cors_layer);
-    
-    axum::serve(listener, app).await?;
+        .layer(from_fn_with_state(state.clone(), middleware::auth))
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+        .layer(from_fn_with_state(state.clone(), cors::cors))
+        .layer(from_fn_with_state(state.clone(), audit::audit))
+        .with_state(state.clone());
+    // the rewrite has to happen before routing, so it wraps the whole router
+    let app = Router::new()
+        .fallback_service(app)
+        .layer(from_fn(request_id::request_id))
+        .layer(from_fn_with_state(state.clone(), addressing::virtual_host))
+        .with_state(state);
+
+    match tls {
+        Some(tls) => {
+            let reloader = Arc::new(CertReloader::new(tls).map_err(|e| e.to_string())?);
+            reloader.clone().watch(tls::RELOAD_INTERVAL);
+            let server_config = reloader.server_config().map_err(|e| e.to_string())?;
+            println!("S3 API started on https://{}:{}", host, port);
+            tls::serve(listener, app, Arc::new(server_config).into()).await?;
+        }
+        None => {
+            println!("S3 API started on http://{}:{}", host, port);
+            axum::serve(listener, app).await?;
+        }
+    }
     Ok(())
 }
\ No newline at end of file
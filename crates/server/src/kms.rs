@@ -0,0 +1,340 @@
+// built-in stand-in for AWS KMS, enough for SSE-KMS. named keys live in one json file with
+// their material wrapped by the SSE master key. rotating adds a new version that new
+// objects use, old versions stay around so existing objects keep working. a disabled key
+// can't wrap or unwrap anything, which makes every object under it unreadable.
+use chrono::{DateTime, Utc};
+use lumi_credentials::envelope::{self, MasterKey, KEY_LEN};
+use lumi_utils::errors::ErrorCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::Mutex;
+
+use crate::storage::sse::{DataKey, ObjectEncryption};
+
+/// used when a request asks for aws:kms without naming a key, created on first use
+pub const DEFAULT_KEY_ID: &str = "default";
+
+#[derive(Debug)]
+pub enum KmsError {
+    NotFound,
+    Disabled,
+    AlreadyExists,
+    InvalidKeyId,
+    Io(io::Error),
+}
+
+impl std::fmt::Display for KmsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KmsError::NotFound => write!(f, "key not found"),
+            KmsError::Disabled => write!(f, "key is disabled"),
+            KmsError::AlreadyExists => write!(f, "key already exists"),
+            KmsError::InvalidKeyId => write!(f, "invalid key id"),
+            KmsError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<KmsError> for ErrorCode {
+    fn from(e: KmsError) -> Self {
+        match e {
+            KmsError::NotFound => ErrorCode::KmsKeyNotFound,
+            KmsError::Disabled => ErrorCode::KmsKeyDisabled,
+            KmsError::AlreadyExists => ErrorCode::KmsKeyAlreadyExists,
+            KmsError::InvalidKeyId => ErrorCode::InvalidArgument,
+            KmsError::Io(e) => {
+                eprintln!("KMS error: {}", e);
+                ErrorCode::InternalError
+            }
+        }
+    }
+}
+
+impl From<io::Error> for KmsError {
+    fn from(e: io::Error) -> Self {
+        KmsError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyVersion {
+    created: DateTime<Utc>,
+    /// key material wrapped by the SSE master key
+    material: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredKey {
+    created: DateTime<Utc>,
+    enabled: bool,
+    /// version n is `versions[n - 1]`, the last one is current
+    versions: Vec<KeyVersion>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeyFile {
+    keys: BTreeMap<String, StoredKey>,
+}
+
+/// What the admin API shows about a key, never the material.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyInfo {
+    pub key_id: String,
+    pub enabled: bool,
+    pub created: DateTime<Utc>,
+    pub current_version: u32,
+    pub rotated: Option<DateTime<Utc>>,
+}
+
+impl KeyInfo {
+    fn new(key_id: &str, key: &StoredKey) -> Self {
+        Self {
+            key_id: key_id.to_string(),
+            enabled: key.enabled,
+            created: key.created,
+            current_version: key.versions.len() as u32,
+            rotated: (key.versions.len() > 1).then(|| key.versions.last().unwrap().created),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Kms {
+    path: PathBuf,
+    master: MasterKey,
+    file: Mutex<KeyFile>,
+}
+
+fn valid_key_id(key_id: &str) -> bool {
+    !key_id.is_empty()
+        && key_id.len() <= 256
+        && key_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+impl Kms {
+    pub fn open(path: &Path, master: MasterKey) -> io::Result<Self> {
+        let file = match std::fs::read(path) {
+            Ok(raw) => serde_json::from_slice(&raw)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => KeyFile::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            master,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Called with the file lock held, which is what keeps two saves from interleaving.
+    async fn save(&self, file: &KeyFile) -> io::Result<()> {
+        let raw = serde_json::to_vec_pretty(file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, raw).await?;
+        fs::rename(&tmp, &self.path).await
+    }
+
+    fn new_version(&self) -> Result<KeyVersion, KmsError> {
+        let material = envelope::random_bytes::<KEY_LEN>();
+        let material = envelope::wrap_key(&self.master, &material)
+            .map_err(|e| KmsError::Io(io::Error::other(e.to_string())))?;
+        Ok(KeyVersion {
+            created: Utc::now(),
+            material,
+        })
+    }
+
+    fn material(&self, version: &KeyVersion) -> Result<[u8; KEY_LEN], KmsError> {
+        envelope::unwrap_key(&self.master, &version.material)
+            .map_err(|e| KmsError::Io(io::Error::other(e.to_string())))
+    }
+
+    /// Runs `f` on a key and persists the change, or rolls it back if saving fails.
+    async fn update<T>(
+        &self,
+        key_id: &str,
+        f: impl FnOnce(&mut StoredKey) -> Result<T, KmsError>,
+    ) -> Result<T, KmsError> {
+        let mut file = self.file.lock().await;
+        let key = file.keys.get_mut(key_id).ok_or(KmsError::NotFound)?;
+        let before = key.clone();
+        let out = f(key)?;
+        if let Err(e) = self.save(&file).await {
+            file.keys.insert(key_id.to_string(), before);
+            return Err(e.into());
+        }
+        Ok(out)
+    }
+
+    pub async fn create(&self, key_id: &str) -> Result<KeyInfo, KmsError> {
+        if !valid_key_id(key_id) {
+            return Err(KmsError::InvalidKeyId);
+        }
+        let mut file = self.file.lock().await;
+        if file.keys.contains_key(key_id) {
+            return Err(KmsError::AlreadyExists);
+        }
+        let key = StoredKey {
+            created: Utc::now(),
+            enabled: true,
+            versions: vec![self.new_version()?],
+        };
+        let info = KeyInfo::new(key_id, &key);
+        file.keys.insert(key_id.to_string(), key);
+        if let Err(e) = self.save(&file).await {
+            file.keys.remove(key_id);
+            return Err(e.into());
+        }
+        Ok(info)
+    }
+
+    pub async fn list(&self) -> Vec<KeyInfo> {
+        let file = self.file.lock().await;
+        file.keys.iter().map(|(id, key)| KeyInfo::new(id, key)).collect()
+    }
+
+    pub async fn set_enabled(&self, key_id: &str, enabled: bool) -> Result<KeyInfo, KmsError> {
+        self.update(key_id, |key| {
+            key.enabled = enabled;
+            Ok(KeyInfo::new(key_id, key))
+        })
+        .await
+    }
+
+    /// New material for new objects, existing ones stay on the version they were written with.
+    pub async fn rotate(&self, key_id: &str) -> Result<KeyInfo, KmsError> {
+        let version = self.new_version()?;
+        self.update(key_id, |key| {
+            if !key.enabled {
+                return Err(KmsError::Disabled);
+            }
+            key.versions.push(version);
+            Ok(KeyInfo::new(key_id, key))
+        })
+        .await
+    }
+
+    /// A fresh data key wrapped under the current version of `key_id`.
+    pub async fn generate_data_key(&self, key_id: &str) -> Result<(DataKey, ObjectEncryption), KmsError> {
+        if key_id == DEFAULT_KEY_ID && !self.file.lock().await.keys.contains_key(key_id) {
+            match self.create(key_id).await {
+                Ok(_) | Err(KmsError::AlreadyExists) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let file = self.file.lock().await;
+        let key = file.keys.get(key_id).ok_or(KmsError::NotFound)?;
+        if !key.enabled {
+            return Err(KmsError::Disabled);
+        }
+        let material = self.material(key.versions.last().unwrap())?;
+        let data_key = envelope::random_bytes::<KEY_LEN>();
+        let wrapped = envelope::encrypt(&material, &data_key)
+            .map_err(|e| KmsError::Io(io::Error::other(e.to_string())))?;
+        Ok((
+            data_key,
+            ObjectEncryption::Kms {
+                key_id: key_id.to_string(),
+                key_version: key.versions.len() as u32,
+                wrapped_key: hex::encode(wrapped),
+            },
+        ))
+    }
+
+    pub async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        key_version: u32,
+        wrapped_key: &str,
+    ) -> Result<DataKey, KmsError> {
+        let file = self.file.lock().await;
+        let key = file.keys.get(key_id).ok_or(KmsError::NotFound)?;
+        if !key.enabled {
+            return Err(KmsError::Disabled);
+        }
+        let version = key
+            .versions
+            .get((key_version as usize).wrapping_sub(1))
+            .ok_or(KmsError::NotFound)?;
+        let material = self.material(version)?;
+        let bad = || KmsError::Io(io::Error::new(io::ErrorKind::InvalidData, "bad wrapped data key"));
+        let wrapped = hex::decode(wrapped_key).map_err(|_| bad())?;
+        let data_key = envelope::decrypt(&material, &wrapped)
+            .map_err(|e| KmsError::Io(io::Error::other(e.to_string())))?;
+        data_key.try_into().map_err(|_| bad())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_kms() -> (Kms, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "lumi-kms-{}-{}.json",
+            std::process::id(),
+            hex::encode(envelope::random_bytes::<4>())
+        ));
+        (Kms::open(&path, MasterKey::generate()).unwrap(), path)
+    }
+
+    async fn unwrap_object(kms: &Kms, encryption: &ObjectEncryption) -> Result<DataKey, KmsError> {
+        let ObjectEncryption::Kms {
+            key_id,
+            key_version,
+            wrapped_key,
+        } = encryption
+        else {
+            panic!("not a kms object");
+        };
+        kms.decrypt_data_key(key_id, *key_version, wrapped_key).await
+    }
+
+    #[tokio::test]
+    async fn test_rotate_and_disable() {
+        let (kms, path) = temp_kms();
+        kms.create("tenant-a").await.unwrap();
+        assert!(matches!(kms.create("tenant-a").await, Err(KmsError::AlreadyExists)));
+        assert!(matches!(kms.create("no/slashes").await, Err(KmsError::InvalidKeyId)));
+
+        let (old_key, old) = kms.generate_data_key("tenant-a").await.unwrap();
+        assert_eq!(kms.rotate("tenant-a").await.unwrap().current_version, 2);
+        let (new_key, new) = kms.generate_data_key("tenant-a").await.unwrap();
+        assert!(matches!(new, ObjectEncryption::Kms { key_version: 2, .. }));
+        assert_eq!(unwrap_object(&kms, &old).await.unwrap(), old_key);
+        assert_eq!(unwrap_object(&kms, &new).await.unwrap(), new_key);
+
+        kms.set_enabled("tenant-a", false).await.unwrap();
+        assert!(matches!(unwrap_object(&kms, &old).await, Err(KmsError::Disabled)));
+        assert!(matches!(kms.generate_data_key("tenant-a").await, Err(KmsError::Disabled)));
+        kms.set_enabled("tenant-a", true).await.unwrap();
+        assert_eq!(unwrap_object(&kms, &old).await.unwrap(), old_key);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_keys_persist() {
+        let (kms, path) = temp_kms();
+        let (data_key, encryption) = kms.generate_data_key(DEFAULT_KEY_ID).await.unwrap();
+        kms.set_enabled(DEFAULT_KEY_ID, false).await.unwrap();
+
+        let reopened = Kms::open(&path, kms.master.clone()).unwrap();
+        assert_eq!(reopened.list().await, kms.list().await);
+        assert!(!reopened.list().await[0].enabled);
+        reopened.set_enabled(DEFAULT_KEY_ID, true).await.unwrap();
+        assert_eq!(unwrap_object(&reopened, &encryption).await.unwrap(), data_key);
+        // without the right master key the material is useless
+        let stolen = Kms::open(&path, MasterKey::generate()).unwrap();
+        assert!(unwrap_object(&stolen, &encryption).await.is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,4 +1,15 @@
+pub mod addressing;
+pub mod audit;
+pub mod cors;
 pub mod http;
+pub mod kms;
+pub mod lifecycle;
 pub mod middleware;
+pub mod operation;
+pub mod request_id;
 pub mod routes;
+pub mod state;
+pub mod storage;
+pub mod tls;
+pub mod website;
 pub mod xml;
\ No newline at end of file
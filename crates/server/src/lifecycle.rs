@@ -0,0 +1,363 @@
+// bucket lifecycle rules and the scanner that carries them out. the scanner walks every
+// bucket with a lifecycle configuration once per interval, nothing is tracked between
+// runs so a restart just means the next pass picks up where things are.
+use chrono::{DateTime, Duration, Utc};
+use lumi_utils::errors::ErrorCode;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+use crate::storage::{self, config, multipart as uploads, versioning, ObjectMeta, WriteCondition, WriteError};
+use crate::xml::{LifecycleConfiguration, LifecycleRule, Tag};
+
+pub const ENABLED: &str = "Enabled";
+pub const DISABLED: &str = "Disabled";
+pub const MAX_RULES: usize = 1000;
+const MAX_RULE_ID_LEN: usize = 255;
+/// how often a bucket scan says how far along it is
+const PROGRESS_EVERY: usize = 1000;
+
+/// 1 to 1000 rules with unique ids of up to 255 chars, each Enabled or Disabled, one
+/// filter condition (or an And), and at least one action. Days have to be positive and
+/// an expiration is days, a date or ExpiredObjectDeleteMarker, never a mix. Rules that
+/// match on tags cant expire delete markers or abort uploads, neither has tags.
+pub fn validate(config: &LifecycleConfiguration) -> Result<(), ErrorCode> {
+    if config.rules.is_empty() || config.rules.len() > MAX_RULES {
+        return Err(ErrorCode::MalformedXml);
+    }
+    let mut ids = HashSet::new();
+    for rule in &config.rules {
+        if rule.status != ENABLED && rule.status != DISABLED {
+            return Err(ErrorCode::MalformedXml);
+        }
+        if let Some(id) = &rule.id
+            && (id.len() > MAX_RULE_ID_LEN || !ids.insert(id))
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        if let Some(filter) = &rule.filter {
+            let conditions = [
+                filter.prefix.is_some(),
+                filter.tag.is_some(),
+                filter.object_size_greater_than.is_some(),
+                filter.object_size_less_than.is_some(),
+                filter.and.is_some(),
+            ];
+            if rule.prefix.is_some() || conditions.iter().filter(|c| **c).count() > 1 {
+                return Err(ErrorCode::MalformedXml);
+            }
+        }
+        let has_tags = !filter_tags(rule).is_empty();
+        if rule.expiration.is_none()
+            && rule.noncurrent_version_expiration.is_none()
+            && rule.abort_incomplete_multipart_upload.is_none()
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        if let Some(expiration) = &rule.expiration {
+            let marker = expiration.expired_object_delete_marker.is_some();
+            match (expiration.days, &expiration.date) {
+                (Some(0), _) | (Some(_), Some(_)) => return Err(ErrorCode::InvalidArgument),
+                (None, None) if !marker => return Err(ErrorCode::InvalidArgument),
+                (Some(_), None) | (None, Some(_)) if marker => return Err(ErrorCode::InvalidArgument),
+                _ => {}
+            }
+            if let Some(date) = &expiration.date {
+                DateTime::parse_from_rfc3339(date).map_err(|_| ErrorCode::InvalidArgument)?;
+            }
+            // delete markers have no tags to match
+            if marker && has_tags {
+                return Err(ErrorCode::InvalidArgument);
+            }
+        }
+        if let Some(noncurrent) = &rule.noncurrent_version_expiration
+            && noncurrent.noncurrent_days.is_none_or(|d| d == 0)
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        if let Some(abort) = &rule.abort_incomplete_multipart_upload
+            && (abort.days_after_initiation == 0 || has_tags)
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+    }
+    Ok(())
+}
+
+fn filter_tags(rule: &LifecycleRule) -> &[Tag] {
+    match &rule.filter {
+        Some(filter) => match &filter.and {
+            Some(and) => &and.tags,
+            None => filter.tag.as_slice(),
+        },
+        None => &[],
+    }
+}
+
+/// Whether the rule's filter covers an object.
+pub fn rule_matches(rule: &LifecycleRule, key: &str, size: u64, tags: &BTreeMap<String, String>) -> bool {
+    let (prefix, greater_than, less_than) = match &rule.filter {
+        None => (rule.prefix.as_deref(), None, None),
+        Some(filter) => match &filter.and {
+            Some(and) => (and.prefix.as_deref(), and.object_size_greater_than, and.object_size_less_than),
+            None => (filter.prefix.as_deref(), filter.object_size_greater_than, filter.object_size_less_than),
+        },
+    };
+    key.starts_with(prefix.unwrap_or_default())
+        && greater_than.is_none_or(|n| size > n)
+        && less_than.is_none_or(|n| size < n)
+        && filter_tags(rule)
+            .iter()
+            .all(|t| tags.get(&t.key) == Some(&t.value))
+}
+
+/// S3 counts lifecycle days from `since` and rounds up to the next midnight UTC.
+fn due(since: DateTime<Utc>, days: u32) -> DateTime<Utc> {
+    let at = since + Duration::days(days.into());
+    let midnight = at.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+    if midnight == at { at } else { midnight + Duration::days(1) }
+}
+
+/// Whether the current version has reached its expiration under `rule`.
+fn current_expired(rule: &LifecycleRule, meta: &ObjectMeta, now: DateTime<Utc>) -> bool {
+    let Some(expiration) = &rule.expiration else {
+        return false;
+    };
+    if !rule_matches(rule, &meta.key, meta.size, &meta.tags) {
+        return false;
+    }
+    match (expiration.days, &expiration.date) {
+        (Some(days), _) => now >= due(meta.last_modified, days),
+        (None, Some(date)) => DateTime::parse_from_rfc3339(date).is_ok_and(|date| now >= date),
+        (None, None) => false,
+    }
+}
+
+/// Whether a noncurrent version has expired under `rule`. `noncurrent_since` is when
+/// the next newer version replaced it, `newer` how many noncurrent versions are newer.
+fn noncurrent_expired(
+    rule: &LifecycleRule,
+    meta: &ObjectMeta,
+    noncurrent_since: DateTime<Utc>,
+    newer: usize,
+    now: DateTime<Utc>,
+) -> bool {
+    let Some(noncurrent) = &rule.noncurrent_version_expiration else {
+        return false;
+    };
+    let Some(days) = noncurrent.noncurrent_days else {
+        return false;
+    };
+    let keep = noncurrent.newer_noncurrent_versions.unwrap_or(0) as usize;
+    newer >= keep
+        && now >= due(noncurrent_since, days)
+        && rule_matches(rule, &meta.key, meta.size, &meta.tags)
+}
+
+/// What a bucket scan did, for the log.
+#[derive(Debug, Default)]
+struct Progress {
+    keys: usize,
+    expired: usize,
+    noncurrent: usize,
+    delete_markers: usize,
+    uploads: usize,
+}
+
+/// Runs a scan every `interval`, the first one right away.
+pub fn spawn(interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = scan().await {
+                eprintln!("Lifecycle scan failed: {}", e);
+            }
+        }
+    });
+}
+
+/// One pass over every bucket. A bucket that fails is logged and skipped.
+pub async fn scan() -> io::Result<()> {
+    for bucket in storage::bucket_names().await? {
+        let lifecycle = match config::load(&bucket).await {
+            Ok(cfg) => cfg.lifecycle,
+            Err(e) => {
+                eprintln!("Lifecycle: cant read config of bucket '{}': {}", bucket, e);
+                continue;
+            }
+        };
+        let Some(lifecycle) = lifecycle else {
+            continue;
+        };
+        let rules: Vec<&LifecycleRule> = lifecycle.rules.iter().filter(|r| r.status == ENABLED).collect();
+        if rules.is_empty() {
+            continue;
+        }
+        println!("Lifecycle: scanning bucket '{}'", bucket);
+        let mut progress = Progress::default();
+        match scan_bucket(&bucket, &rules, &mut progress).await {
+            Ok(()) => println!(
+                "Lifecycle: bucket '{}' done, {} keys, {} expired, {} noncurrent versions, {} delete markers, {} uploads aborted",
+                bucket,
+                progress.keys,
+                progress.expired,
+                progress.noncurrent,
+                progress.delete_markers,
+                progress.uploads
+            ),
+            Err(e) => eprintln!("Lifecycle: scan of bucket '{}' failed after {} keys: {}", bucket, progress.keys, e),
+        }
+    }
+    Ok(())
+}
+
+/// Removes `seen` if it is still what the scan saw, the current version if `version_id`
+/// is `None`. Versions under object lock or changed since are left for a later pass,
+/// anything else is an error.
+async fn remove(bucket: &str, key: &str, version_id: Option<&str>, seen: &ObjectMeta) -> io::Result<bool> {
+    let condition = WriteCondition::Unchanged {
+        etag: seen.etag.clone(),
+        last_modified: seen.last_modified,
+    };
+    match storage::delete_object(bucket, key, version_id, false, &condition).await {
+        Ok(_) => Ok(true),
+        Err(WriteError::Condition(_)) => Ok(false),
+        Err(WriteError::Io(e)) => Err(e),
+    }
+}
+
+async fn scan_bucket(bucket: &str, rules: &[&LifecycleRule], progress: &mut Progress) -> io::Result<()> {
+    let now = Utc::now();
+    for key in storage::list_keys(bucket, "").await? {
+        progress.keys += 1;
+        if progress.keys.is_multiple_of(PROGRESS_EVERY) {
+            println!("Lifecycle: bucket '{}', {} keys scanned", bucket, progress.keys);
+        }
+        let versions = storage::object_versions(bucket, &key).await?;
+        let Some(current) = versions.first() else {
+            continue;
+        };
+        if !current.delete_marker && rules.iter().any(|r| current_expired(r, current, now)) {
+            // versioned buckets get a delete marker, the rest goes on the next pass
+            if remove(bucket, &key, None, current).await? {
+                progress.expired += 1;
+            }
+            continue;
+        }
+        let mut left = versions.len();
+        for (i, meta) in versions.iter().enumerate().skip(1) {
+            let since = versions[i - 1].last_modified;
+            if rules.iter().any(|r| noncurrent_expired(r, meta, since, i - 1, now))
+                && remove(bucket, &key, Some(versioning::version_name(meta)), meta).await?
+            {
+                progress.noncurrent += 1;
+                left -= 1;
+            }
+        }
+        // a delete marker with nothing left behind it is just clutter
+        let sole_marker = current.delete_marker && left == 1;
+        if sole_marker
+            && rules.iter().any(|r| {
+                r.expiration
+                    .as_ref()
+                    .is_some_and(|e| e.expired_object_delete_marker == Some(true))
+                    && rule_matches(r, &key, 0, &BTreeMap::new())
+            })
+            && remove(bucket, &key, Some(versioning::version_name(current)), current).await?
+        {
+            progress.delete_markers += 1;
+        }
+    }
+
+    for (upload_id, upload) in uploads::list_uploads(bucket).await? {
+        let abort = rules.iter().any(|r| {
+            r.abort_incomplete_multipart_upload
+                .as_ref()
+                .is_some_and(|a| now >= due(upload.initiated, a.days_after_initiation))
+                && rule_matches(r, &upload.key, 0, &upload.tags)
+        });
+        if abort {
+            uploads::abort(bucket, &upload_id).await?;
+            progress.uploads += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xml::{LifecycleAnd, LifecycleExpiration, LifecycleFilter};
+
+    fn rule(filter: Option<LifecycleFilter>) -> LifecycleRule {
+        LifecycleRule {
+            id: None,
+            prefix: None,
+            filter,
+            status: ENABLED.into(),
+            expiration: Some(LifecycleExpiration {
+                days: Some(1),
+                ..Default::default()
+            }),
+            noncurrent_version_expiration: None,
+            abort_incomplete_multipart_upload: None,
+        }
+    }
+
+    #[test]
+    fn test_rule_matches() {
+        let tags = BTreeMap::from([("env".to_string(), "scratch".to_string())]);
+        assert!(rule_matches(&rule(None), "any", 1, &BTreeMap::new()));
+        let by_prefix = rule(Some(LifecycleFilter {
+            prefix: Some("tmp/".into()),
+            ..Default::default()
+        }));
+        assert!(rule_matches(&by_prefix, "tmp/a", 1, &BTreeMap::new()));
+        assert!(!rule_matches(&by_prefix, "keep/a", 1, &BTreeMap::new()));
+        let combined = rule(Some(LifecycleFilter {
+            and: Some(LifecycleAnd {
+                prefix: Some("tmp/".into()),
+                tags: vec![Tag {
+                    key: "env".into(),
+                    value: "scratch".into(),
+                }],
+                object_size_greater_than: Some(10),
+                object_size_less_than: None,
+            }),
+            ..Default::default()
+        }));
+        assert!(rule_matches(&combined, "tmp/a", 11, &tags));
+        assert!(!rule_matches(&combined, "tmp/a", 10, &tags));
+        assert!(!rule_matches(&combined, "tmp/a", 11, &BTreeMap::new()));
+    }
+
+    #[test]
+    fn test_due_rounds_to_midnight() {
+        let since = DateTime::parse_from_rfc3339("2024-05-01T12:30:00Z").unwrap().to_utc();
+        assert_eq!(due(since, 1).to_rfc3339(), "2024-05-03T00:00:00+00:00");
+        let midnight = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z").unwrap().to_utc();
+        assert_eq!(due(midnight, 1).to_rfc3339(), "2024-05-02T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |rules| LifecycleConfiguration { rules };
+        assert_eq!(validate(&config(vec![rule(None)])), Ok(()));
+        assert_eq!(validate(&config(vec![])), Err(ErrorCode::MalformedXml));
+        let mut no_action = rule(None);
+        no_action.expiration = None;
+        assert_eq!(validate(&config(vec![no_action])), Err(ErrorCode::InvalidArgument));
+        let two_conditions = rule(Some(LifecycleFilter {
+            prefix: Some("a".into()),
+            object_size_less_than: Some(1),
+            ..Default::default()
+        }));
+        assert_eq!(validate(&config(vec![two_conditions])), Err(ErrorCode::MalformedXml));
+        let mut duplicate = rule(None);
+        duplicate.id = Some("x".into());
+        assert_eq!(
+            validate(&config(vec![duplicate.clone(), duplicate])),
+            Err(ErrorCode::InvalidArgument)
+        );
+    }
+}
@@ -1,63 +1,83 @@
 use axum::{
     body::{to_bytes, Body},
-    extract::Request,
-    http::Method,
+    extract::{OriginalUri, Query, Request, State},
+    http::header,
     middleware::Next,
     response::{
         Response,
         IntoResponse
     },
 };
-use lumi_credentials::aws_sigv4;
+use http_body_util::LengthLimitError;
+use lumi_credentials::aws_sigv4::{self, AwsCredentials};
 use lumi_utils::errors::{ErrorCode, RestErrorResponse};
+use std::{collections::HashMap, sync::Arc};
 
-const REQUEST_ID: &str = "unknown";
+use crate::http::MAX_BODY_SIZE;
+use crate::operation::Operation;
+use crate::state::AppState;
+use crate::tls::ClientCertificate;
 
-fn s3_error(code: ErrorCode, resource: &str) -> Response {
-    RestErrorResponse::from_error_code(code, resource.to_string(), REQUEST_ID.to_string())
-        .into_response()
+/// Who a request was authenticated as, either from SigV4 or a mapped client certificate.
+/// Public reads dont get one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub access_key: String,
 }
 
-/// Public routes: no auth. Everything else is protected and must pass Sig V4.
-fn is_public(method: &Method, path: &str) -> bool {
-    let path = path.trim_matches('/');
-    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-
-    // GET /{bucket}/{key} — public object read
-    if method == Method::GET && segments.len() >= 2 {
-        return true;
-    }
-    false
-}
-
-fn require_auth(
-    method: &str,
-    uri: &str,
+/// Everything that can be checked from the headers alone, so an unsigned or wrongly scoped
+/// request is turned away before its body is read. Gives back the parsed credentials and
+/// the secret to verify the signature with.
+fn check_credentials(
+    state: &AppState,
     headers: &axum::http::HeaderMap,
-    body: &[u8],
-    resource: &str,
-    expected_access_key: &str,
-    expected_secret_key: &str,
-) -> Result<(), Response> {
+) -> Result<(AwsCredentials, String), Box<RestErrorResponse>> {
     use aws_sigv4::ErrorCode as CredError;
 
     let creds = match aws_sigv4::parse_aws_credentials(headers) {
         Ok(c) => c,
-        Err(CredError::MissingCredTag) => {
-            return Err(s3_error(ErrorCode::MissingCredTag, resource));
-        }
-        Err(_) => {
-            return Err(s3_error(ErrorCode::CredMalformed, resource));
-        }
+        Err(CredError::MissingCredTag) => return Err(Box::new(ErrorCode::MissingCredTag.into())),
+        Err(CredError::InvalidRequest) => return Err(Box::new(ErrorCode::SignatureVersionNotSupported.into())),
+        Err(_) => return Err(Box::new(ErrorCode::CredMalformed.into())),
     };
 
-    let secret = if creds.access_key == expected_access_key {
-        expected_secret_key
-    } else {
-        return Err(s3_error(ErrorCode::InvalidAccessKeyId, resource));
+    // the signing key is derived from the scope's region, so any region would verify
+    if aws_sigv4::scope_region(&creds.credential_scope) != Some(state.region.as_str()) {
+        let mut err = RestErrorResponse::from(ErrorCode::WrongRegion);
+        err.message = format!(
+            "The authorization header is malformed; the region '{}' is wrong; expecting '{}'",
+            aws_sigv4::scope_region(&creds.credential_scope).unwrap_or_default(),
+            state.region
+        );
+        err.region = Some(state.region.clone());
+        return Err(Box::new(err));
+    }
+
+    let secret = match state.secret_key(&creds.access_key) {
+        Ok(Some(secret)) => secret,
+        Ok(None) => {
+            let mut err = RestErrorResponse::from(ErrorCode::InvalidAccessKeyId);
+            err.access_key_id = Some(creds.access_key);
+            return Err(Box::new(err));
+        }
+        Err(e) => {
+            eprintln!("Failed to read credential store: {}", e);
+            return Err(Box::new(ErrorCode::InternalError.into()));
+        }
     };
+    Ok((creds, secret))
+}
 
-    let expected = match aws_sigv4::calculate_signature(
+fn verify_signature(
+    state: &AppState,
+    creds: AwsCredentials,
+    secret: &str,
+    method: &str,
+    uri: &str,
+    headers: &axum::http::HeaderMap,
+    body: &[u8],
+) -> Result<Identity, Box<RestErrorResponse>> {
+    let expected = match aws_sigv4::signature_details(
         secret,
         method,
         uri,
@@ -67,47 +87,153 @@ fn require_auth(
         &creds.signed_headers,
     ) {
         Ok(s) => s,
-        Err(_) => return Err(s3_error(ErrorCode::SignatureDoesNotMatch, resource)),
+        Err(_) => return Err(Box::new(ErrorCode::SignatureDoesNotMatch.into())),
     };
 
-    if expected != creds.signature {
-        return Err(s3_error(ErrorCode::SignatureDoesNotMatch, resource));
+    if expected.signature != creds.signature {
+        // what we signed gives away nothing secret, but it's noisy, so only when asked
+        return Err(Box::new(match state.debug_signatures {
+            true => RestErrorResponse::signature_mismatch(
+                creds.access_key,
+                creds.signature,
+                expected.string_to_sign,
+                expected.canonical_request,
+            ),
+            false => ErrorCode::SignatureDoesNotMatch.into(),
+        }));
+    }
+
+    Ok(Identity {
+        access_key: creds.access_key,
+    })
+}
+
+/// `to_bytes` fails with a `LengthLimitError` somewhere in the chain once the body is past
+/// the limit, anything else is the client going away mid body.
+fn is_length_limit(e: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
     }
+    false
+}
+
+pub async fn auth(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let query = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .map(|Query(q)| q)
+        .unwrap_or_default();
+    let operation = match Operation::classify(request.method(), request.uri().path(), &query, request.headers()) {
+        Ok(operation) => operation,
+        Err(code) => return code.into_response(),
+    };
+    request.extensions_mut().insert(operation);
+    // left on the response as well, for the audit line
+    let mut response = authenticate(&state, operation, &query, request, next).await;
+    response.extensions_mut().insert(operation);
+    response
+}
 
-    Ok(())
+/// Runs the request as `identity`, which also ends up on the response.
+async fn run_as(mut request: Request, next: Next, identity: Identity) -> Response {
+    request.extensions_mut().insert(identity.clone());
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(identity);
+    response
 }
 
-pub async fn auth(
+async fn authenticate(
+    state: &AppState,
+    operation: Operation,
+    query: &HashMap<String, String>,
     request: Request,
     next: Next,
-    expected_access_key: &str,
-    expected_secret_key: &str,
 ) -> Response {
-    if is_public(request.method(), request.uri().path()) {
+    // a verified client cert that maps to a user counts the same as a request signed by them
+    if let Some(cert) = request.extensions().get::<ClientCertificate>()
+        && let Some(access_key) = state.cert_identity(cert)
+    {
+        let identity = Identity {
+            access_key: access_key.to_string(),
+        };
+        return run_as(request, next, identity).await;
+    }
+
+    // a signed public read is still checked, so it is logged as whoever signed it
+    if operation.is_public(query) && !request.headers().contains_key(header::AUTHORIZATION) {
         return next.run(request).await;
     }
 
+    let (creds, secret) = match check_credentials(state, request.headers()) {
+        Ok(checked) => checked,
+        Err(err) => return err.into_response(),
+    };
     let (parts, body) = request.into_parts();
-    let resource = parts.uri.path().to_string();
-    let body_bytes = match to_bytes(body, usize::MAX).await {
+    let body_bytes = match to_bytes(body, MAX_BODY_SIZE).await {
         Ok(b) => b,
-        Err(_) => return s3_error(ErrorCode::InvalidRequest, &resource),
+        Err(e) if is_length_limit(&e) => return ErrorCode::EntityTooLarge.into_response(),
+        Err(_) => return ErrorCode::InvalidRequest.into_response(),
     };
 
-    let uri = parts.uri.to_string();
+    // h2 requests carry an absolute uri, sigv4 only ever signs the path and query. virtual
+    // hosted requests were signed before the bucket moved into the path
+    let signed_uri = match parts.extensions.get::<OriginalUri>() {
+        Some(OriginalUri(original)) => original,
+        None => &parts.uri,
+    };
+    let uri = signed_uri
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
     let method = parts.method.as_str();
-    if let Err(r) = require_auth(
-        method,
-        &uri,
-        &parts.headers,
-        &body_bytes,
-        &resource,
-        expected_access_key,
-        expected_secret_key,
-    ) {
-        return r;
-    }
+    let identity = match verify_signature(state, creds, &secret, method, &uri, &parts.headers, &body_bytes) {
+        Ok(identity) => identity,
+        Err(err) => return err.into_response(),
+    };
 
     let request = Request::from_parts(parts, Body::from(body_bytes));
-    next.run(request).await
+    run_as(request, next, identity).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header::AUTHORIZATION, HeaderMap};
+
+    fn signed_for(region: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let auth = format!(
+            "AWS4-HMAC-SHA256 Credential=lumiserver/20261019/{}/s3/aws4_request, SignedHeaders=host, Signature=00",
+            region
+        );
+        headers.insert(AUTHORIZATION, auth.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_scope_region_has_to_match() {
+        let state = AppState::for_tests("eu-central-1");
+        let err = check_credentials(&state, &signed_for("us-east-1")).unwrap_err();
+        assert_eq!(err.code, "AuthorizationHeaderMalformed");
+        assert_eq!(err.status_code, axum::http::StatusCode::BAD_REQUEST);
+        assert!(err.message.contains("'us-east-1' is wrong; expecting 'eu-central-1'"));
+        let body = quick_xml::se::to_string(&*err).unwrap();
+        assert!(body.contains("<Region>eu-central-1</Region>"), "{}", body);
+
+        // the right region gets as far as checking the signature
+        let headers = signed_for("eu-central-1");
+        let (creds, secret) = check_credentials(&state, &headers).unwrap();
+        let err = verify_signature(&state, creds, &secret, "GET", "/", &headers, b"").unwrap_err();
+        assert_eq!(err.code, "SignatureDoesNotMatch");
+        assert_eq!(err.region, None);
+    }
+
+    #[tokio::test]
+    async fn test_body_past_the_limit() {
+        let err = to_bytes(Body::from(vec![0u8; 16]), 8).await.unwrap_err();
+        assert!(is_length_limit(&err));
+        assert!(to_bytes(Body::from(vec![0u8; 8]), 8).await.is_ok());
+    }
 }
@@ -0,0 +1,307 @@
+// which S3 operation a request is. worked out once from the method, path, query and
+// headers by the auth middleware, which leaves it on the request for the handlers to
+// dispatch on and on the response for the audit line. anything we recognize but don't do
+// is still classified, so it gets a NotImplemented instead of falling through to some
+// other operation.
+use axum::http::{HeaderMap, Method};
+use lumi_utils::errors::ErrorCode;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::routes::admin;
+use crate::routes::object::copy::COPY_SOURCE_HEADER;
+
+/// Named like the S3 API actions, `Display` gives that name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    ListBuckets,
+    // buckets
+    CreateBucket,
+    DeleteBucket,
+    HeadBucket,
+    ListObjects,
+    ListObjectsV2,
+    ListObjectVersions,
+    ListMultipartUploads,
+    DeleteObjects,
+    PostObject,
+    GetBucketLocation,
+    GetBucketEncryption,
+    PutBucketEncryption,
+    DeleteBucketEncryption,
+    GetBucketVersioning,
+    PutBucketVersioning,
+    GetBucketTagging,
+    PutBucketTagging,
+    DeleteBucketTagging,
+    GetBucketCors,
+    PutBucketCors,
+    DeleteBucketCors,
+    GetBucketWebsite,
+    PutBucketWebsite,
+    DeleteBucketWebsite,
+    GetBucketLifecycleConfiguration,
+    PutBucketLifecycleConfiguration,
+    DeleteBucketLifecycle,
+    GetObjectLockConfiguration,
+    PutObjectLockConfiguration,
+    GetBucketPolicy,
+    PutBucketPolicy,
+    DeleteBucketPolicy,
+    GetBucketAcl,
+    PutBucketAcl,
+    GetBucketLogging,
+    PutBucketLogging,
+    GetBucketNotificationConfiguration,
+    PutBucketNotificationConfiguration,
+    GetBucketReplication,
+    PutBucketReplication,
+    DeleteBucketReplication,
+    GetPublicAccessBlock,
+    PutPublicAccessBlock,
+    DeletePublicAccessBlock,
+    GetBucketOwnershipControls,
+    PutBucketOwnershipControls,
+    DeleteBucketOwnershipControls,
+    GetBucketAccelerateConfiguration,
+    PutBucketAccelerateConfiguration,
+    GetBucketRequestPayment,
+    PutBucketRequestPayment,
+    // objects
+    GetObject,
+    HeadObject,
+    PutObject,
+    CopyObject,
+    DeleteObject,
+    GetObjectTagging,
+    PutObjectTagging,
+    DeleteObjectTagging,
+    GetObjectRetention,
+    PutObjectRetention,
+    GetObjectLegalHold,
+    PutObjectLegalHold,
+    GetObjectAcl,
+    PutObjectAcl,
+    GetObjectAttributes,
+    GetObjectTorrent,
+    RestoreObject,
+    SelectObjectContent,
+    CreateMultipartUpload,
+    UploadPart,
+    UploadPartCopy,
+    CompleteMultipartUpload,
+    AbortMultipartUpload,
+    ListParts,
+    /// lumi's own endpoints under `/_lumi`, they route themselves
+    LumiAdmin,
+}
+
+use Operation::*;
+
+/// `?subresource` + method -> operation, the first subresource present wins.
+const BUCKET_SUBRESOURCES: &[(&str, &str, Operation)] = &[
+    ("delete", "POST", DeleteObjects),
+    ("encryption", "GET", GetBucketEncryption),
+    ("encryption", "PUT", PutBucketEncryption),
+    ("encryption", "DELETE", DeleteBucketEncryption),
+    ("versions", "GET", ListObjectVersions),
+    ("versioning", "GET", GetBucketVersioning),
+    ("versioning", "PUT", PutBucketVersioning),
+    ("tagging", "GET", GetBucketTagging),
+    ("tagging", "PUT", PutBucketTagging),
+    ("tagging", "DELETE", DeleteBucketTagging),
+    ("cors", "GET", GetBucketCors),
+    ("cors", "PUT", PutBucketCors),
+    ("cors", "DELETE", DeleteBucketCors),
+    ("website", "GET", GetBucketWebsite),
+    ("website", "PUT", PutBucketWebsite),
+    ("website", "DELETE", DeleteBucketWebsite),
+    ("lifecycle", "GET", GetBucketLifecycleConfiguration),
+    ("lifecycle", "PUT", PutBucketLifecycleConfiguration),
+    ("lifecycle", "DELETE", DeleteBucketLifecycle),
+    ("object-lock", "GET", GetObjectLockConfiguration),
+    ("object-lock", "PUT", PutObjectLockConfiguration),
+    ("uploads", "GET", ListMultipartUploads),
+    ("location", "GET", GetBucketLocation),
+    ("policy", "GET", GetBucketPolicy),
+    ("policy", "PUT", PutBucketPolicy),
+    ("policy", "DELETE", DeleteBucketPolicy),
+    ("acl", "GET", GetBucketAcl),
+    ("acl", "PUT", PutBucketAcl),
+    ("logging", "GET", GetBucketLogging),
+    ("logging", "PUT", PutBucketLogging),
+    ("notification", "GET", GetBucketNotificationConfiguration),
+    ("notification", "PUT", PutBucketNotificationConfiguration),
+    ("replication", "GET", GetBucketReplication),
+    ("replication", "PUT", PutBucketReplication),
+    ("replication", "DELETE", DeleteBucketReplication),
+    ("publicAccessBlock", "GET", GetPublicAccessBlock),
+    ("publicAccessBlock", "PUT", PutPublicAccessBlock),
+    ("publicAccessBlock", "DELETE", DeletePublicAccessBlock),
+    ("ownershipControls", "GET", GetBucketOwnershipControls),
+    ("ownershipControls", "PUT", PutBucketOwnershipControls),
+    ("ownershipControls", "DELETE", DeleteBucketOwnershipControls),
+    ("accelerate", "GET", GetBucketAccelerateConfiguration),
+    ("accelerate", "PUT", PutBucketAccelerateConfiguration),
+    ("requestPayment", "GET", GetBucketRequestPayment),
+    ("requestPayment", "PUT", PutBucketRequestPayment),
+];
+
+const OBJECT_SUBRESOURCES: &[(&str, &str, Operation)] = &[
+    ("retention", "GET", GetObjectRetention),
+    ("retention", "PUT", PutObjectRetention),
+    ("tagging", "GET", GetObjectTagging),
+    ("tagging", "PUT", PutObjectTagging),
+    ("tagging", "DELETE", DeleteObjectTagging),
+    ("legal-hold", "GET", GetObjectLegalHold),
+    ("legal-hold", "PUT", PutObjectLegalHold),
+    ("acl", "GET", GetObjectAcl),
+    ("acl", "PUT", PutObjectAcl),
+    ("attributes", "GET", GetObjectAttributes),
+    ("torrent", "GET", GetObjectTorrent),
+    ("restore", "POST", RestoreObject),
+    ("select", "POST", SelectObjectContent),
+    ("uploads", "POST", CreateMultipartUpload),
+];
+
+/// Looks `query` up in a subresource table. `Some(Err)` when a subresource is there but
+/// not with this method.
+fn by_subresource(
+    table: &[(&str, &str, Operation)],
+    method: &Method,
+    query: &HashMap<String, String>,
+) -> Option<Result<Operation, ErrorCode>> {
+    let subresource = table.iter().map(|(name, ..)| *name).find(|name| query.contains_key(*name))?;
+    let found = table
+        .iter()
+        .find(|(name, m, _)| *name == subresource && *m == method.as_str())
+        .map(|(.., operation)| *operation);
+    Some(found.ok_or(ErrorCode::MethodNotAllowed))
+}
+
+impl Operation {
+    /// `path` is path style, virtual-hosted requests have been rewritten by then.
+    pub fn classify(
+        method: &Method,
+        path: &str,
+        query: &HashMap<String, String>,
+        headers: &HeaderMap,
+    ) -> Result<Operation, ErrorCode> {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        if bucket == admin::PREFIX {
+            return Ok(LumiAdmin);
+        }
+        if bucket.is_empty() {
+            return match *method {
+                Method::GET => Ok(ListBuckets),
+                _ => Err(ErrorCode::MethodNotAllowed),
+            };
+        }
+        if key.is_empty() {
+            if let Some(operation) = by_subresource(BUCKET_SUBRESOURCES, method, query) {
+                return operation;
+            }
+            return match *method {
+                Method::GET if query.get("list-type").is_some_and(|v| v == "2") => Ok(ListObjectsV2),
+                Method::GET => Ok(ListObjects),
+                Method::PUT => Ok(CreateBucket),
+                Method::DELETE => Ok(DeleteBucket),
+                Method::HEAD => Ok(HeadBucket),
+                Method::POST => Ok(PostObject),
+                _ => Err(ErrorCode::MethodNotAllowed),
+            };
+        }
+        if let Some(operation) = by_subresource(OBJECT_SUBRESOURCES, method, query) {
+            return operation;
+        }
+        let copy = headers.contains_key(COPY_SOURCE_HEADER);
+        if query.contains_key("uploadId") {
+            return match *method {
+                Method::GET => Ok(ListParts),
+                Method::PUT if copy => Ok(UploadPartCopy),
+                Method::PUT => Ok(UploadPart),
+                Method::POST => Ok(CompleteMultipartUpload),
+                Method::DELETE => Ok(AbortMultipartUpload),
+                _ => Err(ErrorCode::MethodNotAllowed),
+            };
+        }
+        match *method {
+            Method::GET => Ok(GetObject),
+            Method::HEAD => Ok(HeadObject),
+            Method::PUT if copy => Ok(CopyObject),
+            Method::PUT => Ok(PutObject),
+            Method::DELETE => Ok(DeleteObject),
+            _ => Err(ErrorCode::MethodNotAllowed),
+        }
+    }
+
+    /// Reads anyone may do without signing, only ever the current version of an object.
+    pub fn is_public(self, query: &HashMap<String, String>) -> bool {
+        self == GetObject && !query.contains_key("versionId")
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_query(query: &str) -> HashMap<String, String> {
+        query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                (k.to_string(), v.to_string())
+            })
+            .collect()
+    }
+
+    fn classify(method: Method, path: &str, query: &str) -> Result<Operation, ErrorCode> {
+        Operation::classify(&method, path, &parse_query(query), &HeaderMap::new())
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(Method::GET, "/", ""), Ok(ListBuckets));
+        assert_eq!(classify(Method::GET, "/photos", "location"), Ok(GetBucketLocation));
+        assert_eq!(classify(Method::GET, "/photos/", "list-type=2&prefix=a"), Ok(ListObjectsV2));
+        assert_eq!(classify(Method::GET, "/photos", "versioning"), Ok(GetBucketVersioning));
+        assert_eq!(classify(Method::GET, "/photos", "tagging"), Ok(GetBucketTagging));
+        assert_eq!(classify(Method::POST, "/photos", "versioning"), Err(ErrorCode::MethodNotAllowed));
+        assert_eq!(classify(Method::PUT, "/photos/a/b.jpg", "tagging&versionId=1"), Ok(PutObjectTagging));
+        assert_eq!(classify(Method::PUT, "/photos/a", "partNumber=1&uploadId=x"), Ok(UploadPart));
+        assert_eq!(classify(Method::POST, "/photos/a", "uploads"), Ok(CreateMultipartUpload));
+        assert_eq!(classify(Method::GET, "/photos/a", "acl"), Ok(GetObjectAcl));
+        assert_eq!(classify(Method::GET, "/photos/a", "versionId=1"), Ok(GetObject));
+        assert_eq!(classify(Method::PATCH, "/photos/a", ""), Err(ErrorCode::MethodNotAllowed));
+        assert_eq!(classify(Method::GET, "/_lumi/admin/kms/keys", ""), Ok(LumiAdmin));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COPY_SOURCE_HEADER, "/src/key".parse().unwrap());
+        let copy = Operation::classify(&Method::PUT, "/photos/a", &HashMap::new(), &headers);
+        assert_eq!(copy, Ok(CopyObject));
+        assert_eq!(GetBucketLifecycleConfiguration.to_string(), "GetBucketLifecycleConfiguration");
+    }
+
+    #[test]
+    fn test_is_public() {
+        let public = |method: Method, path: &str, query: &str| {
+            classify(method, path, query).unwrap().is_public(&parse_query(query))
+        };
+        assert!(public(Method::GET, "/photos/a", ""));
+        assert!(public(Method::GET, "/photos/a", "response-content-type=text/plain"));
+        // older versions stay private, even the null one
+        assert!(!public(Method::GET, "/photos/a", "versionId=1"));
+        assert!(!public(Method::GET, "/photos/a", "versionId=null"));
+        assert!(!public(Method::HEAD, "/photos/a", ""));
+        assert!(!public(Method::GET, "/photos", "list-type=2"));
+        assert!(!public(Method::GET, "/photos/a", "tagging"));
+    }
+}
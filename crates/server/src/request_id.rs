@@ -0,0 +1,95 @@
+// every request gets an id, sent back as x-amz-request-id (and a host id as x-amz-id-2)
+// and put in the body of error responses together with the resource they were about, so
+// a failure a client reports can be found in the server log.
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lumi_credentials::aws_sigv4::percent_decode;
+use lumi_credentials::envelope::random_bytes;
+use lumi_utils::errors::RestErrorResponse;
+
+pub const REQUEST_ID_HEADER: &str = "x-amz-request-id";
+pub const HOST_ID_HEADER: &str = "x-amz-id-2";
+
+/// The ids of the request being handled, for handlers that render their own errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId {
+    pub id: String,
+    pub host_id: String,
+}
+
+/// 16 uppercase hex characters, like S3's.
+pub fn new_request_id() -> String {
+    hex::encode_upper(random_bytes::<8>())
+}
+
+/// Gives the request an id and completes error responses with it. Has to run after the
+/// virtual-hosted rewrite so the resource comes out path style.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = new_request_id();
+    let host_id = STANDARD.encode(random_bytes::<32>());
+    let method = request.method().clone();
+    let path = String::from_utf8_lossy(&percent_decode(request.uri().path())).into_owned();
+    request.extensions_mut().insert(RequestId {
+        id: id.clone(),
+        host_id: host_id.clone(),
+    });
+
+    let mut response = next.run(request).await;
+    if let Some(err) = response.extensions_mut().remove::<RestErrorResponse>() {
+        let err = err.complete(id.clone(), host_id.clone(), &path);
+        if err.status_code.is_server_error() {
+            eprintln!("Request {} ({} {}) failed: {}", id, method, path, err.code);
+        }
+        // the rendered error replaces the body, headers added on the way out (cors) stay.
+        // HEAD responses can't have one, the status and headers are all they get
+        let (mut parts, _) = response.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        let body = match method {
+            Method::HEAD => Body::empty(),
+            _ => err.into_response().into_body(),
+        };
+        response = Response::from_parts(parts, body);
+    }
+    let headers = response.headers_mut();
+    headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&id).unwrap());
+    headers.insert(HOST_ID_HEADER, HeaderValue::from_str(&host_id).unwrap());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lumi_utils::errors::ErrorCode;
+
+    #[test]
+    fn test_complete() {
+        let id = new_request_id();
+        assert_eq!(id.len(), 16);
+        assert!(id.bytes().all(|b| b.is_ascii_digit() || b.is_ascii_uppercase()));
+
+        let blank = RestErrorResponse::from;
+        let err = blank(ErrorCode::NoSuchKey).complete(id.clone(), String::new(), "/photos/a b/c.jpg");
+        assert_eq!(err.resource, "/photos/a b/c.jpg");
+        assert_eq!(err.request_id, id);
+        assert_eq!(err.bucket_name.as_deref(), Some("photos"));
+        assert_eq!(err.key.as_deref(), Some("a b/c.jpg"));
+
+        let err = blank(ErrorCode::NoSuchBucket).complete(id.clone(), String::new(), "/photos/");
+        assert_eq!((err.resource.as_str(), err.bucket_name.as_deref()), ("/photos", Some("photos")));
+
+        let err = blank(ErrorCode::AccessDenied).complete(id.clone(), String::new(), "/");
+        assert_eq!((err.resource.as_str(), err.bucket_name), ("/", None));
+
+        // whatever a handler did fill in is kept
+        let err = RestErrorResponse::no_such_key("x".into(), "b".into(), "k".into());
+        let err = err.complete(id.clone(), "host".into(), "/b/other");
+        assert_eq!((err.resource.as_str(), err.key.as_deref()), ("/b/k", Some("k")));
+        assert_eq!((err.request_id, err.host_id.as_str()), (id, "host"));
+    }
+}
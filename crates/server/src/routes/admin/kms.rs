@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Path, State},
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use lumi_utils::errors::ErrorCode;
+use std::{collections::HashMap, sync::Arc};
+
+use super::{json, require_root};
+use crate::middleware::Identity;
+use crate::state::AppState;
+
+// GET  /_lumi/admin/kms/keys                    list keys
+// GET  /_lumi/admin/kms/keys/{key_id}           describe one
+// POST /_lumi/admin/kms/keys/{key_id}           create
+// POST /_lumi/admin/kms/keys/{key_id}/{action}  enable, disable or rotate
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    identity: Option<Extension<Identity>>,
+    method: Method,
+    path: Option<Path<HashMap<String, String>>>,
+) -> Response {
+    if let Err(code) = require_root(&state, identity) {
+        return code.into_response();
+    }
+    let params = path.map(|p| p.0).unwrap_or_default();
+    let key_id = params.get("key_id").map(String::as_str);
+    let action = params.get("action").map(String::as_str);
+    let kms = &state.kms;
+    let result = match (method, key_id, action) {
+        (Method::GET, None, None) => return json(StatusCode::OK, kms.list().await),
+        (Method::GET, Some(key_id), None) => {
+            match kms.list().await.into_iter().find(|k| k.key_id == key_id) {
+                Some(info) => Ok(info),
+                None => Err(ErrorCode::KmsKeyNotFound),
+            }
+        }
+        (Method::POST, Some(key_id), None) => match kms.create(key_id).await {
+            Ok(info) => return json(StatusCode::CREATED, info),
+            Err(e) => Err(e.into()),
+        },
+        (Method::POST, Some(key_id), Some("enable")) => kms.set_enabled(key_id, true).await.map_err(Into::into),
+        (Method::POST, Some(key_id), Some("disable")) => kms.set_enabled(key_id, false).await.map_err(Into::into),
+        (Method::POST, Some(key_id), Some("rotate")) => kms.rotate(key_id).await.map_err(Into::into),
+        _ => Err(ErrorCode::MethodNotAllowed),
+    };
+    match result {
+        Ok(info) => json(StatusCode::OK, info),
+        Err(code) => code.into_response(),
+    }
+}
@@ -0,0 +1,23 @@
+use axum::{
+    extract::State,
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use lumi_utils::errors::ErrorCode;
+use std::sync::Arc;
+
+use super::{json, require_root};
+use crate::middleware::Identity;
+use crate::state::AppState;
+
+// GET /_lumi/admin/metrics  requests and errors per operation since startup
+pub async fn handler(State(state): State<Arc<AppState>>, identity: Option<Extension<Identity>>, method: Method) -> Response {
+    if let Err(code) = require_root(&state, identity) {
+        return code.into_response();
+    }
+    match method {
+        Method::GET => json(StatusCode::OK, state.metrics.snapshot()),
+        _ => ErrorCode::MethodNotAllowed.into_response(),
+    }
+}
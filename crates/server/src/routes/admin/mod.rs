@@ -0,0 +1,28 @@
+// lumi's own admin endpoints under /_lumi/admin. bucket names cant start with an
+// underscore so these never collide with s3 paths. root only.
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use lumi_utils::errors::ErrorCode;
+use serde::Serialize;
+
+use crate::middleware::Identity;
+use crate::state::AppState;
+
+pub mod kms;
+pub mod metrics;
+
+pub const PREFIX: &str = "_lumi";
+
+fn require_root(state: &AppState, identity: Option<Extension<Identity>>) -> Result<(), ErrorCode> {
+    match identity {
+        Some(Extension(identity)) if identity.access_key == state.root_access_key => Ok(()),
+        _ => Err(ErrorCode::AccessDenied),
+    }
+}
+
+fn json<T: Serialize>(status: StatusCode, value: T) -> Response {
+    (status, Json(value)).into_response()
+}
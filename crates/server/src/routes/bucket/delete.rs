@@ -0,0 +1,110 @@
+// POST /{bucket}?delete, up to 1000 keys in one request. every key gets its own
+// Deleted / Error entry, one bad key doesnt fail the rest.
+use axum::{
+    body::Bytes,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lumi_utils::errors::ErrorCode;
+
+use crate::storage::{self, versioning, WriteCondition, WriteError};
+use crate::xml::{self, Delete, DeleteError, DeleteResult, DeletedObject};
+
+pub const MAX_DELETE_OBJECTS: usize = 1000;
+
+/// Checks `Content-MD5` when the client sent one. Newer SDKs send a checksum header
+/// instead, the body is still parsed and validated as xml either way.
+fn check_content_md5(headers: &HeaderMap, body: &[u8]) -> Result<(), ErrorCode> {
+    let Some(value) = headers.get("content-md5") else {
+        return Ok(());
+    };
+    let expected = STANDARD
+        .decode(value.as_bytes())
+        .map_err(|_| ErrorCode::InvalidDigest)?;
+    if expected.as_slice() != md5::compute(body).as_slice() {
+        return Err(ErrorCode::InvalidDigest);
+    }
+    Ok(())
+}
+
+pub async fn delete_objects(
+    bucket: String,
+    headers: HeaderMap,
+    body: Bytes,
+    bypass_governance: bool,
+) -> Response {
+    if let Err(code) = check_content_md5(&headers, &body) {
+        return code.into_response();
+    }
+    let request: Delete = match xml::from_xml(&body) {
+        Ok(r) => r,
+        Err(code) => return code.into_response(),
+    };
+    if request.objects.is_empty() {
+        return ErrorCode::MalformedXml.into_response();
+    }
+    if request.objects.len() > MAX_DELETE_OBJECTS {
+        return ErrorCode::InvalidMaxDeleteObjects.into_response();
+    }
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+
+    let mut result = DeleteResult::default();
+    for object in request.objects {
+        let outcome = match object.version_id.as_deref() {
+            Some(id) if !versioning::is_valid_version_id(id) => Err(ErrorCode::NoSuchVersion),
+            id => storage::delete_object(&bucket, &object.key, id, bypass_governance, &WriteCondition::Always)
+                .await
+                .map_err(|e| match e {
+                    WriteError::Condition(code) => code,
+                    WriteError::Io(e) => {
+                        eprintln!("Failed to delete object '{}/{}': {}", bucket, object.key, e);
+                        ErrorCode::InternalError
+                    }
+                }),
+        };
+        match outcome {
+            // deleting something that isnt there still counts as deleted
+            Ok(_) if request.quiet => {}
+            Ok(deletion) => result.deleted.push(DeletedObject {
+                key: object.key,
+                delete_marker: deletion.delete_marker.then_some(true),
+                // a new marker has its own version, removing one by id just echoes it
+                delete_marker_version_id: deletion
+                    .version_id
+                    .filter(|_| deletion.delete_marker),
+                version_id: object.version_id,
+            }),
+            Err(code) => {
+                let error = code.to_api_error();
+                result.errors.push(DeleteError {
+                    key: object.key,
+                    version_id: object.version_id,
+                    code: error.code,
+                    message: error.description,
+                });
+            }
+        }
+    }
+    xml::ok_response(&result, HeaderMap::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_content_md5() {
+        let body = b"<Delete></Delete>";
+        let mut headers = HeaderMap::new();
+        assert_eq!(check_content_md5(&headers, body), Ok(()));
+        let digest = STANDARD.encode(md5::compute(body).as_slice());
+        headers.insert("content-md5", digest.parse().unwrap());
+        assert_eq!(check_content_md5(&headers, body), Ok(()));
+        assert_eq!(check_content_md5(&headers, b"other"), Err(ErrorCode::InvalidDigest));
+        headers.insert("content-md5", "not base64!".parse().unwrap());
+        assert_eq!(check_content_md5(&headers, body), Err(ErrorCode::InvalidDigest));
+    }
+}
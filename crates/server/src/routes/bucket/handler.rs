@@ -1,14 +1,19 @@
 use axum::{
     body::Bytes,
-    extract::{Path, Query},
-    http::{HeaderMap, Method},
+    extract::{Extension, Path, Query, State},
+    http::HeaderMap,
     response::{IntoResponse, Response},
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 use super::operations;
+use crate::middleware::Identity;
+use crate::operation::Operation;
+use crate::state::AppState;
 
 pub async fn handler(
-    method: Method,
+    State(state): State<Arc<AppState>>,
+    identity: Option<Extension<Identity>>,
+    Extension(operation): Extension<Operation>,
     path: Option<Path<HashMap<String, String>>>,
     Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
@@ -22,5 +27,6 @@ pub async fn handler(
         Some(b) if !b.is_empty() => b.clone(),
         _ => {return operations::list_buckets().await.into_response()},
     };
-    operations::handle(method, bucket, query).await.into_response()
+    let identity = identity.map(|Extension(identity)| identity);
+    operations::handle(&state, identity.as_ref(), operation, bucket, query, headers, body).await.into_response()
 }
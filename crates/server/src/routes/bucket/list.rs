@@ -0,0 +1,203 @@
+// GET /{bucket}, ListObjects and ListObjectsV2. current versions only, keys in order
+// with the ones sharing a prefix up to the delimiter folded into CommonPrefixes, the
+// same way ?versions does it.
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lumi_credentials::aws_sigv4::uri_encode;
+use lumi_utils::errors::{ErrorCode, RestErrorResponse};
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use super::versions::{common_prefix, MAX_KEYS};
+use crate::storage::{self, ObjectMeta};
+use crate::xml::{self, CommonPrefix, ListBucketResult, ListBucketResultV2, ListedObject, Owner};
+
+/// Query parameters both versions share.
+struct ListParams {
+    prefix: String,
+    delimiter: Option<String>,
+    max_keys: u32,
+    url_encoded: bool,
+}
+
+impl ListParams {
+    fn parse(query: &HashMap<String, String>) -> Result<Self, Box<RestErrorResponse>> {
+        let max_keys = match query.get("max-keys").map(|n| (n, n.parse::<u32>())) {
+            None => MAX_KEYS,
+            Some((_, Ok(n))) => n.min(MAX_KEYS),
+            Some((n, Err(_))) => return Err(Box::new(ErrorCode::InvalidMaxKeys.argument("max-keys", n))),
+        };
+        let url_encoded = match query.get("encoding-type").map(String::as_str) {
+            None => false,
+            Some("url") => true,
+            Some(other) => return Err(Box::new(ErrorCode::InvalidArgument.argument("encoding-type", other))),
+        };
+        Ok(ListParams {
+            prefix: query.get("prefix").cloned().unwrap_or_default(),
+            delimiter: query.get("delimiter").filter(|d| !d.is_empty()).cloned(),
+            max_keys,
+            url_encoded,
+        })
+    }
+
+    fn encode(&self, value: String) -> String {
+        match self.url_encoded {
+            true => uri_encode(value.as_bytes()),
+            false => value,
+        }
+    }
+}
+
+/// One page of a listing.
+#[derive(Default)]
+struct Page {
+    objects: Vec<ObjectMeta>,
+    common_prefixes: Vec<String>,
+    is_truncated: bool,
+    /// the last key or prefix on the page, where the next one starts after
+    last: Option<String>,
+}
+
+/// Lists everything after `after` (exclusive).
+async fn list_page(bucket: &str, params: &ListParams, after: &str) -> Result<Page, ErrorCode> {
+    if !storage::bucket_exists(bucket).await {
+        return Err(ErrorCode::NoSuchBucket);
+    }
+    let keys = storage::list_keys(bucket, &params.prefix).await.map_err(|e| {
+        eprintln!("Failed to list keys of '{}': {}", bucket, e);
+        ErrorCode::InternalError
+    })?;
+    let mut page = Page::default();
+    let start = (Bound::Excluded(after.to_string()), Bound::Unbounded);
+    for key in keys.range::<String, _>(start) {
+        let group = common_prefix(key, &params.prefix, params.delimiter.as_deref());
+        // the last page may have ended on this very prefix
+        if let Some(group) = group
+            && (group == after || page.common_prefixes.last().is_some_and(|p| p == group))
+        {
+            continue;
+        }
+        // keys whose latest version is a delete marker aren't listed, and a prefix with
+        // only those under it isnt either
+        let meta = match storage::stat_object(bucket, key).await {
+            Ok(Some(meta)) => meta,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Failed to read object '{}/{}': {}", bucket, key, e);
+                return Err(ErrorCode::InternalError);
+            }
+        };
+        if page.objects.len() + page.common_prefixes.len() == params.max_keys as usize {
+            page.is_truncated = true;
+            break;
+        }
+        match group {
+            Some(group) => {
+                page.common_prefixes.push(group.to_string());
+                page.last = Some(group.to_string());
+            }
+            None => {
+                page.last = Some(key.clone());
+                page.objects.push(meta);
+            }
+        }
+    }
+    Ok(page)
+}
+
+fn contents(page: &Page, params: &ListParams, owner: bool) -> (Vec<ListedObject>, Vec<CommonPrefix>) {
+    let objects = page
+        .objects
+        .iter()
+        .map(|meta| ListedObject {
+            key: params.encode(meta.key.clone()),
+            last_modified: xml::timestamp(&meta.last_modified),
+            etag: format!("\"{}\"", meta.etag),
+            size: meta.size,
+            owner: owner.then(Owner::default),
+            storage_class: "STANDARD".into(),
+        })
+        .collect();
+    let prefixes = page
+        .common_prefixes
+        .iter()
+        .map(|prefix| CommonPrefix {
+            prefix: params.encode(prefix.clone()),
+        })
+        .collect();
+    (objects, prefixes)
+}
+
+pub async fn list_objects(bucket: String, query: &HashMap<String, String>) -> Response {
+    let params = match ListParams::parse(query) {
+        Ok(p) => p,
+        Err(err) => return err.into_response(),
+    };
+    let marker = query.get("marker").cloned().unwrap_or_default();
+    let page = match list_page(&bucket, &params, &marker).await {
+        Ok(p) => p,
+        Err(code) => return code.into_response(),
+    };
+    let (contents, common_prefixes) = contents(&page, &params, true);
+    let next_marker = match page.is_truncated && params.delimiter.is_some() {
+        true => page.last.map(|m| params.encode(m)),
+        false => None,
+    };
+    let result = ListBucketResult {
+        name: bucket,
+        prefix: params.encode(params.prefix.clone()),
+        marker: params.encode(marker),
+        next_marker,
+        max_keys: params.max_keys,
+        delimiter: params.delimiter.clone().map(|d| params.encode(d)),
+        encoding_type: params.url_encoded.then(|| "url".to_string()),
+        is_truncated: page.is_truncated,
+        contents,
+        common_prefixes,
+    };
+    xml::ok_response(&result, HeaderMap::new())
+}
+
+pub async fn list_objects_v2(bucket: String, query: &HashMap<String, String>) -> Response {
+    let params = match ListParams::parse(query) {
+        Ok(p) => p,
+        Err(err) => return err.into_response(),
+    };
+    // tokens are just the key to continue after, opaque enough for clients
+    let continuation_token = query.get("continuation-token").cloned();
+    let after = match &continuation_token {
+        Some(token) => match STANDARD.decode(token).ok().and_then(|k| String::from_utf8(k).ok()) {
+            Some(key) => key,
+            None => return ErrorCode::InvalidArgument.argument("continuation-token", token).into_response(),
+        },
+        None => query.get("start-after").cloned().unwrap_or_default(),
+    };
+    let fetch_owner = query.get("fetch-owner").is_some_and(|v| v == "true");
+    let page = match list_page(&bucket, &params, &after).await {
+        Ok(p) => p,
+        Err(code) => return code.into_response(),
+    };
+    let (contents, common_prefixes) = contents(&page, &params, fetch_owner);
+    let next_continuation_token = match page.is_truncated {
+        true => page.last.map(|last| STANDARD.encode(last)),
+        false => None,
+    };
+    let result = ListBucketResultV2 {
+        name: bucket,
+        prefix: params.encode(params.prefix.clone()),
+        continuation_token,
+        next_continuation_token,
+        start_after: query.get("start-after").cloned().map(|s| params.encode(s)),
+        key_count: (contents.len() + common_prefixes.len()) as u32,
+        max_keys: params.max_keys,
+        delimiter: params.delimiter.clone().map(|d| params.encode(d)),
+        encoding_type: params.url_encoded.then(|| "url".to_string()),
+        is_truncated: page.is_truncated,
+        contents,
+        common_prefixes,
+    };
+    xml::ok_response(&result, HeaderMap::new())
+}
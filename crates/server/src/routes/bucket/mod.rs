@@ -1,3 +1,6 @@
+pub mod delete;
 pub mod handler;
+pub mod list;
 pub mod operations;
+pub mod versions;
 pub use handler::handler;
\ No newline at end of file
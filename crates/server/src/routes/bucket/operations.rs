@@ -1,5 +1,6 @@
 use axum::{
-    http::{Method, StatusCode},
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::{
         IntoResponse,
         Response
@@ -9,75 +10,131 @@ use std::collections::HashMap;
 use tokio::fs::read_dir;
 use chrono::{DateTime, Utc};
 use lumi_utils::errors::ErrorCode;
+use super::{delete, list, versions};
+use crate::cors;
+use crate::http::DEFAULT_REGION;
+use crate::lifecycle;
+use crate::middleware::Identity;
+use crate::operation::Operation;
+use crate::routes::object::lock;
+use crate::state::AppState;
+use crate::website;
+use crate::storage::{self, config, object_lock, sse, tagging, versioning};
 use crate::xml::{
+    self,
+    AccessControlPolicy,
     ListAllMyBucketsResult, 
     Owner, 
     Buckets, 
-    Bucket
+    Bucket,
+    CorsConfiguration,
+    CreateBucketConfiguration,
+    LifecycleConfiguration,
+    LocationConstraint,
+    ObjectLockConfiguration,
+    ServerSideEncryptionConfiguration,
+    TagSet,
+    Tagging,
+    VersioningConfiguration,
+    WebsiteConfiguration,
 };
 
+pub const OBJECT_LOCK_ENABLED_HEADER: &str = "x-amz-bucket-object-lock-enabled";
+/// on every response about a bucket that exists, how SDKs find out where it lives
+pub const BUCKET_REGION_HEADER: &str = "x-amz-bucket-region";
+
 pub async fn handle(
-    method: Method,
+    state: &AppState,
+    identity: Option<&Identity>,
+    operation: Operation,
     bucket: String,
     query: HashMap<String, String>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Response {
-    match method {
-        Method::GET => list_objects().await.into_response(),
-        Method::PUT => create_bucket(bucket).await.into_response(),
-        _ => ErrorCode::MethodNotAllowed.into_response(),
+    let name = bucket.clone();
+    let mut response = match operation {
+        Operation::ListBuckets => list_buckets().await.into_response(),
+        Operation::CreateBucket => create_bucket(state, bucket, &headers, body).await.into_response(),
+        Operation::HeadBucket => head_bucket(bucket).await.into_response(),
+        Operation::GetBucketLocation => get_bucket_location(state, bucket).await.into_response(),
+        Operation::DeleteObjects => {
+            let bypass_governance = lock::bypass_governance(state, identity, &headers);
+            delete::delete_objects(bucket, headers, body, bypass_governance).await
+        }
+        Operation::ListObjects => list::list_objects(bucket, &query).await,
+        Operation::ListObjectsV2 => list::list_objects_v2(bucket, &query).await,
+        Operation::GetBucketAcl => get_bucket_acl(bucket).await.into_response(),
+        Operation::ListObjectVersions => versions::list_object_versions(bucket, &query).await,
+        Operation::GetBucketEncryption => get_bucket_encryption(bucket).await.into_response(),
+        Operation::PutBucketEncryption => put_bucket_encryption(bucket, body).await.into_response(),
+        Operation::DeleteBucketEncryption => delete_bucket_encryption(bucket).await.into_response(),
+        Operation::GetBucketVersioning => get_bucket_versioning(bucket).await.into_response(),
+        Operation::PutBucketVersioning => put_bucket_versioning(bucket, body).await.into_response(),
+        Operation::GetBucketTagging => get_bucket_tagging(bucket).await.into_response(),
+        Operation::PutBucketTagging => put_bucket_tagging(bucket, body).await.into_response(),
+        Operation::DeleteBucketTagging => delete_bucket_tagging(bucket).await.into_response(),
+        Operation::GetBucketCors => get_bucket_cors(bucket).await.into_response(),
+        Operation::PutBucketCors => put_bucket_cors(bucket, body).await.into_response(),
+        Operation::DeleteBucketCors => delete_bucket_cors(bucket).await.into_response(),
+        Operation::GetBucketWebsite => get_bucket_website(bucket).await.into_response(),
+        Operation::PutBucketWebsite => put_bucket_website(bucket, body).await.into_response(),
+        Operation::DeleteBucketWebsite => delete_bucket_website(bucket).await.into_response(),
+        Operation::GetBucketLifecycleConfiguration => get_bucket_lifecycle(bucket).await.into_response(),
+        Operation::PutBucketLifecycleConfiguration => put_bucket_lifecycle(bucket, body).await.into_response(),
+        Operation::DeleteBucketLifecycle => delete_bucket_lifecycle(bucket).await.into_response(),
+        Operation::GetObjectLockConfiguration => get_object_lock_configuration(bucket).await.into_response(),
+        Operation::PutObjectLockConfiguration => {
+            put_object_lock_configuration(bucket, body).await.into_response()
+        }
+        // recognized, just not something lumi does (yet)
+        _ => ErrorCode::NotImplemented.into_response(),
+    };
+    if operation != Operation::ListBuckets
+        && let Ok(region) = bucket_region(state, &name).await
+        && let Ok(value) = HeaderValue::from_str(&region)
+    {
+        response.headers_mut().insert(BUCKET_REGION_HEADER, value);
     }
+    response
 }
 
 pub async fn list_buckets() -> impl IntoResponse {
     let mut buckets = Vec::new();
-    let mut entries = match read_dir("./data").await {
+    let mut entries = match read_dir(storage::data_dir()).await {
         Ok(e) => e,
         Err(_) => return ErrorCode::InternalError.into_response(),
     };
     while let Ok(Some(entry)) = entries.next_entry().await {
-        if let Ok(filetype) = entry.file_type().await {
-            if filetype.is_dir() {
-                let metadata = entry.metadata().await.ok();
-                let created = metadata
-                    .and_then(|m| m.created().ok())
-                    .map(|t| DateTime::<Utc>::from(t).to_rfc3339())
-                    .unwrap_or_else(|| Utc::now().to_rfc3339());
-                buckets.push(Bucket {
-                    name: entry.file_name().to_string_lossy().to_string(),
-                    creation_date: created,
-                });
-            }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !storage::valid_bucket_name(&name) {
+            continue;
+        }
+        if let Ok(filetype) = entry.file_type().await
+            && filetype.is_dir()
+        {
+            let metadata = entry.metadata().await.ok();
+            let created = metadata
+                .and_then(|m| m.created().ok())
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(Utc::now);
+            buckets.push(Bucket {
+                name,
+                creation_date: xml::timestamp(&created),
+            });
         }
     }
-    let response = ListAllMyBucketsResult {
+    let result = ListAllMyBucketsResult {
         owner: Owner::default(),
         buckets: Buckets { bucket: buckets },
     };
-    match quick_xml::se::to_string(&response) {
-        Ok(xml) => {
-            let body = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}"#, xml);
-            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "application/xml")], body).into_response()
-        }
-        Err(_) => ErrorCode::InternalError.into_response()
-    }
+    xml::ok_response(&result, HeaderMap::new())
 }
 
-async fn list_objects() -> impl IntoResponse {
-    ErrorCode::NotImplemented.into_response()
-}
-
-pub async fn create_bucket(bucket: String) -> impl IntoResponse {
-    if bucket.len() < 3 || bucket.len() > 63
-        || !bucket.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
-        || bucket.starts_with('-') || bucket.ends_with('-')
-        || bucket.contains("..")
-    {
+pub async fn create_bucket(state: &AppState, bucket: String, headers: &HeaderMap, body: Bytes) -> impl IntoResponse {
+    let Some(path) = storage::bucket_path(&bucket) else {
         return ErrorCode::InvalidBucketName.into_response();
-    }
-        let path = format!("./data/{}", bucket);
-        if bucket.is_empty() {
-            return ErrorCode::NoSuchBucket.into_response();
-        }
+    };
         /*
             <Cthon98> hey, if you type in your pw, it will show as stars
             <Cthon98> ********* see!
@@ -104,11 +161,454 @@ pub async fn create_bucket(bucket: String) -> impl IntoResponse {
         if tokio::fs::metadata(&path).await.is_ok() {
             return ErrorCode::BucketAlreadyExists.into_response();
         }
-        match tokio::fs::create_dir_all(&path).await {
-            Ok(_) => StatusCode::OK.into_response(),
-            Err(e) => {
-                eprintln!("Failed to create bucket '{}': {}", bucket, e);
-                ErrorCode::InternalError.into_response()
+        // the body is optional, a constraint in it has to be the region we are
+        if !body.is_empty() {
+            let request: CreateBucketConfiguration = match xml::from_xml(&body) {
+                Ok(c) => c,
+                Err(code) => return code.into_response(),
+            };
+            if let Some(location) = request.location_constraint
+                && !location.is_empty()
+                && location != state.region
+            {
+                return ErrorCode::IllegalLocationConstraint.into_response();
+            }
+        }
+        let object_lock = match headers.get(OBJECT_LOCK_ENABLED_HEADER).map(|v| v.to_str()) {
+            None => false,
+            Some(Ok(v)) if v.eq_ignore_ascii_case("true") => true,
+            Some(Ok(v)) if v.eq_ignore_ascii_case("false") => false,
+            Some(_) => return ErrorCode::InvalidArgument.into_response(),
+        };
+        if let Err(e) = tokio::fs::create_dir_all(&path).await {
+            eprintln!("Failed to create bucket '{}': {}", bucket, e);
+            return ErrorCode::InternalError.into_response();
+        }
+        let update = |cfg: &mut config::BucketConfig| {
+            cfg.location = Some(state.region.clone());
+            // object lock can only be had from the start, and it needs versioning to work
+            if object_lock {
+                cfg.versioning = Some(VersioningConfiguration {
+                    status: Some(versioning::ENABLED.into()),
+                    mfa_delete: None,
+                });
+                cfg.object_lock = Some(ObjectLockConfiguration {
+                    object_lock_enabled: Some(object_lock::ENABLED.into()),
+                    rule: None,
+                });
             }
+            Ok(())
+        };
+        if let Err(code) = update_config(&bucket, update).await {
+            return code.into_response();
+        }
+        StatusCode::OK.into_response()
+}
+
+/// Where a bucket lives, buckets from before lumi kept track are in the server's region.
+async fn bucket_region(state: &AppState, bucket: &str) -> Result<String, ErrorCode> {
+    if !storage::bucket_exists(bucket).await {
+        return Err(ErrorCode::NoSuchBucket);
+    }
+    match config::load(bucket).await {
+        Ok(cfg) => Ok(cfg.location.unwrap_or_else(|| state.region.clone())),
+        Err(_) => Err(ErrorCode::InternalError),
+    }
+}
+
+/// The region header gets added on the way out like for every other bucket request.
+pub async fn head_bucket(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    StatusCode::OK.into_response()
+}
+
+pub async fn get_bucket_location(state: &AppState, bucket: String) -> impl IntoResponse {
+    let location = match bucket_region(state, &bucket).await {
+        Ok(location) => location,
+        Err(code) => return code.into_response(),
+    };
+    let region = match location.as_str() {
+        DEFAULT_REGION => String::new(),
+        _ => location,
+    };
+    xml::ok_response(&LocationConstraint { region }, HeaderMap::new())
+}
+
+async fn update_config(
+    bucket: &str,
+    update: impl FnOnce(&mut config::BucketConfig) -> Result<(), ErrorCode>,
+) -> Result<(), ErrorCode> {
+    if !storage::bucket_exists(bucket).await {
+        return Err(ErrorCode::NoSuchBucket);
+    }
+    let mut cfg = config::load(bucket).await.map_err(|_| ErrorCode::InternalError)?;
+    update(&mut cfg)?;
+    config::save(bucket, &cfg).await.map_err(|e| {
+        eprintln!("Failed to save config for bucket '{}': {}", bucket, e);
+        ErrorCode::InternalError
+    })
+}
+
+pub async fn get_bucket_encryption(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        Ok(config::BucketConfig { encryption: Some(encryption), .. }) => xml::ok_response(&encryption, HeaderMap::new()),
+        Ok(_) => ErrorCode::ServerSideEncryptionConfigurationNotFound.into_response(),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+pub async fn put_bucket_encryption(bucket: String, body: Bytes) -> impl IntoResponse {
+    let encryption: ServerSideEncryptionConfiguration = match xml::from_xml(&body) {
+        Ok(e) => e,
+        Err(code) => return code.into_response(),
+    };
+    match encryption.default_algorithm() {
+        Some(default) if default.sse_algorithm == sse::AES256 && default.kms_master_key_id.is_none() => {}
+        Some(default) if default.sse_algorithm == sse::AWS_KMS => {}
+        _ => return ErrorCode::MalformedXml.into_response(),
+    }
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.encryption = Some(encryption);
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn delete_bucket_encryption(bucket: String) -> impl IntoResponse {
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.encryption = None;
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn get_bucket_versioning(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        // never configured is an empty VersioningConfiguration, not an error
+        Ok(cfg) => xml::ok_response(&cfg.versioning.unwrap_or_default(), HeaderMap::new()),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+pub async fn put_bucket_versioning(bucket: String, body: Bytes) -> impl IntoResponse {
+    let versioning: VersioningConfiguration = match xml::from_xml(&body) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    match versioning.status.as_deref() {
+        Some(versioning::ENABLED) | Some(versioning::SUSPENDED) => {}
+        _ => return ErrorCode::MalformedXml.into_response(),
+    }
+    if versioning.mfa_delete.as_deref() == Some("Enabled") {
+        return ErrorCode::NotImplemented.into_response();
+    }
+    let status = versioning.status;
+    let update = |cfg: &mut config::BucketConfig| {
+        // object lock relies on every version being kept
+        if object_lock::is_enabled(cfg.object_lock.as_ref()) && status.as_deref() != Some(versioning::ENABLED) {
+            return Err(ErrorCode::InvalidBucketState);
+        }
+        cfg.versioning = Some(VersioningConfiguration {
+            status,
+            mfa_delete: None,
+        });
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn get_object_lock_configuration(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        Ok(config::BucketConfig { object_lock: Some(object_lock), .. }) => xml::ok_response(&object_lock, HeaderMap::new()),
+        Ok(_) => ErrorCode::ObjectLockConfigurationNotFound.into_response(),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+/// Sets the default retention of a bucket that has object lock. A versioned bucket can
+/// have lock turned on here as well, but never off again.
+pub async fn put_object_lock_configuration(bucket: String, body: Bytes) -> impl IntoResponse {
+    let object_lock: ObjectLockConfiguration = match xml::from_xml(&body) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    if object_lock.object_lock_enabled.as_deref() != Some(object_lock::ENABLED) {
+        return ErrorCode::MalformedXml.into_response();
+    }
+    if let Some(rule) = &object_lock.rule {
+        let default = &rule.default_retention;
+        if object_lock::RetentionMode::parse(&default.mode).is_none() {
+            return ErrorCode::MalformedXml.into_response();
+        }
+        match (default.days, default.years) {
+            (Some(n), None) | (None, Some(n)) if n > 0 => {}
+            _ => return ErrorCode::InvalidArgument.into_response(),
+        }
+    }
+    let update = |cfg: &mut config::BucketConfig| {
+        let versioning = cfg.versioning.as_ref().and_then(|v| v.status.as_deref());
+        if versioning != Some(versioning::ENABLED) {
+            return Err(ErrorCode::InvalidBucketState);
+        }
+        cfg.object_lock = Some(object_lock);
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn get_bucket_lifecycle(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        Ok(config::BucketConfig { lifecycle: Some(lifecycle), .. }) => xml::ok_response(&lifecycle, HeaderMap::new()),
+        Ok(_) => ErrorCode::NoSuchLifecycleConfiguration.into_response(),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+/// Replaces the whole rule set, the scanner picks it up on its next pass.
+pub async fn put_bucket_lifecycle(bucket: String, body: Bytes) -> impl IntoResponse {
+    let lifecycle: LifecycleConfiguration = match xml::from_xml(&body) {
+        Ok(l) => l,
+        Err(code) => return code.into_response(),
+    };
+    if let Err(code) = lifecycle::validate(&lifecycle) {
+        return code.into_response();
+    }
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.lifecycle = Some(lifecycle);
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn delete_bucket_lifecycle(bucket: String) -> impl IntoResponse {
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.lifecycle = None;
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn get_bucket_tagging(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        Ok(cfg) if cfg.tags.is_empty() => ErrorCode::NoSuchTagSet.into_response(),
+        Ok(cfg) => {
+            let result = Tagging {
+                tag_set: TagSet {
+                    tags: tagging::to_tag_set(&cfg.tags),
+                },
+            };
+            xml::ok_response(&result, HeaderMap::new())
         }
-}
\ No newline at end of file
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+pub async fn put_bucket_tagging(bucket: String, body: Bytes) -> impl IntoResponse {
+    let request: Tagging = match xml::from_xml(&body) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    let tags = match tagging::validate(request.tag_set.tags, tagging::MAX_BUCKET_TAGS) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.tags = tags;
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn delete_bucket_tagging(bucket: String) -> impl IntoResponse {
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.tags.clear();
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn get_bucket_cors(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        Ok(config::BucketConfig { cors: Some(cors), .. }) => xml::ok_response(&cors, HeaderMap::new()),
+        Ok(_) => ErrorCode::NoSuchCorsConfiguration.into_response(),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+/// Replaces the bucket's rules, `--allowed-origin` no longer applies to it afterwards.
+pub async fn put_bucket_cors(bucket: String, body: Bytes) -> impl IntoResponse {
+    let cors: CorsConfiguration = match xml::from_xml(&body) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    if let Err(code) = cors::validate(&cors) {
+        return code.into_response();
+    }
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.cors = Some(cors);
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn delete_bucket_cors(bucket: String) -> impl IntoResponse {
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.cors = None;
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn get_bucket_website(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    match config::load(&bucket).await {
+        Ok(config::BucketConfig { website: Some(website), .. }) => xml::ok_response(&website, HeaderMap::new()),
+        Ok(_) => ErrorCode::NoSuchWebsiteConfiguration.into_response(),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+pub async fn put_bucket_website(bucket: String, body: Bytes) -> impl IntoResponse {
+    let website: WebsiteConfiguration = match xml::from_xml(&body) {
+        Ok(w) => w,
+        Err(code) => return code.into_response(),
+    };
+    if let Err(code) = website::validate(&website) {
+        return code.into_response();
+    }
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.website = Some(website);
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+pub async fn delete_bucket_website(bucket: String) -> impl IntoResponse {
+    let update = |cfg: &mut config::BucketConfig| {
+        cfg.website = None;
+        Ok(())
+    };
+    match update_config(&bucket, update).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(code) => code.into_response(),
+    }
+}
+
+/// Buckets are private to the server's owner, there is nothing else to show.
+pub async fn get_bucket_acl(bucket: String) -> impl IntoResponse {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    xml::ok_response(&AccessControlPolicy::private(Owner::default()), HeaderMap::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn call(state: &AppState, operation: Operation, bucket: &str, body: &str) -> Response {
+        let body = Bytes::from(body.to_string());
+        handle(state, None, operation, bucket.into(), HashMap::new(), HeaderMap::new(), body).await
+    }
+
+    fn create_config(location: &str) -> String {
+        format!(
+            "<CreateBucketConfiguration xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">{}</CreateBucketConfiguration>",
+            location
+        )
+    }
+
+    #[tokio::test]
+    async fn test_location_constraint() {
+        let state = AppState::for_tests("eu-central-1");
+        let elsewhere = create_config("<LocationConstraint>us-west-2</LocationConstraint>");
+        let response = call(&state, Operation::CreateBucket, "loc-elsewhere", &elsewhere).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!storage::bucket_exists("loc-elsewhere").await);
+        let garbled = "<CreateBucketConfiguration><LocationConstraint>";
+        let response = call(&state, Operation::CreateBucket, "loc-garbled", garbled).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!storage::bucket_exists("loc-garbled").await);
+
+        let here = create_config("<LocationConstraint>eu-central-1</LocationConstraint>");
+        let response = call(&state, Operation::CreateBucket, "loc-here", &here).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[BUCKET_REGION_HEADER], "eu-central-1");
+        // an empty constraint and no body at all both mean this region
+        let response = call(&state, Operation::CreateBucket, "loc-empty", &create_config("<LocationConstraint/>")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(&state, Operation::CreateBucket, "loc-nobody", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(&state, Operation::GetBucketLocation, "loc-empty", "").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(">eu-central-1</LocationConstraint>"));
+
+        let response = call(&state, Operation::HeadBucket, "loc-nobody", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[BUCKET_REGION_HEADER], "eu-central-1");
+        let response = call(&state, Operation::HeadBucket, "loc-missing", "").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!response.headers().contains_key(BUCKET_REGION_HEADER));
+
+        for bucket in ["loc-here", "loc-empty", "loc-nobody"] {
+            tokio::fs::remove_dir_all(storage::bucket_path(bucket).unwrap()).await.unwrap();
+            tokio::fs::remove_dir_all(storage::bucket_sys_path(bucket)).await.unwrap();
+        }
+    }
+}
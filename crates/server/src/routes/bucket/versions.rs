@@ -0,0 +1,179 @@
+// GET /{bucket}?versions. keys in order, each with its versions newest first, keys that
+// share a prefix up to the delimiter folded into one CommonPrefixes entry.
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use lumi_credentials::aws_sigv4::uri_encode;
+use lumi_utils::errors::ErrorCode;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use crate::storage::{self, versioning};
+use crate::xml::{
+    self, CommonPrefix, DeleteMarkerEntry, ListVersionsResult, ObjectVersion, Owner, VersionEntry,
+};
+
+pub const MAX_KEYS: u32 = 1000;
+
+/// The CommonPrefixes entry `key` falls under, if any.
+pub(super) fn common_prefix<'a>(key: &'a str, prefix: &str, delimiter: Option<&str>) -> Option<&'a str> {
+    let delimiter = delimiter?;
+    let rest = key.strip_prefix(prefix)?;
+    rest.find(delimiter)
+        .map(|i| &key[..prefix.len() + i + delimiter.len()])
+}
+
+pub async fn list_object_versions(bucket: String, query: &HashMap<String, String>) -> Response {
+    let param = |name: &str| query.get(name).filter(|v| !v.is_empty()).cloned();
+    let prefix = param("prefix").unwrap_or_default();
+    let delimiter = param("delimiter");
+    let key_marker = param("key-marker").unwrap_or_default();
+    let version_id_marker = param("version-id-marker");
+    let max_keys = match query.get("max-keys").map(|n| (n, n.parse::<u32>())) {
+        None => MAX_KEYS,
+        Some((_, Ok(n))) => n.min(MAX_KEYS),
+        Some((n, Err(_))) => return ErrorCode::InvalidMaxKeys.argument("max-keys", n).into_response(),
+    };
+    let url_encoded = match query.get("encoding-type").map(String::as_str) {
+        None => false,
+        Some("url") => true,
+        Some(other) => return ErrorCode::InvalidArgument.argument("encoding-type", other).into_response(),
+    };
+    // a version marker only means something together with the key it belongs to
+    if version_id_marker.is_some() && key_marker.is_empty() {
+        return ErrorCode::InvalidArgument.into_response();
+    }
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    let keys = match storage::list_keys(&bucket, &prefix).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Failed to list keys of '{}': {}", bucket, e);
+            return ErrorCode::InternalError.into_response();
+        }
+    };
+
+    let mut entries = Vec::new();
+    let mut common_prefixes: Vec<CommonPrefix> = Vec::new();
+    let mut count = 0;
+    let mut is_truncated = false;
+    let (mut next_key_marker, mut next_version_id_marker) = (None, None);
+    // with a version marker the marker's key still has versions left to list
+    let start = match version_id_marker {
+        Some(_) => Bound::Included(key_marker.clone()),
+        None => Bound::Excluded(key_marker.clone()),
+    };
+    'keys: for key in keys.range::<String, _>((start, Bound::Unbounded)) {
+        if !key.starts_with(&prefix) {
+            continue;
+        }
+        if let Some(group) = common_prefix(key, &prefix, delimiter.as_deref()) {
+            // the last page may have ended on this very prefix
+            if group == key_marker || common_prefixes.last().is_some_and(|p| p.prefix == group) {
+                continue;
+            }
+            if count == max_keys {
+                is_truncated = true;
+                break;
+            }
+            common_prefixes.push(CommonPrefix {
+                prefix: group.to_string(),
+            });
+            count += 1;
+            next_key_marker = Some(group.to_string());
+            next_version_id_marker = None;
+            continue;
+        }
+        let versions = match storage::object_versions(&bucket, key).await {
+            Ok(versions) => versions,
+            Err(e) => {
+                eprintln!("Failed to list versions of '{}/{}': {}", bucket, key, e);
+                return ErrorCode::InternalError.into_response();
+            }
+        };
+        let mut skipping = *key == key_marker && version_id_marker.is_some();
+        for (i, meta) in versions.iter().enumerate() {
+            let version_id = versioning::version_name(meta);
+            if skipping {
+                skipping = Some(version_id) != version_id_marker.as_deref();
+                continue;
+            }
+            if count == max_keys {
+                is_truncated = true;
+                break 'keys;
+            }
+            let last_modified = xml::timestamp(&meta.last_modified);
+            entries.push(if meta.delete_marker {
+                VersionEntry::DeleteMarker(DeleteMarkerEntry {
+                    key: key.clone(),
+                    version_id: version_id.to_string(),
+                    is_latest: i == 0,
+                    last_modified,
+                    owner: Owner::default(),
+                })
+            } else {
+                VersionEntry::Version(ObjectVersion {
+                    key: key.clone(),
+                    version_id: version_id.to_string(),
+                    is_latest: i == 0,
+                    last_modified,
+                    etag: format!("\"{}\"", meta.etag),
+                    size: meta.size,
+                    owner: Owner::default(),
+                    storage_class: "STANDARD".into(),
+                })
+            });
+            count += 1;
+            next_key_marker = Some(key.clone());
+            next_version_id_marker = Some(version_id.to_string());
+        }
+    }
+
+    let encode = |value: String| match url_encoded {
+        true => uri_encode(value.as_bytes()),
+        false => value,
+    };
+    if url_encoded {
+        for entry in &mut entries {
+            let key = match entry {
+                VersionEntry::Version(v) => &mut v.key,
+                VersionEntry::DeleteMarker(m) => &mut m.key,
+            };
+            *key = uri_encode(key.as_bytes());
+        }
+        for group in &mut common_prefixes {
+            group.prefix = uri_encode(group.prefix.as_bytes());
+        }
+    }
+    let result = ListVersionsResult {
+        name: bucket,
+        prefix: encode(prefix),
+        key_marker: encode(key_marker),
+        version_id_marker: version_id_marker.unwrap_or_default(),
+        next_key_marker: next_key_marker.filter(|_| is_truncated).map(encode),
+        next_version_id_marker: next_version_id_marker.filter(|_| is_truncated),
+        max_keys,
+        delimiter: delimiter.map(encode),
+        encoding_type: url_encoded.then(|| "url".to_string()),
+        is_truncated,
+        entries,
+        common_prefixes,
+    };
+    xml::ok_response(&result, HeaderMap::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(common_prefix("a/b/c", "", Some("/")), Some("a/"));
+        assert_eq!(common_prefix("a/b/c", "a/", Some("/")), Some("a/b/"));
+        assert_eq!(common_prefix("a/b", "a/", Some("/")), None);
+        assert_eq!(common_prefix("a/b/c", "", None), None);
+        assert_eq!(common_prefix("logs--2024--x", "logs", Some("--")), Some("logs--"));
+    }
+}
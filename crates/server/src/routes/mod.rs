@@ -1,3 +1,4 @@
 // i wish i didnt need this shit icl :sob:
+pub mod admin;
 pub mod bucket;
 pub mod object;
\ No newline at end of file
@@ -0,0 +1,361 @@
+// server side copies. when the destination ends up encrypted the same way as the source
+// the stored bytes are copied as they are (copy_file_range on linux, never through
+// userspace), otherwise the plaintext is streamed through and sealed again.
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use futures_util::{stream::BoxStream, StreamExt};
+use lumi_credentials::aws_sigv4::percent_decode;
+use lumi_utils::errors::ErrorCode;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+use super::operations::{
+    data_key, encryption_for_put, insert_encryption_headers, open_readable, quoted_etag,
+    write_failed, NewVersion,
+};
+use super::lock::NewLock;
+use super::preconditions::{self, Outcome};
+use super::tagging;
+use crate::state::AppState;
+use crate::storage::{
+    self,
+    sse::{
+        self, CustomerKey, DataKey, NonceBase, ObjectEncryption, PartSealer, COPY_SOURCE_SSE_C_PREFIX,
+        SSE_C_PREFIX, SSE_HEADER,
+    },
+    versioning, ContentMeta, ObjectMeta,
+};
+use crate::xml::{self, CopyObjectResult};
+
+pub const COPY_SOURCE_HEADER: &str = "x-amz-copy-source";
+const COPY_SOURCE_PREFIX: &str = "x-amz-copy-source-";
+pub(super) const COPY_SOURCE_RANGE_HEADER: &str = "x-amz-copy-source-range";
+
+#[derive(Debug, PartialEq)]
+pub(super) struct CopySource {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: Option<String>,
+}
+
+/// `bucket/key` or `/bucket/key`, url encoded, optionally with `?versionId=`.
+pub(super) fn parse_copy_source(value: &str) -> Result<CopySource, ErrorCode> {
+    let (path, version_id) = match value.split_once("?versionId=") {
+        Some((path, version)) => (path, Some(version.to_string())),
+        None => (value, None),
+    };
+    let path = String::from_utf8(percent_decode(path.trim_start_matches('/')))
+        .map_err(|_| ErrorCode::InvalidCopySource)?;
+    match path.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(CopySource {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            version_id,
+        }),
+        _ => Err(ErrorCode::InvalidCopySource),
+    }
+}
+
+/// `x-amz-copy-source-range` into `start..end`. Unlike `Range` on a GET this has to be
+/// exactly `bytes=first-last` and fit inside the source.
+pub(super) fn parse_copy_range(value: &str, size: u64) -> Result<(u64, u64), ErrorCode> {
+    let (first, last) = value
+        .trim()
+        .strip_prefix("bytes=")
+        .and_then(|spec| spec.split_once('-'))
+        .ok_or(ErrorCode::InvalidArgument)?;
+    let (Ok(first), Ok(last)) = (first.parse::<u64>(), last.parse::<u64>()) else {
+        return Err(ErrorCode::InvalidArgument);
+    };
+    if first > last {
+        return Err(ErrorCode::InvalidArgument);
+    }
+    if last >= size {
+        return Err(ErrorCode::InvalidRange);
+    }
+    Ok((first, last + 1))
+}
+
+/// The source of a copy, opened and checked against the copy-source conditions and
+/// SSE-C headers.
+pub(super) struct OpenSource {
+    pub location: CopySource,
+    pub meta: ObjectMeta,
+    file: File,
+    data_key: Option<DataKey>,
+}
+
+pub(super) async fn open_source(state: &AppState, req_headers: &HeaderMap) -> Result<OpenSource, ErrorCode> {
+    let value = req_headers
+        .get(COPY_SOURCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(ErrorCode::InvalidCopySource)?;
+    let location = parse_copy_source(value)?;
+    let version_id = location.version_id.as_deref();
+    if version_id.is_some_and(|v| !versioning::is_valid_version_id(v)) {
+        return Err(ErrorCode::InvalidArgument);
+    }
+    let (meta, file) = open_readable(&location.bucket, &location.key, version_id)
+        .await
+        .map_err(|failure| failure.code())?;
+    if preconditions::evaluate(&meta, req_headers, COPY_SOURCE_PREFIX) != Outcome::Proceed {
+        return Err(ErrorCode::PreconditionFailed);
+    }
+    let customer = CustomerKey::from_headers(req_headers, COPY_SOURCE_SSE_C_PREFIX)?;
+    let data_key = data_key(state, meta.encryption.as_ref(), customer.as_ref()).await?;
+    Ok(OpenSource {
+        location,
+        meta,
+        file,
+        data_key,
+    })
+}
+
+impl OpenSource {
+    /// `x-amz-copy-source-version-id` for the response, if the source has a version.
+    pub fn insert_version_header(&self, headers: &mut HeaderMap) {
+        if self.meta.version_id.is_some() || self.location.version_id.is_some() {
+            let id = versioning::version_name(&self.meta);
+            headers.insert("x-amz-copy-source-version-id", HeaderValue::from_str(id).unwrap());
+        }
+    }
+
+    /// Plaintext bytes `start..end` of the source.
+    async fn plaintext(self, start: u64, end: u64) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+        let OpenSource {
+            meta,
+            mut file,
+            data_key,
+            ..
+        } = self;
+        match data_key {
+            Some(data_key) => {
+                Ok(sse::decrypt_range(file, data_key, meta.segments(), start, end).boxed())
+            }
+            None => {
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok(ReaderStream::new(file.take(end - start)).boxed())
+            }
+        }
+    }
+
+    /// Writes plaintext `start..end` to `out`, sealed as part `part` under `dest_key` with
+    /// the given nonce base if there is one. Returns the md5 of the plaintext.
+    pub async fn write_range(
+        self,
+        start: u64,
+        end: u64,
+        out: &Path,
+        dest_key: Option<(&DataKey, u32, &NonceBase)>,
+    ) -> io::Result<String> {
+        let mut input = self.plaintext(start, end).await?;
+        let mut output = File::create(out).await?;
+        let mut sealer =
+            dest_key.map(|(key, part, nonce_base)| PartSealer::new(key, part, nonce_base, end - start));
+        let mut md5 = md5::Context::new();
+        while let Some(chunk) = input.next().await {
+            let chunk = chunk?;
+            md5.consume(&chunk);
+            match &mut sealer {
+                Some(sealer) => output.write_all(&sealer.update(&chunk)?).await?,
+                None => output.write_all(&chunk).await?,
+            }
+        }
+        if let Some(sealer) = sealer {
+            output.write_all(&sealer.finish()?).await?;
+        }
+        output.sync_all().await?;
+        Ok(format!("{:x}", md5.finalize()))
+    }
+
+    /// Copies the stored bytes as they are.
+    async fn copy_raw(self, out: &Path) -> io::Result<()> {
+        let mut input = self.file.into_std().await;
+        let out = out.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut output = std::fs::File::create(out)?;
+            // std picks copy_file_range / sendfile for file to file copies
+            io::copy(&mut input, &mut output)?;
+            output.sync_all()
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+/// The stored bytes can be reused when the destination would be encrypted the same way.
+fn reusable(source: Option<&ObjectEncryption>, dest: Option<&ObjectEncryption>) -> bool {
+    match (source, dest) {
+        (None, None) => true,
+        (Some(ObjectEncryption::SseS3 { .. }), Some(ObjectEncryption::SseS3 { .. })) => true,
+        (Some(ObjectEncryption::Kms { key_id: a, .. }), Some(ObjectEncryption::Kms { key_id: b, .. })) => a == b,
+        _ => false,
+    }
+}
+
+enum MetadataDirective {
+    Copy,
+    Replace,
+}
+
+pub async fn copy_object(state: &AppState, bucket: String, key: String, req_headers: HeaderMap) -> Response {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    if storage::object_path(&bucket, &key).is_none() {
+        return ErrorCode::NoSuchKey.into_response();
+    }
+    let directive = match req_headers.get("x-amz-metadata-directive").map(|v| v.as_bytes()) {
+        None | Some(b"COPY") => MetadataDirective::Copy,
+        Some(b"REPLACE") => MetadataDirective::Replace,
+        Some(_) => return ErrorCode::InvalidArgument.into_response(),
+    };
+    let condition = match preconditions::write_condition(&req_headers) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let customer = match CustomerKey::from_headers(&req_headers, SSE_C_PREFIX) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let source = match open_source(state, &req_headers).await {
+        Ok(s) => s,
+        Err(code) => return code.into_response(),
+    };
+    let changes_encryption = req_headers.contains_key(SSE_HEADER) || customer.is_some();
+    if source.location.bucket == bucket
+        && source.location.key == key
+        && matches!(directive, MetadataDirective::Copy)
+        && !changes_encryption
+    {
+        return ErrorCode::InvalidCopyDest.into_response();
+    }
+    let content = match directive {
+        MetadataDirective::Copy => source.meta.content.clone(),
+        MetadataDirective::Replace => match ContentMeta::from_headers(&req_headers) {
+            Ok(c) => c,
+            Err(code) => return code.into_response(),
+        },
+    };
+    let tags = match tagging::tags_for_copy(&req_headers, &source.meta) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    let dest = match encryption_for_put(state, &bucket, &req_headers).await {
+        Ok(d) => d,
+        Err(code) => return code.into_response(),
+    };
+    let version = match NewVersion::for_bucket(&bucket).await {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    // lock settings come from the copy request, never from the source
+    let lock = match NewLock::for_put(&bucket, &req_headers).await {
+        Ok(l) => l,
+        Err(code) => return code.into_response(),
+    };
+
+    let tmp = storage::tmp_path();
+    if let Err(e) = tokio::fs::create_dir_all(tmp.parent().unwrap()).await {
+        return write_failed(e.into(), &bucket, &key);
+    }
+    let source_meta = source.meta.clone();
+    let mut headers = HeaderMap::new();
+    source.insert_version_header(&mut headers);
+    let fast = !source_meta.etag.is_empty()
+        && reusable(source_meta.encryption.as_ref(), dest.as_ref().map(|(_, e)| e));
+    let meta = if fast {
+        if let Err(e) = source.copy_raw(&tmp).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return write_failed(e.into(), &bucket, &key);
+        }
+        ObjectMeta {
+            key,
+            size: source_meta.size,
+            etag: source_meta.etag,
+            last_modified: Utc::now(),
+            version_id: version.id.clone(),
+            delete_marker: false,
+            data: None,
+            encryption: source_meta.encryption,
+            parts: source_meta.parts,
+            retention: lock.retention,
+            legal_hold: lock.legal_hold,
+            tags: tags.clone(),
+            content,
+        }
+    } else {
+        let dest_key = dest.as_ref().map(|(data_key, _)| (data_key, 0, &sse::OBJECT_NONCE_BASE));
+        let etag = match source.write_range(0, source_meta.size, &tmp, dest_key).await {
+            Ok(etag) => etag,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                return write_failed(e.into(), &bucket, &key);
+            }
+        };
+        ObjectMeta {
+            key,
+            size: source_meta.size,
+            etag,
+            last_modified: Utc::now(),
+            version_id: version.id.clone(),
+            delete_marker: false,
+            data: None,
+            encryption: dest.map(|(_, encryption)| encryption),
+            parts: Vec::new(),
+            retention: lock.retention,
+            legal_hold: lock.legal_hold,
+            tags,
+            content,
+        }
+    };
+    if let Err(e) = storage::commit_object(&bucket, &meta, &tmp, &condition).await {
+        return write_failed(e, &bucket, &meta.key);
+    }
+
+    insert_encryption_headers(&mut headers, meta.encryption.as_ref(), customer.as_ref());
+    version.insert_header(&mut headers);
+    let result = CopyObjectResult {
+        etag: quoted_etag(&meta.etag),
+        last_modified: xml::timestamp(&meta.last_modified),
+    };
+    xml::ok_response(&result, headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_copy_source() {
+        assert_eq!(
+            parse_copy_source("/bkt/dir/my%20file%2B1.txt?versionId=abc"),
+            Ok(CopySource {
+                bucket: "bkt".into(),
+                key: "dir/my file+1.txt".into(),
+                version_id: Some("abc".into()),
+            })
+        );
+        assert_eq!(parse_copy_source("bkt/k").unwrap().version_id, None);
+        assert_eq!(parse_copy_source("bkt"), Err(ErrorCode::InvalidCopySource));
+        assert_eq!(parse_copy_source("/bkt/"), Err(ErrorCode::InvalidCopySource));
+    }
+
+    #[test]
+    fn test_parse_copy_range() {
+        assert_eq!(parse_copy_range("bytes=0-9", 10), Ok((0, 10)));
+        assert_eq!(parse_copy_range("bytes=5-5", 10), Ok((5, 6)));
+        assert_eq!(parse_copy_range("bytes=0-10", 10), Err(ErrorCode::InvalidRange));
+        assert_eq!(parse_copy_range("bytes=5-", 10), Err(ErrorCode::InvalidArgument));
+        assert_eq!(parse_copy_range("bytes=-5", 10), Err(ErrorCode::InvalidArgument));
+        assert_eq!(parse_copy_range("bytes=6-5", 10), Err(ErrorCode::InvalidArgument));
+        assert_eq!(parse_copy_range("0-5", 10), Err(ErrorCode::InvalidArgument));
+    }
+}
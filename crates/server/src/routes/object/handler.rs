@@ -1,15 +1,20 @@
 use axum::{
     body::Bytes,
-    extract::{Path, Query},
-    http::{HeaderMap, Method},
+    extract::{Extension, Path, Query, State},
+    http::HeaderMap,
     response::{IntoResponse, Response},
 };
 use lumi_utils::errors::ErrorCode;
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 use super::operations;
+use crate::middleware::Identity;
+use crate::operation::Operation;
+use crate::state::AppState;
 
 pub async fn handler(
-    method: Method,
+    State(state): State<Arc<AppState>>,
+    identity: Option<Extension<Identity>>,
+    Extension(operation): Extension<Operation>,
     Path(params): Path<HashMap<String, String>>,
     Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
@@ -23,5 +28,6 @@ pub async fn handler(
         Some(k) => k.clone(),
         None => return ErrorCode::InvalidRequest.into_response(),
     };
-    operations::handle(method, bucket, key, headers, body).await.into_response()
+    let identity = identity.map(|Extension(identity)| identity);
+    operations::handle(&state, identity.as_ref(), operation, bucket, key, query, headers, body).await.into_response()
 }
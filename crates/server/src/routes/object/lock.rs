@@ -0,0 +1,267 @@
+// object lock on the object side: the retention / legal hold a new object starts with,
+// and the ?retention and ?legal-hold subresources to change them later on.
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lumi_utils::errors::ErrorCode;
+use std::collections::HashMap;
+
+use super::operations::{open_readable, version_query, write_failed};
+use crate::middleware::Identity;
+use crate::state::AppState;
+use crate::storage::{
+    self, config,
+    object_lock::{
+        self, Retention, RetentionMode, BYPASS_GOVERNANCE_HEADER, LEGAL_HOLD_HEADER, MODE_HEADER,
+        RETAIN_UNTIL_HEADER,
+    },
+    ObjectMeta,
+};
+use crate::xml::{self, LegalHold, ObjectRetention};
+
+const ON: &str = "ON";
+const OFF: &str = "OFF";
+
+/// Asking for a bypass only counts when the caller is allowed to.
+pub fn bypass_governance(state: &AppState, identity: Option<&Identity>, headers: &HeaderMap) -> bool {
+    let requested = headers
+        .get(BYPASS_GOVERNANCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"));
+    requested && identity.is_some_and(|i| state.can_bypass_governance(&i.access_key))
+}
+
+/// Retain-until dates have to be in the future, in the past there is nothing to lock.
+fn parse_retention(mode: &str, retain_until: &str) -> Result<Retention, ErrorCode> {
+    let mode = RetentionMode::parse(mode).ok_or(ErrorCode::InvalidArgument)?;
+    let retain_until = DateTime::parse_from_rfc3339(retain_until)
+        .map_err(|_| ErrorCode::InvalidArgument)?
+        .with_timezone(&Utc);
+    if retain_until <= Utc::now() {
+        return Err(ErrorCode::InvalidArgument);
+    }
+    Ok(Retention { mode, retain_until })
+}
+
+fn format_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+async fn lock_enabled(bucket: &str) -> Result<bool, ErrorCode> {
+    let cfg = config::load(bucket).await.map_err(|_| ErrorCode::InternalError)?;
+    Ok(object_lock::is_enabled(cfg.object_lock.as_ref()))
+}
+
+/// The retention and legal hold a new object starts out with, from the request headers
+/// or else the bucket's default retention.
+#[derive(Debug, Default)]
+pub(super) struct NewLock {
+    pub retention: Option<Retention>,
+    pub legal_hold: bool,
+}
+
+impl NewLock {
+    pub async fn for_put(bucket: &str, headers: &HeaderMap) -> Result<Self, ErrorCode> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| v.to_str().map_err(|_| ErrorCode::InvalidArgument))
+                .transpose()
+        };
+        let (mode, retain_until) = (header(MODE_HEADER)?, header(RETAIN_UNTIL_HEADER)?);
+        let legal_hold = header(LEGAL_HOLD_HEADER)?;
+        let cfg = config::load(bucket).await.map_err(|_| ErrorCode::InternalError)?;
+        if !object_lock::is_enabled(cfg.object_lock.as_ref()) {
+            if mode.is_some() || retain_until.is_some() || legal_hold.is_some() {
+                return Err(ErrorCode::MissingObjectLockConfiguration);
+            }
+            return Ok(NewLock::default());
+        }
+        let retention = match (mode, retain_until) {
+            (Some(mode), Some(retain_until)) => Some(parse_retention(mode, retain_until)?),
+            (None, None) => cfg.object_lock.as_ref().and_then(object_lock::default_retention),
+            // one without the other
+            _ => return Err(ErrorCode::InvalidArgument),
+        };
+        let legal_hold = match legal_hold {
+            None | Some(OFF) => false,
+            Some(ON) => true,
+            Some(_) => return Err(ErrorCode::InvalidArgument),
+        };
+        Ok(NewLock { retention, legal_hold })
+    }
+}
+
+/// Lock state of an object for GET/HEAD.
+pub(super) fn insert_headers(headers: &mut HeaderMap, meta: &ObjectMeta) {
+    if let Some(retention) = &meta.retention {
+        headers.insert(MODE_HEADER, HeaderValue::from_static(retention.mode.as_str()));
+        let retain_until = format_date(&retention.retain_until);
+        headers.insert(RETAIN_UNTIL_HEADER, HeaderValue::from_str(&retain_until).unwrap());
+    }
+    if meta.legal_hold {
+        headers.insert(LEGAL_HOLD_HEADER, HeaderValue::from_static(ON));
+    }
+}
+
+/// The version a ?retention / ?legal-hold request is about, in a bucket that has lock.
+async fn locked_version(
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<ObjectMeta, Response> {
+    if !storage::bucket_exists(bucket).await {
+        return Err(ErrorCode::NoSuchBucket.into_response());
+    }
+    match lock_enabled(bucket).await {
+        Ok(true) => {}
+        Ok(false) => return Err(ErrorCode::MissingObjectLockConfiguration.into_response()),
+        Err(code) => return Err(code.into_response()),
+    }
+    open_readable(bucket, key, version_id)
+        .await
+        .map(|(meta, _)| meta)
+        .map_err(IntoResponse::into_response)
+}
+
+/// Applies `update` to the version under its lock, a missing version is the same 404 a
+/// read would get.
+async fn update_locked_version<F>(
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+    update: F,
+) -> Response
+where
+    F: FnOnce(&mut ObjectMeta) -> Result<(), ErrorCode>,
+{
+    if let Err(response) = locked_version(bucket, key, version_id).await {
+        return response;
+    }
+    let update = |meta: &mut ObjectMeta| match meta.delete_marker {
+        true => Err(ErrorCode::MethodNotAllowed),
+        false => update(meta),
+    };
+    match storage::update_version(bucket, key, version_id, update).await {
+        Ok(Some(_)) => StatusCode::OK.into_response(),
+        Ok(None) if version_id.is_some() => ErrorCode::NoSuchVersion.into_response(),
+        Ok(None) => ErrorCode::NoSuchKey.into_response(),
+        Err(e) => write_failed(e, bucket, key),
+    }
+}
+
+pub async fn get_object_retention(bucket: String, key: String, query: &HashMap<String, String>) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let meta = match locked_version(&bucket, &key, version_id).await {
+        Ok(meta) => meta,
+        Err(response) => return response,
+    };
+    match meta.retention {
+        Some(retention) => {
+            let result = ObjectRetention {
+                mode: Some(retention.mode.as_str().to_string()),
+                retain_until_date: Some(format_date(&retention.retain_until)),
+            };
+            xml::ok_response(&result, HeaderMap::new())
+        }
+        None => ErrorCode::NoSuchObjectLockConfiguration.into_response(),
+    }
+}
+
+pub async fn put_object_retention(
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    bypass_governance: bool,
+    body: Bytes,
+) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let request: ObjectRetention = match xml::from_xml(&body) {
+        Ok(r) => r,
+        Err(code) => return code.into_response(),
+    };
+    let retention = match (request.mode.as_deref(), request.retain_until_date.as_deref()) {
+        (Some(mode), Some(retain_until)) => match parse_retention(mode, retain_until) {
+            Ok(r) => Some(r),
+            Err(code) => return code.into_response(),
+        },
+        (None, None) => None,
+        _ => return ErrorCode::MalformedXml.into_response(),
+    };
+    update_locked_version(&bucket, &key, version_id, |meta| {
+        object_lock::check_retention_change(meta.retention.as_ref(), retention.as_ref(), bypass_governance)?;
+        meta.retention = retention;
+        Ok(())
+    })
+    .await
+}
+
+pub async fn get_object_legal_hold(bucket: String, key: String, query: &HashMap<String, String>) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    match locked_version(&bucket, &key, version_id).await {
+        Ok(meta) => {
+            let result = LegalHold {
+                status: if meta.legal_hold { ON } else { OFF }.to_string(),
+            };
+            xml::ok_response(&result, HeaderMap::new())
+        }
+        Err(response) => response,
+    }
+}
+
+pub async fn put_object_legal_hold(
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    body: Bytes,
+) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let request: LegalHold = match xml::from_xml(&body) {
+        Ok(r) => r,
+        Err(code) => return code.into_response(),
+    };
+    let legal_hold = match request.status.as_str() {
+        ON => true,
+        OFF => false,
+        _ => return ErrorCode::MalformedXml.into_response(),
+    };
+    update_locked_version(&bucket, &key, version_id, |meta| {
+        meta.legal_hold = legal_hold;
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retention() {
+        let future = format_date(&(Utc::now() + chrono::Duration::days(1)));
+        let retention = parse_retention("COMPLIANCE", &future).unwrap();
+        assert_eq!(retention.mode, RetentionMode::Compliance);
+        assert_eq!(format_date(&retention.retain_until), future);
+        assert_eq!(parse_retention("compliance", &future), Err(ErrorCode::InvalidArgument));
+        assert_eq!(parse_retention("GOVERNANCE", "tomorrow"), Err(ErrorCode::InvalidArgument));
+        assert_eq!(
+            parse_retention("GOVERNANCE", "2020-01-01T00:00:00Z"),
+            Err(ErrorCode::InvalidArgument)
+        );
+    }
+}
@@ -1,3 +1,8 @@
+pub mod copy;
 pub mod handler;
+pub mod lock;
+pub mod multipart;
 pub mod operations;
+pub mod preconditions;
+pub mod tagging;
 pub use handler::handler;
\ No newline at end of file
@@ -0,0 +1,397 @@
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use lumi_utils::errors::ErrorCode;
+use std::collections::HashMap;
+
+use super::copy::{self, COPY_SOURCE_RANGE_HEADER};
+use super::lock::NewLock;
+use super::operations::{
+    data_key, encryption_for_put, insert_encryption_headers, quoted_etag, write_failed,
+    NewVersion,
+};
+use super::preconditions;
+use super::tagging;
+use crate::state::AppState;
+use crate::storage::{
+    self,
+    multipart::{self as uploads, Upload, MAX_PART_NUMBER, MAX_PART_SIZE, MIN_PART_SIZE},
+    sse::{self, CustomerKey, SSE_C_PREFIX},
+    ContentMeta, ObjectMeta, PartMeta,
+};
+use crate::xml::{
+    self, CompleteMultipartUpload, CompleteMultipartUploadResult, CopyPartResult,
+    InitiateMultipartUploadResult, ListPartsResult, Part,
+};
+
+/// The upload has to exist and belong to the key in the path.
+async fn load_upload(bucket: &str, key: &str, upload_id: &str) -> Result<Upload, ErrorCode> {
+    match uploads::load(bucket, upload_id).await {
+        Ok(Some(upload)) if upload.key == key => Ok(upload),
+        Ok(_) => Err(ErrorCode::NoSuchUpload),
+        Err(e) => {
+            eprintln!("Failed to read upload '{}' in '{}': {}", upload_id, bucket, e);
+            Err(ErrorCode::InternalError)
+        }
+    }
+}
+
+pub async fn create_multipart_upload(
+    state: &AppState,
+    bucket: String,
+    key: String,
+    req_headers: HeaderMap,
+) -> Response {
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    if storage::object_path(&bucket, &key).is_none() {
+        return ErrorCode::NoSuchKey.into_response();
+    }
+    let customer = match CustomerKey::from_headers(&req_headers, SSE_C_PREFIX) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let content = match ContentMeta::from_headers(&req_headers) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let encryption = match encryption_for_put(state, &bucket, &req_headers).await {
+        Ok(e) => e.map(|(_, encryption)| encryption),
+        Err(code) => return code.into_response(),
+    };
+    let lock = match NewLock::for_put(&bucket, &req_headers).await {
+        Ok(l) => l,
+        Err(code) => return code.into_response(),
+    };
+    let tags = match tagging::tags_for_put(&req_headers) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    let upload = Upload {
+        key,
+        initiated: Utc::now(),
+        encryption,
+        retention: lock.retention,
+        legal_hold: lock.legal_hold,
+        tags,
+        content,
+    };
+    let upload_id = match uploads::create(&bucket, &upload).await {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Failed to create upload for '{}/{}': {}", bucket, upload.key, e);
+            return ErrorCode::InternalError.into_response();
+        }
+    };
+    let mut headers = HeaderMap::new();
+    insert_encryption_headers(&mut headers, upload.encryption.as_ref(), customer.as_ref());
+    xml::ok_response(
+        &InitiateMultipartUploadResult {
+            bucket,
+            key: upload.key,
+            upload_id,
+        },
+        headers,
+    )
+}
+
+pub async fn upload_part(
+    state: &AppState,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    query: &HashMap<String, String>,
+    req_headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let number = match query.get("partNumber").map(|n| (n, n.parse::<u32>())) {
+        Some((_, Ok(n))) if (1..=MAX_PART_NUMBER).contains(&n) => n,
+        Some((n, _)) => return ErrorCode::InvalidArgument.argument("partNumber", n).into_response(),
+        None => return ErrorCode::InvalidArgument.into_response(),
+    };
+    // held until the part is in place, see `uploads::lock`
+    let _guard = uploads::lock(&bucket, &upload_id).await;
+    let upload = match load_upload(&bucket, &key, &upload_id).await {
+        Ok(u) => u,
+        Err(code) => return code.into_response(),
+    };
+    // SSE-C uploads need the same key on every part
+    let customer = match CustomerKey::from_headers(&req_headers, SSE_C_PREFIX) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    // every part shares the upload's data key, a fresh nonce base per write keeps a
+    // re-uploaded part number from reusing nonces
+    let nonce_base = sse::new_nonce_base();
+    let data_key = data_key(state, upload.encryption.as_ref(), customer.as_ref()).await;
+    let (payload, nonce_base) = match data_key {
+        Ok(Some(data_key)) => match sse::encrypt_part(&data_key, number, &nonce_base, &body) {
+            Ok(sealed) => (Bytes::from(sealed), Some(hex::encode(nonce_base))),
+            Err(_) => return ErrorCode::InternalError.into_response(),
+        },
+        Ok(None) => (body.clone(), None),
+        Err(code) => return code.into_response(),
+    };
+    let part = PartMeta {
+        number,
+        size: body.len() as u64,
+        etag: format!("{:x}", md5::compute(&body)),
+        last_modified: Utc::now(),
+        nonce_base,
+        file: None,
+    };
+    if let Err(e) = uploads::write_part(&bucket, &upload_id, &part, &payload).await {
+        eprintln!("Failed to write part {} of upload '{}': {}", number, upload_id, e);
+        return ErrorCode::InternalError.into_response();
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert("etag", HeaderValue::from_str(&quoted_etag(&part.etag)).unwrap());
+    insert_encryption_headers(&mut headers, upload.encryption.as_ref(), customer.as_ref());
+    (StatusCode::OK, headers).into_response()
+}
+
+/// UploadPart with `x-amz-copy-source`, the part comes from (a range of) another object.
+pub async fn upload_part_copy(
+    state: &AppState,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    query: &HashMap<String, String>,
+    req_headers: HeaderMap,
+) -> Response {
+    let number = match query.get("partNumber").map(|n| (n, n.parse::<u32>())) {
+        Some((_, Ok(n))) if (1..=MAX_PART_NUMBER).contains(&n) => n,
+        Some((n, _)) => return ErrorCode::InvalidArgument.argument("partNumber", n).into_response(),
+        None => return ErrorCode::InvalidArgument.into_response(),
+    };
+    let _guard = uploads::lock(&bucket, &upload_id).await;
+    let upload = match load_upload(&bucket, &key, &upload_id).await {
+        Ok(u) => u,
+        Err(code) => return code.into_response(),
+    };
+    let customer = match CustomerKey::from_headers(&req_headers, SSE_C_PREFIX) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let dest_key = match data_key(state, upload.encryption.as_ref(), customer.as_ref()).await {
+        Ok(k) => k,
+        Err(code) => return code.into_response(),
+    };
+    let source = match copy::open_source(state, &req_headers).await {
+        Ok(s) => s,
+        Err(code) => return code.into_response(),
+    };
+    let range = match req_headers.get(COPY_SOURCE_RANGE_HEADER).map(|v| v.to_str()) {
+        None => Ok((0, source.meta.size)),
+        Some(Ok(value)) => copy::parse_copy_range(value, source.meta.size),
+        Some(Err(_)) => Err(ErrorCode::InvalidArgument),
+    };
+    let (start, end) = match range {
+        Ok(r) => r,
+        Err(code) => return code.into_response(),
+    };
+    if end - start > MAX_PART_SIZE {
+        return ErrorCode::EntityTooLarge.into_response();
+    }
+
+    let tmp = storage::tmp_path();
+    if let Err(e) = tokio::fs::create_dir_all(tmp.parent().unwrap()).await {
+        return write_failed(e.into(), &bucket, &key);
+    }
+    // parts are always sealed on their own, so the source is re-encrypted even when it
+    // uses the same key
+    let mut headers = HeaderMap::new();
+    source.insert_version_header(&mut headers);
+    let nonce_base = sse::new_nonce_base();
+    let sealed_as = dest_key.as_ref().map(|k| (k, number, &nonce_base));
+    let etag = match source.write_range(start, end, &tmp, sealed_as).await {
+        Ok(etag) => etag,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return write_failed(e.into(), &bucket, &key);
+        }
+    };
+    let part = PartMeta {
+        number,
+        size: end - start,
+        etag,
+        last_modified: Utc::now(),
+        nonce_base: dest_key.is_some().then(|| hex::encode(nonce_base)),
+        file: None,
+    };
+    if let Err(e) = uploads::commit_part(&bucket, &upload_id, &part, &tmp).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        eprintln!("Failed to write part {} of upload '{}': {}", number, upload_id, e);
+        return ErrorCode::InternalError.into_response();
+    }
+    insert_encryption_headers(&mut headers, upload.encryption.as_ref(), customer.as_ref());
+    xml::ok_response(
+        &CopyPartResult {
+            etag: quoted_etag(&part.etag),
+            last_modified: xml::timestamp(&part.last_modified),
+        },
+        headers,
+    )
+}
+
+/// S3 style multipart etag, md5 of the binary part md5s with the part count tacked on.
+fn multipart_etag(parts: &[PartMeta]) -> String {
+    let mut digests = Vec::with_capacity(parts.len() * 16);
+    for part in parts {
+        digests.extend(hex::decode(&part.etag).unwrap_or_default());
+    }
+    format!("{:x}-{}", md5::compute(&digests), parts.len())
+}
+
+pub async fn complete_multipart_upload(
+    bucket: String,
+    key: String,
+    upload_id: String,
+    req_headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let _guard = uploads::lock(&bucket, &upload_id).await;
+    let upload = match load_upload(&bucket, &key, &upload_id).await {
+        Ok(u) => u,
+        Err(code) => return code.into_response(),
+    };
+    let condition = match preconditions::write_condition(&req_headers) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let request: CompleteMultipartUpload = match xml::from_xml(&body) {
+        Ok(r) => r,
+        Err(code) => return code.into_response(),
+    };
+    if request.parts.is_empty() {
+        return ErrorCode::MalformedXml.into_response();
+    }
+    let uploaded: HashMap<u32, PartMeta> = match uploads::list_parts(&bucket, &upload_id).await {
+        Ok(parts) => parts.into_iter().map(|p| (p.number, p)).collect(),
+        Err(e) => {
+            eprintln!("Failed to list parts of upload '{}': {}", upload_id, e);
+            return ErrorCode::InternalError.into_response();
+        }
+    };
+
+    let mut parts: Vec<PartMeta> = Vec::with_capacity(request.parts.len());
+    for requested in &request.parts {
+        if parts.last().is_some_and(|p| p.number >= requested.part_number) {
+            return ErrorCode::InvalidPartOrder.into_response();
+        }
+        match uploaded.get(&requested.part_number) {
+            Some(part) if part.etag == requested.etag.trim_matches('"') => parts.push(part.clone()),
+            _ => return ErrorCode::InvalidPart.into_response(),
+        }
+    }
+    if parts[..parts.len() - 1].iter().any(|p| p.size < MIN_PART_SIZE) {
+        return ErrorCode::EntityTooSmall.into_response();
+    }
+
+    let version = match NewVersion::for_bucket(&bucket).await {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let meta = ObjectMeta {
+        key,
+        size: parts.iter().map(|p| p.size).sum(),
+        etag: multipart_etag(&parts),
+        last_modified: Utc::now(),
+        version_id: version.id.clone(),
+        delete_marker: false,
+        data: None,
+        encryption: upload.encryption,
+        parts,
+        retention: upload.retention,
+        legal_hold: upload.legal_hold,
+        tags: upload.tags,
+        content: upload.content,
+    };
+    if let Err(e) = uploads::complete(&bucket, &upload_id, &meta, &condition).await {
+        return write_failed(e, &bucket, &meta.key);
+    }
+    let mut headers = HeaderMap::new();
+    insert_encryption_headers(&mut headers, meta.encryption.as_ref(), None);
+    version.insert_header(&mut headers);
+    xml::ok_response(
+        &CompleteMultipartUploadResult {
+            location: format!("/{}/{}", bucket, meta.key),
+            bucket,
+            etag: quoted_etag(&meta.etag),
+            key: meta.key,
+        },
+        headers,
+    )
+}
+
+pub async fn abort_multipart_upload(bucket: String, key: String, upload_id: String) -> Response {
+    let _guard = uploads::lock(&bucket, &upload_id).await;
+    if let Err(code) = load_upload(&bucket, &key, &upload_id).await {
+        return code.into_response();
+    }
+    match uploads::abort(&bucket, &upload_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            eprintln!("Failed to abort upload '{}': {}", upload_id, e);
+            ErrorCode::InternalError.into_response()
+        }
+    }
+}
+
+pub async fn list_parts(
+    bucket: String,
+    key: String,
+    upload_id: String,
+    query: &HashMap<String, String>,
+) -> Response {
+    let max_parts = match query.get("max-parts").map(|n| (n, n.parse::<u32>())) {
+        None => 1000,
+        Some((_, Ok(n))) => n.min(1000),
+        Some((n, Err(_))) => return ErrorCode::InvalidMaxParts.argument("max-parts", n).into_response(),
+    };
+    let marker = match query.get("part-number-marker").map(|n| (n, n.parse::<u32>())) {
+        None => 0,
+        Some((_, Ok(n))) => n,
+        Some((n, Err(_))) => {
+            return ErrorCode::InvalidPartNumberMarker.argument("part-number-marker", n).into_response();
+        }
+    };
+    if let Err(code) = load_upload(&bucket, &key, &upload_id).await {
+        return code.into_response();
+    }
+    let mut parts = match uploads::list_parts(&bucket, &upload_id).await {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!("Failed to list parts of upload '{}': {}", upload_id, e);
+            return ErrorCode::InternalError.into_response();
+        }
+    };
+    parts.retain(|p| p.number > marker);
+    let is_truncated = parts.len() > max_parts as usize;
+    parts.truncate(max_parts as usize);
+    xml::ok_response(
+        &ListPartsResult {
+            bucket,
+            key,
+            upload_id,
+            part_number_marker: marker,
+            next_part_number_marker: parts.last().map(|p| p.number).unwrap_or(marker),
+            max_parts,
+            is_truncated,
+            parts: parts
+                .into_iter()
+                .map(|p| Part {
+                    part_number: p.number,
+                    last_modified: xml::timestamp(&p.last_modified),
+                    etag: quoted_etag(&p.etag),
+                    size: p.size,
+                })
+                .collect(),
+        },
+        HeaderMap::new(),
+    )
+}
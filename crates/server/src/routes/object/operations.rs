@@ -1,94 +1,700 @@
 use axum::{
     body::{Body, Bytes},
-    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
 use lumi_utils::errors::ErrorCode;
-use std::path::{Component, Path, PathBuf};
-use tokio::fs::{self, File};
-use tokio::io::AsyncWriteExt;
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
+
+use super::copy;
+use super::lock::{self, NewLock};
+use super::multipart;
+use super::preconditions::{self, Outcome};
+use super::tagging;
+use crate::kms;
+use crate::middleware::Identity;
+use crate::operation::Operation;
+use crate::state::AppState;
+use crate::storage::{
+    self, config,
+    sse::{self, CustomerKey, DataKey, ObjectEncryption, KMS_KEY_ID_HEADER, SSE_C_PREFIX, SSE_HEADER},
+    tagging::TAGGING_COUNT_HEADER,
+    versioning::{self, Versioning},
+    ContentMeta, ObjectMeta, WriteCondition, WriteError,
+};
+use crate::xml::{self, AccessControlPolicy, Owner};
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle(
-    method: Method,
+    state: &AppState,
+    identity: Option<&Identity>,
+    operation: Operation,
     bucket: String,
     key: String,
+    query: HashMap<String, String>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Response {
-    match method {
-        Method::GET => get_object(bucket, key, headers).await.into_response(),
-        Method::PUT => put_object(bucket, key, headers, body).await.into_response(),
-        _ => ErrorCode::MethodNotAllowed.into_response(),
+    let bypass_governance = lock::bypass_governance(state, identity, &headers);
+    let upload_id = query.get("uploadId").cloned().unwrap_or_default();
+    match operation {
+        Operation::GetObject => get_object(state, bucket, key, &query, headers).await.into_response(),
+        Operation::HeadObject => head_object(state, bucket, key, &query, headers).await.into_response(),
+        Operation::PutObject => put_object(state, bucket, key, headers, body).await.into_response(),
+        Operation::CopyObject => copy::copy_object(state, bucket, key, headers).await,
+        Operation::DeleteObject => {
+            delete_object(bucket, key, &query, bypass_governance).await.into_response()
+        }
+        Operation::GetObjectRetention => lock::get_object_retention(bucket, key, &query).await,
+        Operation::PutObjectRetention => {
+            lock::put_object_retention(bucket, key, &query, bypass_governance, body).await
+        }
+        Operation::GetObjectLegalHold => lock::get_object_legal_hold(bucket, key, &query).await,
+        Operation::PutObjectLegalHold => lock::put_object_legal_hold(bucket, key, &query, body).await,
+        Operation::GetObjectTagging => tagging::get_object_tagging(bucket, key, &query).await,
+        Operation::PutObjectTagging => tagging::put_object_tagging(bucket, key, &query, body).await,
+        Operation::DeleteObjectTagging => tagging::delete_object_tagging(bucket, key, &query).await,
+        Operation::GetObjectAcl => get_object_acl(bucket, key, &query).await,
+        Operation::CreateMultipartUpload => {
+            multipart::create_multipart_upload(state, bucket, key, headers)
+                .await
+                .into_response()
+        }
+        Operation::UploadPart => {
+            multipart::upload_part(state, bucket, key, upload_id, &query, headers, body)
+                .await
+                .into_response()
+        }
+        Operation::UploadPartCopy => {
+            multipart::upload_part_copy(state, bucket, key, upload_id, &query, headers)
+                .await
+                .into_response()
+        }
+        Operation::CompleteMultipartUpload => {
+            multipart::complete_multipart_upload(bucket, key, upload_id, headers, body)
+                .await
+                .into_response()
+        }
+        Operation::AbortMultipartUpload => {
+            multipart::abort_multipart_upload(bucket, key, upload_id).await.into_response()
+        }
+        Operation::ListParts => multipart::list_parts(bucket, key, upload_id, &query).await.into_response(),
+        // recognized, just not something lumi does (yet)
+        _ => ErrorCode::NotImplemented.into_response(),
+    }
+}
+
+/// Picks the encryption for a new object from the request headers, falling back to the
+/// bucket default. Returns the fresh data key along with how it is stored.
+pub(super) async fn encryption_for_put(
+    state: &AppState,
+    bucket: &str,
+    headers: &HeaderMap,
+) -> Result<Option<(DataKey, ObjectEncryption)>, ErrorCode> {
+    if let Some(customer) = CustomerKey::from_headers(headers, SSE_C_PREFIX)? {
+        // one or the other, not both
+        if headers.contains_key(SSE_HEADER) {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        let data_key = sse::new_data_key();
+        let encryption = customer.seal(&data_key).map_err(|_| ErrorCode::InternalError)?;
+        return Ok(Some((data_key, encryption)));
+    }
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|v| v.to_str().map(str::to_string).map_err(|_| ErrorCode::InvalidArgument))
+            .transpose()
+    };
+    let (algorithm, kms_key_id) = match header(SSE_HEADER)? {
+        Some(algorithm) => (Some(algorithm), header(KMS_KEY_ID_HEADER)?),
+        None => match config::load(bucket)
+            .await
+            .map_err(|_| ErrorCode::InternalError)?
+            .encryption
+            .and_then(|e| e.default_algorithm().cloned())
+        {
+            Some(default) => (Some(default.sse_algorithm), default.kms_master_key_id),
+            None => (None, None),
+        },
+    };
+    match algorithm.as_deref() {
+        None => Ok(None),
+        Some(sse::AES256) if kms_key_id.is_none() => {
+            let data_key = sse::new_data_key();
+            let wrapped_key = sse::wrap_data_key(&state.sse_master, &data_key)
+                .map_err(|_| ErrorCode::InternalError)?;
+            Ok(Some((data_key, ObjectEncryption::SseS3 { wrapped_key })))
+        }
+        Some(sse::AWS_KMS) => {
+            let key_id = kms_key_id.as_deref().unwrap_or(kms::DEFAULT_KEY_ID);
+            Ok(Some(state.kms.generate_data_key(key_id).await?))
+        }
+        Some(_) => Err(ErrorCode::InvalidArgument),
+    }
+}
+
+/// Recovers the data key of stored data. SSE-C data needs the same customer key it was
+/// written with, anything else must not come with one.
+pub(super) async fn data_key(
+    state: &AppState,
+    encryption: Option<&ObjectEncryption>,
+    customer: Option<&CustomerKey>,
+) -> Result<Option<DataKey>, ErrorCode> {
+    match (encryption, customer) {
+        (None, None) => Ok(None),
+        (None, Some(_))
+        | (Some(ObjectEncryption::SseS3 { .. }), Some(_))
+        | (Some(ObjectEncryption::Kms { .. }), Some(_)) => Err(ErrorCode::InvalidRequest),
+        (Some(ObjectEncryption::SseS3 { wrapped_key }), None) => {
+            sse::unwrap_data_key(&state.sse_master, wrapped_key)
+                .map(Some)
+                .map_err(|e| {
+                    eprintln!("Failed to unwrap object data key: {}", e);
+                    ErrorCode::InternalError
+                })
+        }
+        (
+            Some(ObjectEncryption::Kms {
+                key_id,
+                key_version,
+                wrapped_key,
+            }),
+            None,
+        ) => Ok(Some(state.kms.decrypt_data_key(key_id, *key_version, wrapped_key).await?)),
+        (Some(ObjectEncryption::SseC { .. }), None) => Err(ErrorCode::InvalidRequest),
+        (Some(encryption @ ObjectEncryption::SseC { .. }), Some(customer)) => {
+            match customer.open(encryption) {
+                Ok(Some(key)) => Ok(Some(key)),
+                Ok(None) => Err(ErrorCode::AccessDenied),
+                Err(e) => {
+                    eprintln!("Failed to unwrap object data key: {}", e);
+                    Err(ErrorCode::InternalError)
+                }
+            }
+        }
+    }
+}
+
+pub(super) fn insert_encryption_headers(
+    headers: &mut HeaderMap,
+    encryption: Option<&ObjectEncryption>,
+    customer: Option<&CustomerKey>,
+) {
+    match encryption {
+        Some(ObjectEncryption::SseC { .. }) => {
+            headers.insert(
+                "x-amz-server-side-encryption-customer-algorithm",
+                HeaderValue::from_static(sse::AES256),
+            );
+            if let Some(customer) = customer
+                && let Ok(md5) = HeaderValue::from_str(&customer.key_md5)
+            {
+                headers.insert("x-amz-server-side-encryption-customer-key-md5", md5);
+            }
+        }
+        Some(ObjectEncryption::Kms { key_id, .. }) => {
+            headers.insert(SSE_HEADER, HeaderValue::from_static(sse::AWS_KMS));
+            if let Ok(key_id) = HeaderValue::from_str(key_id) {
+                headers.insert(KMS_KEY_ID_HEADER, key_id);
+            }
+        }
+        Some(encryption) => {
+            headers.insert(SSE_HEADER, HeaderValue::from_static(encryption.algorithm()));
+        }
+        None => {}
+    }
+}
+
+pub(super) fn write_failed(e: WriteError, bucket: &str, key: &str) -> Response {
+    match e {
+        WriteError::Condition(code) => code.into_response(),
+        WriteError::Io(e) => {
+            eprintln!("Failed to write object '{}/{}': {}", bucket, key, e);
+            ErrorCode::InternalError.into_response()
+        }
+    }
+}
+
+pub(super) fn quoted_etag(etag: &str) -> String {
+    format!("\"{}\"", etag)
+}
+
+/// The version a new object gets, and whether the bucket versions at all (suspended
+/// buckets still answer with the null version id).
+pub(super) struct NewVersion {
+    pub id: Option<String>,
+    versioned: bool,
+}
+
+impl NewVersion {
+    pub async fn for_bucket(bucket: &str) -> Result<Self, ErrorCode> {
+        let status = versioning::status(bucket).await.map_err(|e| {
+            eprintln!("Failed to read versioning of bucket '{}': {}", bucket, e);
+            ErrorCode::InternalError
+        })?;
+        Ok(NewVersion {
+            id: (status == Versioning::Enabled).then(versioning::new_version_id),
+            versioned: status != Versioning::Unversioned,
+        })
+    }
+
+    pub fn insert_header(&self, headers: &mut HeaderMap) {
+        if self.versioned {
+            let id = self.id.as_deref().unwrap_or(versioning::NULL_VERSION);
+            headers.insert("x-amz-version-id", HeaderValue::from_str(id).unwrap());
+        }
+    }
+}
+
+/// `?versionId=` of a request, rejected outright if it isnt something we hand out.
+pub(super) fn version_query(query: &HashMap<String, String>) -> Result<Option<&str>, ErrorCode> {
+    match query.get("versionId").map(String::as_str) {
+        Some(id) if !versioning::is_valid_version_id(id) => Err(ErrorCode::InvalidArgument),
+        id => Ok(id),
+    }
+}
+
+/// Why a read came back empty handed. Hitting a delete marker says so in the headers,
+/// so clients can tell a deleted key from one that never existed.
+pub(super) enum ReadFailure {
+    Code(ErrorCode),
+    DeleteMarker {
+        code: ErrorCode,
+        version_id: String,
+        last_modified: DateTime<Utc>,
+    },
+}
+
+impl ReadFailure {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ReadFailure::Code(code) | ReadFailure::DeleteMarker { code, .. } => *code,
+        }
+    }
+}
+
+impl From<ErrorCode> for ReadFailure {
+    fn from(code: ErrorCode) -> Self {
+        ReadFailure::Code(code)
+    }
+}
+
+impl IntoResponse for ReadFailure {
+    fn into_response(self) -> Response {
+        match self {
+            ReadFailure::Code(code) => code.into_response(),
+            ReadFailure::DeleteMarker {
+                code,
+                version_id,
+                last_modified,
+            } => {
+                let mut response = code.into_response();
+                let headers = response.headers_mut();
+                headers.insert("x-amz-delete-marker", HeaderValue::from_static("true"));
+                if let Ok(id) = HeaderValue::from_str(&version_id) {
+                    headers.insert("x-amz-version-id", id);
+                }
+                let last_modified = preconditions::http_date(&last_modified);
+                headers.insert("last-modified", HeaderValue::from_str(&last_modified).unwrap());
+                response
+            }
+        }
+    }
+}
+
+/// The object (version) a read is after, with its data. The latest version being a
+/// delete marker is a 404, asking for a delete marker by id a 405.
+pub(super) async fn open_readable(
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<(ObjectMeta, File), ReadFailure> {
+    match storage::open_object(bucket, key, version_id).await {
+        Ok(Some((meta, Some(file)))) => Ok((meta, file)),
+        Ok(Some((marker, None))) => Err(ReadFailure::DeleteMarker {
+            code: match version_id {
+                Some(_) => ErrorCode::MethodNotAllowed,
+                None => ErrorCode::NoSuchKey,
+            },
+            version_id: versioning::version_name(&marker).to_string(),
+            last_modified: marker.last_modified,
+        }),
+        Ok(None) if !storage::bucket_exists(bucket).await => Err(ErrorCode::NoSuchBucket.into()),
+        Ok(None) if version_id.is_some() => Err(ErrorCode::NoSuchVersion.into()),
+        Ok(None) => Err(ErrorCode::NoSuchKey.into()),
+        Err(e) => {
+            eprintln!("Failed to read object '{}/{}': {}", bucket, key, e);
+            Err(ErrorCode::InternalError.into())
+        }
     }
 }
 
 pub async fn put_object(
+    state: &AppState,
     bucket: String,
     key: String,
-    _req_headers: HeaderMap,
+    req_headers: HeaderMap,
     body: Bytes,
 ) -> impl IntoResponse {
+    // add some shit to check the content type n prolly some tag shit
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
     // check if theres an object key
-    if key.is_empty() {
+    if storage::object_path(&bucket, &key).is_none() {
         return ErrorCode::NoSuchKey.into_response();
     }
-    let mut safe_key = PathBuf::new();
-    for component in Path::new(&key).components() {
-        match component {
-            Component::Normal(part) => safe_key.push(part),
-            _ => return ErrorCode::NoSuchKey.into_response(),
-        }
+    let customer = match CustomerKey::from_headers(&req_headers, SSE_C_PREFIX) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let content = match ContentMeta::from_headers(&req_headers) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let condition = match preconditions::write_condition(&req_headers) {
+        Ok(c) => c,
+        Err(code) => return code.into_response(),
+    };
+    let encryption = match encryption_for_put(state, &bucket, &req_headers).await {
+        Ok(e) => e,
+        Err(code) => return code.into_response(),
+    };
+    let (payload, encryption) = match encryption {
+        Some((data_key, encryption)) => match sse::encrypt_part(&data_key, 0, &sse::OBJECT_NONCE_BASE, &body) {
+            Ok(sealed) => (Bytes::from(sealed), Some(encryption)),
+            Err(_) => return ErrorCode::InternalError.into_response(),
+        },
+        None => (body.clone(), None),
+    };
+    let version = match NewVersion::for_bucket(&bucket).await {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let lock = match NewLock::for_put(&bucket, &req_headers).await {
+        Ok(l) => l,
+        Err(code) => return code.into_response(),
+    };
+    let tags = match tagging::tags_for_put(&req_headers) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    let meta = ObjectMeta {
+        key,
+        size: body.len() as u64,
+        etag: format!("{:x}", md5::compute(&body)),
+        last_modified: Utc::now(),
+        version_id: version.id.clone(),
+        delete_marker: false,
+        data: None,
+        encryption,
+        parts: Vec::new(),
+        retention: lock.retention,
+        legal_hold: lock.legal_hold,
+        tags,
+        content,
+    };
+    //oki uploaddd :3
+    if let Err(e) = storage::write_object(&bucket, &meta, &payload, &condition).await {
+        return write_failed(e, &bucket, &meta.key);
     }
-    // add some shit to check the content type n prolly some tag shit
-    if fs::metadata(&format!("./data/{}", bucket)).await.is_err() {
+    let mut headers = HeaderMap::new();
+    headers.insert("etag", HeaderValue::from_str(&quoted_etag(&meta.etag)).unwrap());
+    insert_encryption_headers(&mut headers, meta.encryption.as_ref(), customer.as_ref());
+    version.insert_header(&mut headers);
+    (StatusCode::OK, headers).into_response()
+}
+
+/// 204 whether or not there was anything to delete, same as S3.
+pub async fn delete_object(
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    bypass_governance: bool,
+) -> impl IntoResponse {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    if !storage::bucket_exists(&bucket).await {
         return ErrorCode::NoSuchBucket.into_response();
     }
-    if let Some(parent) = Path::new(&format!("./data/{}/{}", bucket, safe_key.display())).parent() {
-        if let Err(_) = fs::create_dir_all(parent).await {
-            return ErrorCode::InternalError.into_response();
+    let deletion = match storage::delete_object(&bucket, &key, version_id, bypass_governance, &WriteCondition::Always)
+        .await
+    {
+        Ok(d) => d,
+        Err(e) => return write_failed(e, &bucket, &key),
+    };
+    let mut headers = HeaderMap::new();
+    if let Some(id) = deletion.version_id
+        && let Ok(id) = HeaderValue::from_str(&id)
+    {
+        headers.insert("x-amz-version-id", id);
+    }
+    if deletion.delete_marker {
+        headers.insert("x-amz-delete-marker", HeaderValue::from_static("true"));
+    }
+    (StatusCode::NO_CONTENT, headers).into_response()
+}
+
+/// Objects are private to the server's owner like their buckets.
+pub async fn get_object_acl(bucket: String, key: String, query: &HashMap<String, String>) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    if let Err(failure) = open_readable(&bucket, &key, version_id).await {
+        return failure.into_response();
+    }
+    xml::ok_response(&AccessControlPolicy::private(Owner::default()), HeaderMap::new())
+}
+
+/// Parses a single `Range: bytes=..` header into `start..end` (end exclusive).
+/// Anything we dont understand, like multiple ranges, is ignored and gets the whole
+/// object; a well formed range that misses the object entirely is `InvalidRange`.
+fn parse_range(value: &str, size: u64) -> Result<Option<(u64, u64)>, ErrorCode> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        // suffix range, the last n bytes
+        let Ok(n) = last.parse::<u64>() else {
+            return Ok(None);
+        };
+        if n == 0 || size == 0 {
+            return Err(ErrorCode::InvalidRange);
         }
+        return Ok(Some((size.saturating_sub(n), size)));
     }
-    //oki uploaddd :3
-    let mut file = match File::create(format!("./data/{}/{}", bucket, safe_key.display())).await {
-        Ok(f) => f,
-        Err(_) => return ErrorCode::InternalError.into_response(),
-    };
-    if let Err(_) = file.write_all(&body).await {
-        return ErrorCode::InternalError.into_response();
-    }
-    (StatusCode::OK, {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "etag",
-            HeaderValue::from_str(&format!("{:x}", md5::compute(&body))).unwrap(),
-        );
-        headers
+    let Ok(start) = first.parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = match last {
+        "" => size,
+        last => match last.parse::<u64>() {
+            Ok(last) if last >= start => (last + 1).min(size),
+            _ => return Ok(None),
+        },
+    };
+    if start >= size {
+        return Err(ErrorCode::InvalidRange);
+    }
+    Ok(Some((start, end)))
+}
+
+/// Unsatisfiable ranges tell the client how big the object actually is.
+fn read_error(code: ErrorCode, meta: &ObjectMeta) -> Response {
+    let mut response = code.into_response();
+    if code == ErrorCode::InvalidRange {
+        let unsatisfied = format!("bytes */{}", meta.size);
+        response
+            .headers_mut()
+            .insert("content-range", HeaderValue::from_str(&unsatisfied).unwrap());
+    }
+    response
+}
+
+/// What part of an object a GET/HEAD gets back.
+struct ReadPlan {
+    data_key: Option<DataKey>,
+    headers: HeaderMap,
+    status: StatusCode,
+    start: u64,
+    end: u64,
+}
+
+/// What the request needs to read the object with, checked before anything about the
+/// object (its etag or dates through the preconditions) is given away.
+struct ReadAccess {
+    customer: Option<CustomerKey>,
+    data_key: Option<DataKey>,
+}
+
+/// Checks the request can read the object, SSE-C objects need the key they were written with.
+async fn read_access(state: &AppState, meta: &ObjectMeta, req_headers: &HeaderMap) -> Result<ReadAccess, ErrorCode> {
+    let customer = CustomerKey::from_headers(req_headers, SSE_C_PREFIX)?;
+    let data_key = data_key(state, meta.encryption.as_ref(), customer.as_ref()).await?;
+    Ok(ReadAccess { customer, data_key })
+}
+
+/// Works out the range the request wants along with the response headers GET and HEAD share.
+fn read_plan(
+    access: ReadAccess,
+    meta: &ObjectMeta,
+    query: &HashMap<String, String>,
+    req_headers: &HeaderMap,
+) -> Result<ReadPlan, ErrorCode> {
+    let ReadAccess { customer, data_key } = access;
+    let mut headers = HeaderMap::new();
+    meta.content.apply(&mut headers);
+    headers.insert("accept-ranges", HeaderValue::from_static("bytes"));
+    if !meta.etag.is_empty()
+        && let Ok(etag) = HeaderValue::from_str(&quoted_etag(&meta.etag))
+    {
+        headers.insert("etag", etag);
+    }
+    let last_modified = preconditions::http_date(&meta.last_modified);
+    headers.insert("last-modified", HeaderValue::from_str(&last_modified).unwrap());
+    insert_encryption_headers(&mut headers, meta.encryption.as_ref(), customer.as_ref());
+    if meta.version_id.is_some() || query.contains_key("versionId") {
+        let id = versioning::version_name(meta);
+        headers.insert("x-amz-version-id", HeaderValue::from_str(id).unwrap());
+    }
+    lock::insert_headers(&mut headers, meta);
+    if !meta.tags.is_empty() {
+        headers.insert(TAGGING_COUNT_HEADER, HeaderValue::from(meta.tags.len()));
+    }
+
+    let range_header = req_headers.get("range").and_then(|v| v.to_str().ok());
+    let range = match (query.get("partNumber"), range_header) {
+        (Some(_), Some(_)) => return Err(ErrorCode::InvalidRequest),
+        (Some(part), None) => {
+            let segments = meta.segments();
+            let index = match part.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= segments.len() => n - 1,
+                Ok(_) => return Err(ErrorCode::InvalidPartNumber),
+                Err(_) => return Err(ErrorCode::InvalidArgument),
+            };
+            if !meta.parts.is_empty() {
+                headers.insert("x-amz-mp-parts-count", HeaderValue::from(segments.len()));
+            }
+            let start: u64 = segments[..index].iter().map(|s| s.plain_len).sum();
+            Some((start, start + segments[index].plain_len))
+        }
+        (None, Some(range)) => parse_range(range, meta.size)?,
+        (None, None) => None,
+    };
+
+    let (status, start, end) = match range {
+        // a single part object asked for part 1 is just the object
+        Some((start, end)) if start == 0 && end == meta.size && range_header.is_none() => {
+            (StatusCode::OK, start, end)
+        }
+        Some((start, end)) => {
+            let content_range = format!("bytes {}-{}/{}", start, end.saturating_sub(1), meta.size);
+            headers.insert("content-range", HeaderValue::from_str(&content_range).unwrap());
+            (StatusCode::PARTIAL_CONTENT, start, end)
+        }
+        None => (StatusCode::OK, 0, meta.size),
+    };
+    headers.insert("content-length", HeaderValue::from(end - start));
+    Ok(ReadPlan {
+        data_key,
+        headers,
+        status,
+        start,
+        end,
     })
-        .into_response()
 }
 
-pub async fn get_object(bucket: String, key: String, _req_headers: HeaderMap) -> impl IntoResponse {
-    let file = match File::open(format!("./data/{}/{}", bucket, key)).await {
-        Ok(f) => f,
-        Err(_) => return ErrorCode::NoSuchKey.into_response(),
+pub async fn get_object(
+    state: &AppState,
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let (meta, mut file) = match open_readable(&bucket, &key, version_id).await {
+        Ok(o) => o,
+        Err(failure) => return failure.into_response(),
+    };
+    let access = match read_access(state, &meta, &req_headers).await {
+        Ok(a) => a,
+        Err(code) => return read_error(code, &meta),
     };
+    match preconditions::evaluate(&meta, &req_headers, "") {
+        Outcome::Proceed => {}
+        Outcome::NotModified => return preconditions::not_modified(&meta),
+        Outcome::Failed => return ErrorCode::PreconditionFailed.into_response(),
+    }
+    let plan = match read_plan(access, &meta, query, &req_headers) {
+        Ok(p) => p,
+        Err(code) => return read_error(code, &meta),
+    };
+    // skipped bytes never get read, encrypted objects only decrypt the chunks in range
+    let body = match plan.data_key {
+        Some(data_key) => Body::from_stream(sse::decrypt_range(
+            file,
+            data_key,
+            meta.segments(),
+            plan.start,
+            plan.end,
+        )),
+        None => {
+            if let Err(e) = file.seek(SeekFrom::Start(plan.start)).await {
+                eprintln!("Failed to seek in '{}/{}': {}", bucket, key, e);
+                return ErrorCode::InternalError.into_response();
+            }
+            Body::from_stream(ReaderStream::new(file.take(plan.end - plan.start)))
+        }
+    };
+    (plan.status, plan.headers, body).into_response()
+}
 
-    let mut headers = HeaderMap::new();
-    let content_type = match key.rsplit('.').next() {
-        Some("html") => "text/html",
-        Some("json") => "application/json",
-        Some("png") => "image/png",
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("gif") => "image/gif",
-        Some("webp") => "image/webp",
-        Some("txt") => "text/plain",
-        Some("css") => "text/css",
-        Some("js") => "application/javascript",
-        Some("pdf") => "application/pdf",
-        _ => "application/octet-stream",
-    };
-    headers.insert("content-type", HeaderValue::from_static(content_type));
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-    (StatusCode::OK, headers, body).into_response()
+pub async fn head_object(
+    state: &AppState,
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let meta = match open_readable(&bucket, &key, version_id).await {
+        Ok((meta, _)) => meta,
+        Err(failure) => return failure.into_response(),
+    };
+    let access = match read_access(state, &meta, &req_headers).await {
+        Ok(a) => a,
+        Err(code) => return read_error(code, &meta),
+    };
+    match preconditions::evaluate(&meta, &req_headers, "") {
+        Outcome::Proceed => {}
+        Outcome::NotModified => return preconditions::not_modified(&meta),
+        Outcome::Failed => return ErrorCode::PreconditionFailed.into_response(),
+    }
+    match read_plan(access, &meta, query, &req_headers) {
+        Ok(plan) => (plan.status, plan.headers).into_response(),
+        Err(code) => read_error(code, &meta),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-9", 100), Ok(Some((0, 10))));
+        assert_eq!(parse_range("bytes=90-", 100), Ok(Some((90, 100))));
+        assert_eq!(parse_range("bytes=-10", 100), Ok(Some((90, 100))));
+        assert_eq!(parse_range("bytes=-500", 100), Ok(Some((0, 100))));
+        assert_eq!(parse_range("bytes=50-500", 100), Ok(Some((50, 100))));
+        assert_eq!(parse_range("bytes=100-", 100), Err(ErrorCode::InvalidRange));
+        assert_eq!(parse_range("bytes=-0", 100), Err(ErrorCode::InvalidRange));
+        assert_eq!(parse_range("bytes=0-", 0), Err(ErrorCode::InvalidRange));
+        // not something we serve partially, whole object instead
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), Ok(None));
+        assert_eq!(parse_range("bytes=9-1", 100), Ok(None));
+        assert_eq!(parse_range("items=0-1", 100), Ok(None));
+    }
 }
@@ -0,0 +1,200 @@
+// If-Match / If-None-Match / If-Modified-Since / If-Unmodified-Since, evaluated in the
+// order RFC 9110 gives them. copies check the same set with an x-amz-copy-source- prefix.
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use lumi_utils::errors::ErrorCode;
+
+use super::operations::quoted_etag;
+use crate::storage::{ObjectMeta, WriteCondition};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+pub fn http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn header<'a>(headers: &'a HeaderMap, prefix: &str, name: &str) -> Option<&'a str> {
+    headers
+        .get(format!("{}{}", prefix, name))
+        .and_then(|v| v.to_str().ok())
+}
+
+/// Unparseable dates are ignored, same as everyone else does.
+fn date_header(headers: &HeaderMap, prefix: &str, name: &str) -> Option<DateTime<Utc>> {
+    let value = header(headers, prefix, name)?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|d| d.with_timezone(&Utc))
+}
+
+/// Etags from a header list, `W/` and quotes stripped. `*` stays `*`.
+fn etag_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag.trim_matches('"').to_string()
+        })
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+fn etag_matches(value: &str, meta: &ObjectMeta) -> bool {
+    etag_list(value)
+        .iter()
+        .any(|tag| tag == "*" || (!meta.etag.is_empty() && *tag == meta.etag))
+}
+
+/// Http dates only go down to the second.
+fn modified_after(meta: &ObjectMeta, date: &DateTime<Utc>) -> bool {
+    meta.last_modified.timestamp() > date.timestamp()
+}
+
+/// Evaluates the read preconditions in `headers` (names prefixed with `prefix`).
+pub fn evaluate(meta: &ObjectMeta, headers: &HeaderMap, prefix: &str) -> Outcome {
+    if let Some(value) = header(headers, prefix, "if-match") {
+        if !etag_matches(value, meta) {
+            return Outcome::Failed;
+        }
+    } else if let Some(date) = date_header(headers, prefix, "if-unmodified-since")
+        && modified_after(meta, &date)
+    {
+        return Outcome::Failed;
+    }
+    if let Some(value) = header(headers, prefix, "if-none-match") {
+        if etag_matches(value, meta) {
+            return Outcome::NotModified;
+        }
+    } else if let Some(date) = date_header(headers, prefix, "if-modified-since")
+        && !modified_after(meta, &date)
+    {
+        return Outcome::NotModified;
+    }
+    Outcome::Proceed
+}
+
+/// 304 with just the validators and caching headers, no body.
+pub fn not_modified(meta: &ObjectMeta) -> Response {
+    let mut headers = HeaderMap::new();
+    if !meta.etag.is_empty()
+        && let Ok(etag) = HeaderValue::from_str(&quoted_etag(&meta.etag))
+    {
+        headers.insert("etag", etag);
+    }
+    headers.insert(
+        "last-modified",
+        HeaderValue::from_str(&http_date(&meta.last_modified)).unwrap(),
+    );
+    for name in ["cache-control", "expires"] {
+        if let Some(value) = meta.content.headers.get(name)
+            && let Ok(value) = HeaderValue::from_str(value)
+        {
+            headers.insert(name, value);
+        }
+    }
+    (StatusCode::NOT_MODIFIED, headers).into_response()
+}
+
+/// The precondition of a PUT / CompleteMultipartUpload, checked when the write lands.
+pub fn write_condition(headers: &HeaderMap) -> Result<WriteCondition, ErrorCode> {
+    match (header(headers, "", "if-none-match"), header(headers, "", "if-match")) {
+        (None, None) => Ok(WriteCondition::Always),
+        (Some(value), None) if value.trim() == "*" => Ok(WriteCondition::NotExists),
+        (None, Some(value)) => {
+            let etags = etag_list(value);
+            if etags.is_empty() {
+                return Err(ErrorCode::InvalidArgument);
+            }
+            Ok(WriteCondition::EtagMatches(etags))
+        }
+        // only `If-None-Match: *` makes sense for a write, and not together with If-Match
+        _ => Err(ErrorCode::NotImplemented),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ContentMeta;
+
+    fn meta() -> ObjectMeta {
+        ObjectMeta {
+            key: "k".into(),
+            size: 1,
+            etag: "abc".into(),
+            last_modified: DateTime::parse_from_rfc3339("2024-05-01T12:00:00.500Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            version_id: None,
+            delete_marker: false,
+            data: None,
+            encryption: None,
+            parts: Vec::new(),
+            retention: None,
+            legal_hold: false,
+            tags: Default::default(),
+            content: ContentMeta::default(),
+        }
+    }
+
+    fn check(pairs: &[(&'static str, &str)]) -> Outcome {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        evaluate(&meta(), &headers, "")
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(check(&[]), Outcome::Proceed);
+        assert_eq!(check(&[("if-match", "\"abc\"")]), Outcome::Proceed);
+        assert_eq!(check(&[("if-match", "\"nope\", W/\"abc\"")]), Outcome::Proceed);
+        assert_eq!(check(&[("if-match", "\"nope\"")]), Outcome::Failed);
+        assert_eq!(check(&[("if-none-match", "\"abc\"")]), Outcome::NotModified);
+        assert_eq!(check(&[("if-none-match", "*")]), Outcome::NotModified);
+        assert_eq!(check(&[("if-none-match", "\"other\"")]), Outcome::Proceed);
+
+        let same_second = "Wed, 01 May 2024 12:00:00 GMT";
+        let before = "Wed, 01 May 2024 11:00:00 GMT";
+        assert_eq!(check(&[("if-modified-since", same_second)]), Outcome::NotModified);
+        assert_eq!(check(&[("if-modified-since", before)]), Outcome::Proceed);
+        assert_eq!(check(&[("if-unmodified-since", before)]), Outcome::Failed);
+        assert_eq!(check(&[("if-unmodified-since", same_second)]), Outcome::Proceed);
+        assert_eq!(check(&[("if-modified-since", "garbage")]), Outcome::Proceed);
+
+        // If-Match wins over If-Unmodified-Since, If-None-Match over If-Modified-Since
+        assert_eq!(
+            check(&[("if-match", "\"abc\""), ("if-unmodified-since", before)]),
+            Outcome::Proceed
+        );
+        assert_eq!(
+            check(&[("if-none-match", "\"other\""), ("if-modified-since", same_second)]),
+            Outcome::Proceed
+        );
+    }
+
+    #[test]
+    fn test_write_condition() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(write_condition(&headers), Ok(WriteCondition::Always));
+        headers.insert("if-none-match", "*".parse().unwrap());
+        assert_eq!(write_condition(&headers), Ok(WriteCondition::NotExists));
+        headers.insert("if-match", "\"abc\"".parse().unwrap());
+        assert!(write_condition(&headers).is_err());
+        headers.remove("if-none-match");
+        assert_eq!(
+            write_condition(&headers),
+            Ok(WriteCondition::EtagMatches(vec!["abc".into()]))
+        );
+    }
+}
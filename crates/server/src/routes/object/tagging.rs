@@ -0,0 +1,116 @@
+// ?tagging on objects. tags belong to one version, without versionId it is the latest.
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use lumi_utils::errors::ErrorCode;
+use std::collections::{BTreeMap, HashMap};
+
+use super::operations::{open_readable, version_query, write_failed};
+use crate::storage::{
+    self,
+    tagging::{self, MAX_OBJECT_TAGS, TAGGING_DIRECTIVE_HEADER, TAGGING_HEADER},
+    versioning, ObjectMeta,
+};
+use crate::xml::{self, TagSet, Tagging};
+
+/// Tags for a new object from `x-amz-tagging`.
+pub(super) fn tags_for_put(headers: &HeaderMap) -> Result<BTreeMap<String, String>, ErrorCode> {
+    match headers.get(TAGGING_HEADER) {
+        Some(value) => tagging::parse_header(value.to_str().map_err(|_| ErrorCode::InvalidTag)?),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Tags for a copy, the source's unless `x-amz-tagging-directive: REPLACE`.
+pub(super) fn tags_for_copy(
+    headers: &HeaderMap,
+    source: &ObjectMeta,
+) -> Result<BTreeMap<String, String>, ErrorCode> {
+    match headers.get(TAGGING_DIRECTIVE_HEADER).map(|v| v.as_bytes()) {
+        None | Some(b"COPY") => Ok(source.tags.clone()),
+        Some(b"REPLACE") => tags_for_put(headers),
+        Some(_) => Err(ErrorCode::InvalidArgument),
+    }
+}
+
+fn version_header(meta: &ObjectMeta, query: &HashMap<String, String>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if meta.version_id.is_some() || query.contains_key("versionId") {
+        let id = versioning::version_name(meta);
+        headers.insert("x-amz-version-id", HeaderValue::from_str(id).unwrap());
+    }
+    headers
+}
+
+pub async fn get_object_tagging(bucket: String, key: String, query: &HashMap<String, String>) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    let meta = match open_readable(&bucket, &key, version_id).await {
+        Ok((meta, _)) => meta,
+        Err(failure) => return failure.into_response(),
+    };
+    let result = Tagging {
+        tag_set: TagSet {
+            tags: tagging::to_tag_set(&meta.tags),
+        },
+    };
+    xml::ok_response(&result, version_header(&meta, query))
+}
+
+/// Replaces the tag set of one version, an empty one drops it.
+async fn set_tags(
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    tags: BTreeMap<String, String>,
+    status: StatusCode,
+) -> Response {
+    let version_id = match version_query(query) {
+        Ok(v) => v,
+        Err(code) => return code.into_response(),
+    };
+    if !storage::bucket_exists(&bucket).await {
+        return ErrorCode::NoSuchBucket.into_response();
+    }
+    let update = |meta: &mut ObjectMeta| {
+        // same as reading it: a 404 for the latest, a 405 when asked for by id
+        match (meta.delete_marker, version_id) {
+            (true, Some(_)) => return Err(ErrorCode::MethodNotAllowed),
+            (true, None) => return Err(ErrorCode::NoSuchKey),
+            (false, _) => {}
+        }
+        meta.tags = tags;
+        Ok(())
+    };
+    match storage::update_version(&bucket, &key, version_id, update).await {
+        Ok(Some(meta)) => (status, version_header(&meta, query)).into_response(),
+        Ok(None) if version_id.is_some() => ErrorCode::NoSuchVersion.into_response(),
+        Ok(None) => ErrorCode::NoSuchKey.into_response(),
+        Err(e) => write_failed(e, &bucket, &key),
+    }
+}
+
+pub async fn put_object_tagging(
+    bucket: String,
+    key: String,
+    query: &HashMap<String, String>,
+    body: Bytes,
+) -> Response {
+    let request: Tagging = match xml::from_xml(&body) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    let tags = match tagging::validate(request.tag_set.tags, MAX_OBJECT_TAGS) {
+        Ok(t) => t,
+        Err(code) => return code.into_response(),
+    };
+    set_tags(bucket, key, query, tags, StatusCode::OK).await
+}
+
+pub async fn delete_object_tagging(bucket: String, key: String, query: &HashMap<String, String>) -> Response {
+    set_tags(bucket, key, query, BTreeMap::new(), StatusCode::NO_CONTENT).await
+}
@@ -0,0 +1,84 @@
+use lumi_credentials::{credentials::KeyError, envelope::MasterKey, store::CredentialStore};
+use std::collections::{HashMap, HashSet};
+
+use crate::audit::Metrics;
+use crate::kms::Kms;
+use crate::tls::ClientCertificate;
+use crate::xml::CorsConfiguration;
+
+/// Shared across every request, built once in `start_http_server`.
+#[derive(Debug)]
+pub struct AppState {
+    /// the one region this server is, requests have to be signed for it
+    pub region: String,
+    /// SignatureDoesNotMatch errors come with the string to sign and canonical request
+    pub debug_signatures: bool,
+    pub root_access_key: String,
+    pub root_secret_key: String,
+    pub credentials: Option<CredentialStore>,
+    /// client certificate name (`cn:svc`, `dns:svc.internal`, ..) -> access key
+    pub cert_identities: HashMap<String, String>,
+    /// wraps the per-object data keys of SSE-S3 objects
+    pub sse_master: MasterKey,
+    /// named keys for SSE-KMS, their material is wrapped by `sse_master`
+    pub kms: Kms,
+    /// access keys that may use `x-amz-bypass-governance-retention`, root always can
+    pub governance_bypass: HashSet<String>,
+    /// CORS rules for buckets without their own, from `--allowed-origin`
+    pub cors_fallback: CorsConfiguration,
+    /// `{bucket}.{domain}` is that bucket, virtual-hosted style
+    pub domains: Vec<String>,
+    /// `{bucket}.{domain}` on the website listener is that bucket
+    pub website_domains: Vec<String>,
+    /// per operation request counts, see `audit`
+    pub metrics: Metrics,
+}
+
+impl AppState {
+    /// The env/default root key first, then the persisted store.
+    pub fn secret_key(&self, access_key: &str) -> Result<Option<String>, KeyError> {
+        if access_key == self.root_access_key {
+            return Ok(Some(self.root_secret_key.clone()));
+        }
+        match &self.credentials {
+            Some(store) => store.secret_key(access_key),
+            None => Ok(None),
+        }
+    }
+
+    pub fn can_bypass_governance(&self, access_key: &str) -> bool {
+        access_key == self.root_access_key || self.governance_bypass.contains(access_key)
+    }
+
+    /// First certificate name with a mapping wins.
+    pub fn cert_identity(&self, cert: &ClientCertificate) -> Option<&str> {
+        cert.names
+            .iter()
+            .find_map(|name| self.cert_identities.get(name))
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Just the default root key and nothing on disk, for handler tests.
+    pub fn for_tests(region: &str) -> Self {
+        let sse_master = MasterKey::generate();
+        AppState {
+            region: region.to_string(),
+            debug_signatures: false,
+            root_access_key: lumi_credentials::DEFAULT_ACCESS_KEY.to_string(),
+            root_secret_key: lumi_credentials::DEFAULT_SECRET_KEY.to_string(),
+            credentials: None,
+            cert_identities: HashMap::new(),
+            kms: Kms::open(&crate::storage::sys_dir().join("kms").join("keys.json"), sse_master.clone())
+                .unwrap(),
+            sse_master,
+            governance_bypass: HashSet::new(),
+            cors_fallback: crate::cors::fallback("*"),
+            domains: Vec::new(),
+            website_domains: Vec::new(),
+            metrics: Metrics::default(),
+        }
+    }
+}
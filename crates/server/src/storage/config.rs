@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use tokio::fs;
+
+use super::{bucket_sys_path, write_atomic};
+use crate::xml::{
+    CorsConfiguration, LifecycleConfiguration, ObjectLockConfiguration, ServerSideEncryptionConfiguration,
+    VersioningConfiguration, WebsiteConfiguration,
+};
+
+/// Per-bucket settings set through the `?subresource` APIs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BucketConfig {
+    /// region the bucket was created in, older buckets are in the server's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<ServerSideEncryptionConfiguration>,
+    /// once set it can only be suspended, never go back to unversioned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versioning: Option<VersioningConfiguration>,
+    /// only ever set on buckets created with object lock, which also keeps versioning on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_lock: Option<ObjectLockConfiguration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifecycle: Option<LifecycleConfiguration>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cors: Option<CorsConfiguration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website: Option<WebsiteConfiguration>,
+}
+
+fn config_path(bucket: &str) -> PathBuf {
+    bucket_sys_path(bucket).join("config.json")
+}
+
+pub async fn load(bucket: &str) -> io::Result<BucketConfig> {
+    match fs::read(config_path(bucket)).await {
+        Ok(raw) => {
+            serde_json::from_slice(&raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BucketConfig::default()),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn save(bucket: &str, config: &BucketConfig) -> io::Result<()> {
+    let raw =
+        serde_json::to_vec_pretty(config).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_atomic(&config_path(bucket), &raw).await
+}
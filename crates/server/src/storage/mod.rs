@@ -0,0 +1,1138 @@
+// everything that knows where things live on disk. buckets are dirs under ./data, objects
+// from before lumi kept metadata are plain files under ./data/{bucket}/{key}. everything
+// newer lives under ./.lumi.sys, next to the data and never reachable as a bucket: each
+// write gets a data file of its own and the metadata pointing at it is what makes it current
+pub mod config;
+pub mod multipart;
+pub mod object_lock;
+pub mod sse;
+pub mod tagging;
+pub mod versioning;
+
+use axum::http::{HeaderMap, HeaderValue};
+use chrono::{DateTime, Utc};
+use lumi_utils::errors::ErrorCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+use tokio::fs::{self, File};
+use tokio::sync::RwLock;
+
+use object_lock::Retention;
+use sse::{ObjectEncryption, Segment};
+use versioning::{Versioning, NULL_VERSION};
+
+pub const DATA_DIR: &str = "./data";
+pub const SYS_DIR: &str = "./.lumi.sys";
+
+// tests get their own scratch copy of both under the temp dir
+static DATA_ROOT: LazyLock<PathBuf> = LazyLock::new(|| root(DATA_DIR));
+static SYS_ROOT: LazyLock<PathBuf> = LazyLock::new(|| root(SYS_DIR));
+
+fn root(dir: &str) -> PathBuf {
+    if cfg!(test) {
+        std::env::temp_dir()
+            .join(format!("lumi-test-{}", std::process::id()))
+            .join(dir)
+    } else {
+        PathBuf::from(dir)
+    }
+}
+
+pub fn data_dir() -> &'static Path {
+    &DATA_ROOT
+}
+
+pub fn sys_dir() -> &'static Path {
+    &SYS_ROOT
+}
+
+/// Older versions kept the system dir inside the data dir, where it was one path-style
+/// request away. Moves it out if it is still there.
+pub async fn migrate_sys_dir() -> io::Result<()> {
+    let old = data_dir().join(".lumi.sys");
+    if fs::metadata(&old).await.is_ok() && fs::metadata(sys_dir()).await.is_err() {
+        fs::rename(&old, sys_dir()).await?;
+    }
+    Ok(())
+}
+
+/// The CreateBucket naming rules: 3 to 63 lowercase letters, digits, dots and hyphens,
+/// starting and ending with a letter or digit, no `..`.
+pub fn valid_bucket_name(bucket: &str) -> bool {
+    let bytes = bucket.as_bytes();
+    (3..=63).contains(&bytes.len())
+        && bytes
+            .iter()
+            .all(|&b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'.')
+        && bytes[0].is_ascii_alphanumeric()
+        && bytes[bytes.len() - 1].is_ascii_alphanumeric()
+        && !bucket.contains("..")
+}
+
+/// `None` for names no bucket can have, so nothing else in the data dir is ever
+/// mistaken for one.
+pub fn bucket_path(bucket: &str) -> Option<PathBuf> {
+    valid_bucket_name(bucket).then(|| data_dir().join(bucket))
+}
+
+/// `None` if the key would escape the bucket (`..`, absolute paths and friends).
+pub fn object_path(bucket: &str, key: &str) -> Option<PathBuf> {
+    if key.is_empty() {
+        return None;
+    }
+    let mut path = bucket_path(bucket)?;
+    for component in Path::new(key).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+pub fn bucket_sys_path(bucket: &str) -> PathBuf {
+    sys_dir().join("buckets").join(bucket)
+}
+
+/// Metadata is keyed by a hash of the object key so any key fits in one flat(ish) dir
+/// no matter how long it is or what it collides with in the data tree.
+pub fn meta_path(bucket: &str, key: &str) -> PathBuf {
+    let hash = hex::encode(Sha256::digest(key.as_bytes()));
+    bucket_sys_path(bucket)
+        .join("meta")
+        .join(&hash[..2])
+        .join(format!("{}.json", hash))
+}
+
+/// Data files of one key, each write gets a fresh name so nothing is ever overwritten
+/// in place.
+fn data_file(bucket: &str, key: &str, id: &str) -> PathBuf {
+    let hash = hex::encode(Sha256::digest(key.as_bytes()));
+    bucket_sys_path(bucket)
+        .join("objects")
+        .join(&hash[..2])
+        .join(hash)
+        .join(id)
+}
+
+fn new_data_id() -> String {
+    hex::encode(lumi_credentials::envelope::random_bytes::<16>())
+}
+
+/// Gives the file at `from` a data file name of its own (another link to it, `from`
+/// stays), for data that was stored somewhere else before.
+async fn link_data(bucket: &str, key: &str, from: &Path) -> io::Result<String> {
+    let id = new_data_id();
+    let path = data_file(bucket, key, &id);
+    fs::create_dir_all(path.parent().unwrap()).await?;
+    fs::hard_link(from, &path).await?;
+    Ok(id)
+}
+
+pub fn tmp_path() -> PathBuf {
+    let name = hex::encode(lumi_credentials::envelope::random_bytes::<16>());
+    sys_dir().join("tmp").join(name)
+}
+
+/// Standard headers kept from the upload and handed back verbatim on GET/HEAD.
+pub const STORED_HEADERS: &[&str] = &[
+    "content-type",
+    "content-encoding",
+    "content-disposition",
+    "content-language",
+    "cache-control",
+    "expires",
+    WEBSITE_REDIRECT_HEADER,
+];
+/// where the website endpoint sends requests for the object instead of serving it
+pub const WEBSITE_REDIRECT_HEADER: &str = "x-amz-website-redirect-location";
+pub const USER_META_PREFIX: &str = "x-amz-meta-";
+/// S3 caps user metadata at 2KB, names and values together
+pub const MAX_USER_META_SIZE: usize = 2 * 1024;
+pub const DEFAULT_CONTENT_TYPE: &str = "binary/octet-stream";
+
+/// The client supplied part of an object's metadata.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContentMeta {
+    /// lowercased names from `STORED_HEADERS`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// `x-amz-meta-*` with the prefix stripped
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub user: BTreeMap<String, String>,
+}
+
+impl ContentMeta {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ErrorCode> {
+        let mut meta = ContentMeta::default();
+        for (name, value) in headers {
+            let name = name.as_str();
+            let value = || value.to_str().map(str::to_string).map_err(|_| ErrorCode::InvalidArgument);
+            if STORED_HEADERS.contains(&name) {
+                meta.headers.insert(name.to_string(), value()?);
+            } else if let Some(user) = name.strip_prefix(USER_META_PREFIX) {
+                meta.user.insert(user.to_string(), value()?);
+            }
+        }
+        if let Some(location) = meta.headers.get(WEBSITE_REDIRECT_HEADER)
+            && !["/", "http://", "https://"].iter().any(|p| location.starts_with(p))
+        {
+            return Err(ErrorCode::InvalidRedirectLocation);
+        }
+        let user_size: usize = meta.user.iter().map(|(k, v)| k.len() + v.len()).sum();
+        if user_size > MAX_USER_META_SIZE {
+            return Err(ErrorCode::MetadataTooLarge);
+        }
+        Ok(meta)
+    }
+
+    /// Adds the stored headers to a response, content-type always gets a value.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                axum::http::HeaderName::try_from(name.as_str()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        if !headers.contains_key("content-type") {
+            headers.insert("content-type", HeaderValue::from_static(DEFAULT_CONTENT_TYPE));
+        }
+        for (name, value) in &self.user {
+            if let (Ok(name), Ok(value)) = (
+                axum::http::HeaderName::try_from(format!("{}{}", USER_META_PREFIX, name)),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectMeta {
+    pub key: String,
+    /// plaintext size, what clients see
+    pub size: u64,
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    /// `None` is the null version, anything written while the bucket wasnt versioning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+    /// delete markers are just a key and a version, no data
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delete_marker: bool,
+    /// name of the data file, `None` for data written before they had their own names
+    /// (the plain file in the bucket tree, or the version's .data)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<ObjectEncryption>,
+    /// parts in object order for multipart uploads, empty for a single PUT
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<PartMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub legal_hold: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default, flatten)]
+    pub content: ContentMeta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartMeta {
+    pub number: u32,
+    pub size: u64,
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    /// hex `sse::NonceBase` the part was sealed with, encrypted uploads only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce_base: Option<String>,
+    /// name of the part's data file in the upload dir, `None` for parts stored as `NNNNN.part`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl ObjectMeta {
+    pub fn delete_marker(key: &str, version_id: Option<String>) -> Self {
+        ObjectMeta {
+            key: key.to_string(),
+            size: 0,
+            etag: String::new(),
+            last_modified: Utc::now(),
+            version_id,
+            delete_marker: true,
+            data: None,
+            encryption: None,
+            parts: Vec::new(),
+            retention: None,
+            legal_hold: false,
+            tags: BTreeMap::new(),
+            content: ContentMeta::default(),
+        }
+    }
+
+    /// How the data file is laid out, one segment per independently written part.
+    pub fn segments(&self) -> Vec<Segment> {
+        if self.parts.is_empty() {
+            return vec![Segment {
+                part: 0,
+                nonce_base: sse::OBJECT_NONCE_BASE,
+                plain_len: self.size,
+            }];
+        }
+        self.parts
+            .iter()
+            .map(|p| Segment {
+                part: p.number,
+                // a garbled base just fails authentication on read
+                nonce_base: p
+                    .nonce_base
+                    .as_deref()
+                    .and_then(|n| hex::decode(n).ok()?.try_into().ok())
+                    .unwrap_or_default(),
+                plain_len: p.size,
+            })
+            .collect()
+    }
+}
+
+/// Where the current version `meta` keeps its data.
+fn current_data_path(bucket: &str, meta: &ObjectMeta) -> Option<PathBuf> {
+    match &meta.data {
+        Some(id) => Some(data_file(bucket, &meta.key, id)),
+        None => object_path(bucket, &meta.key),
+    }
+}
+
+pub async fn bucket_exists(bucket: &str) -> bool {
+    let Some(path) = bucket_path(bucket) else {
+        return false;
+    };
+    fs::metadata(path).await.map(|m| m.is_dir()).unwrap_or(false)
+}
+
+/// Names of all buckets, dirs that couldnt have been created as one are skipped.
+pub async fn bucket_names() -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut entries = fs::read_dir(data_dir()).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if valid_bucket_name(&name) && entry.file_type().await?.is_dir() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// `Ok(None)` for objects written before lumi kept metadata, those are plain files.
+pub async fn read_meta(bucket: &str, key: &str) -> io::Result<Option<ObjectMeta>> {
+    match fs::read(meta_path(bucket, key)).await {
+        Ok(raw) => serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// the metadata write is what commits a version, readers and writers of the same key take
+// one of these so a reader's data file isnt cleaned up under it and conditional writes
+// see what they replace. keys are hashed onto a fixed set of locks, an unlucky collision
+// just means a little extra waiting.
+static KEY_LOCKS: LazyLock<[RwLock<()>; 64]> = LazyLock::new(|| std::array::from_fn(|_| RwLock::new(())));
+
+fn key_lock(bucket: &str, key: &str) -> &'static RwLock<()> {
+    let hash = Sha256::new()
+        .chain_update(bucket.as_bytes())
+        .chain_update([0])
+        .chain_update(key.as_bytes())
+        .finalize();
+    &KEY_LOCKS[hash[0] as usize % KEY_LOCKS.len()]
+}
+
+/// Metadata of the current version, `None` if there is none or it is a delete marker.
+pub async fn stat_object(bucket: &str, key: &str) -> io::Result<Option<ObjectMeta>> {
+    let _guard = key_lock(bucket, key).read().await;
+    Ok(current_unlocked(bucket, key).await?.filter(|m| !m.delete_marker))
+}
+
+/// A version of an object (the latest if `version_id` is `None`) and an open handle on
+/// its data that belong together, even if the object is replaced while the handle is
+/// being read. Delete markers come back without a handle.
+pub async fn open_object(
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> io::Result<Option<(ObjectMeta, Option<File>)>> {
+    let _guard = key_lock(bucket, key).read().await;
+    let current = current_unlocked(bucket, key).await?;
+    let (meta, path) = match (current, version_id) {
+        (Some(current), None) => (current, None),
+        (Some(current), Some(id)) if versioning::version_name(&current) == id => (current, None),
+        (None, None) => return Ok(None),
+        (_, Some(id)) => match versioning::load(bucket, key, id).await? {
+            Some(meta) => {
+                let path = versioning::data_path(bucket, &meta)?;
+                (meta, Some(path))
+            }
+            None => return Ok(None),
+        },
+    };
+    if meta.delete_marker {
+        return Ok(Some((meta, None)));
+    }
+    let Some(path) = path.or_else(|| current_data_path(bucket, &meta)) else {
+        return Ok(None);
+    };
+    match File::open(path).await {
+        Ok(file) => Ok(Some((meta, Some(file)))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Every version of a key, the current one first and then the rest newest first.
+pub async fn object_versions(bucket: &str, key: &str) -> io::Result<Vec<ObjectMeta>> {
+    let _guard = key_lock(bucket, key).read().await;
+    let mut versions: Vec<ObjectMeta> = current_unlocked(bucket, key).await?.into_iter().collect();
+    versions.extend(versioning::list(bucket, key).await?);
+    Ok(versions)
+}
+
+/// Every key in the bucket starting with `prefix` with a current version, delete markers
+/// included. Old plain files are found by walking only the dirs that can hold such keys,
+/// metadata is stored by hash so every file of it gets read.
+pub async fn list_keys(bucket: &str, prefix: &str) -> io::Result<BTreeSet<String>> {
+    let mut keys = BTreeSet::new();
+    let root = bucket_path(bucket).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    let relative_key = |path: &Path| {
+        let parts: Vec<_> = path.strip_prefix(&root).ok()?.iter().map(|p| p.to_string_lossy()).collect();
+        Some(parts.join("/"))
+    };
+    let mut dirs = vec![root.clone()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let Some(key) = relative_key(&entry.path()) else {
+                continue;
+            };
+            if file_type.is_dir() {
+                let dir_prefix = key + "/";
+                if dir_prefix.starts_with(prefix) || prefix.starts_with(&dir_prefix) {
+                    dirs.push(entry.path());
+                }
+            } else if file_type.is_file() && key.starts_with(prefix) {
+                keys.insert(key);
+            }
+        }
+    }
+    // everything written since has its key in the metadata
+    let mut dirs = vec![bucket_sys_path(bucket).join("meta")];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+            let raw = fs::read(entry.path()).await?;
+            if let Ok(meta) = serde_json::from_slice::<ObjectMeta>(&raw)
+                && meta.key.starts_with(prefix)
+            {
+                keys.insert(meta.key);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// The current version, delete markers included. Files from before lumi kept metadata
+/// get a made up record from the filesystem (and no etag).
+async fn current_unlocked(bucket: &str, key: &str) -> io::Result<Option<ObjectMeta>> {
+    let Some(path) = object_path(bucket, key) else {
+        return Ok(None);
+    };
+    if let Some(meta) = read_meta(bucket, key).await? {
+        return Ok(Some(meta));
+    }
+    let file_meta = match fs::metadata(&path).await {
+        Ok(m) if m.is_file() => m,
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(ObjectMeta {
+        key: key.to_string(),
+        size: file_meta.len(),
+        etag: String::new(),
+        last_modified: file_meta
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now()),
+        version_id: None,
+        delete_marker: false,
+        data: None,
+        encryption: None,
+        parts: Vec::new(),
+        retention: None,
+        legal_hold: false,
+        tags: BTreeMap::new(),
+        content: ContentMeta::default(),
+    }))
+}
+
+/// Writes `contents` to a temp file and renames it into place.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path();
+    fs::create_dir_all(tmp.parent().unwrap()).await?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(&tmp, contents).await?;
+    if let Err(e) = fs::rename(&tmp, path).await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// When a write is allowed to replace what is there. Checked under the object's write
+/// lock so two racing conditional writes can't both win.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum WriteCondition {
+    #[default]
+    Always,
+    /// `If-None-Match: *`, create only
+    NotExists,
+    /// `If-Match`, the current etag has to be one of these (or `*` for any)
+    EtagMatches(Vec<String>),
+    /// still the version that was looked at earlier, for decisions made on a snapshot
+    Unchanged {
+        etag: String,
+        last_modified: DateTime<Utc>,
+    },
+}
+
+impl WriteCondition {
+    fn check(&self, current: Option<&ObjectMeta>) -> Result<(), ErrorCode> {
+        match (self, current) {
+            (WriteCondition::Always, _) => Ok(()),
+            (WriteCondition::NotExists, None) => Ok(()),
+            (WriteCondition::NotExists, Some(_)) => Err(ErrorCode::PreconditionFailed),
+            (WriteCondition::EtagMatches(_), None) => Err(ErrorCode::NoSuchKey),
+            (WriteCondition::EtagMatches(etags), Some(current)) => {
+                if etags.iter().any(|e| e == "*" || *e == current.etag) {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::PreconditionFailed)
+                }
+            }
+            (WriteCondition::Unchanged { .. }, None) => Err(ErrorCode::NoSuchKey),
+            (WriteCondition::Unchanged { etag, last_modified }, Some(current)) => {
+                if *etag == current.etag && *last_modified == current.last_modified {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::PreconditionFailed)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    Io(io::Error),
+    /// the write condition didnt hold, nothing was written
+    Condition(ErrorCode),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> Self {
+        WriteError::Io(e)
+    }
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::Io(e) => write!(f, "{}", e),
+            WriteError::Condition(code) => write!(f, "write condition failed: {:?}", code),
+        }
+    }
+}
+
+/// `payload` is what goes on disk, already encrypted if `meta` says so.
+pub async fn write_object(
+    bucket: &str,
+    meta: &ObjectMeta,
+    payload: &[u8],
+    condition: &WriteCondition,
+) -> Result<(), WriteError> {
+    let tmp = tmp_path();
+    fs::create_dir_all(tmp.parent().unwrap()).await?;
+    fs::write(&tmp, payload).await?;
+    commit_object(bucket, meta, &tmp, condition).await
+}
+
+/// Moves an already written data file in under a name of its own and commits `meta`
+/// (which gets that name) as the current version.
+pub async fn commit_object(
+    bucket: &str,
+    meta: &ObjectMeta,
+    data: &Path,
+    condition: &WriteCondition,
+) -> Result<(), WriteError> {
+    let mut meta = meta.clone();
+    let id = new_data_id();
+    let path = data_file(bucket, &meta.key, &id);
+    meta.data = Some(id);
+    let result = match stage_data(data, &path).await {
+        Ok(()) => replace_current(bucket, &meta, condition).await,
+        Err(e) => Err(e.into()),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(data).await;
+        let _ = fs::remove_file(&path).await;
+    }
+    result
+}
+
+async fn stage_data(data: &Path, path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap()).await?;
+    fs::rename(data, path).await
+}
+
+/// Makes `meta` the current version, its data file (if it isnt a delete marker) already
+/// in place. What was current before is kept as a noncurrent version if either of them
+/// has a version id, a null version replacing the null version just overwrites it.
+/// Writing the metadata is the commit point, anything that happens before it leaves the
+/// old version current and anything after it is cleaning up.
+async fn replace_current(bucket: &str, meta: &ObjectMeta, condition: &WriteCondition) -> Result<(), WriteError> {
+    if object_path(bucket, &meta.key).is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid object key").into());
+    }
+    let raw = serde_json::to_vec(meta).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let _guard = key_lock(bucket, &meta.key).write().await;
+    let current = current_unlocked(bucket, &meta.key).await?;
+    condition
+        .check(current.as_ref().filter(|c| !c.delete_marker))
+        .map_err(WriteError::Condition)?;
+    let archive = current
+        .as_ref()
+        .filter(|c| c.version_id.is_some() || meta.version_id.is_some());
+    // whatever gets overwritten instead of archived has to be free of locks
+    if archive.is_none()
+        && let Some(current) = &current
+    {
+        object_lock::check_removable(current, false).map_err(WriteError::Condition)?;
+    }
+    if meta.version_id.is_none()
+        && let Some(null) = versioning::load(bucket, &meta.key, NULL_VERSION).await?
+    {
+        object_lock::check_removable(&null, false).map_err(WriteError::Condition)?;
+    }
+    if let Some(current) = archive {
+        versioning::archive(bucket, current, current_data_path(bucket, current)).await?;
+    }
+    write_atomic(&meta_path(bucket, &meta.key), &raw).await?;
+
+    if meta.version_id.is_none() {
+        // there is only ever one null version
+        versioning::remove(bucket, &meta.key, NULL_VERSION, false).await?;
+    }
+    if let Some(current) = &current {
+        // archived versions took their own link to anything in the bucket tree
+        let legacy = current.data.is_none();
+        if (archive.is_none() || legacy)
+            && !current.delete_marker
+            && let Some(path) = current_data_path(bucket, current)
+        {
+            remove_data(bucket, &path, legacy).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes a data file that is no longer referenced, `legacy` ones sit in the bucket tree.
+async fn remove_data(bucket: &str, path: &Path, legacy: bool) -> io::Result<()> {
+    remove_if_exists(path).await?;
+    match legacy {
+        true => prune_empty_dirs(bucket, path).await,
+        // the key's dir goes with its last data file, never mind if something is left
+        false => {
+            let _ = fs::remove_dir(path.parent().unwrap()).await;
+        }
+    }
+    Ok(())
+}
+
+async fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the dirs above `path` that are left empty, up to the bucket itself.
+async fn prune_empty_dirs(bucket: &str, path: &Path) {
+    let Some(root) = bucket_path(bucket) else {
+        return;
+    };
+    let mut dir = path.parent();
+    while let Some(parent) = dir
+        && parent != root
+    {
+        // fails on the first dir that still has something in it, which is where we stop
+        if fs::remove_dir(parent).await.is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+/// What a delete did, for the x-amz-version-id / x-amz-delete-marker headers.
+#[derive(Debug, Default, PartialEq)]
+pub struct Deletion {
+    pub version_id: Option<String>,
+    pub delete_marker: bool,
+}
+
+/// DeleteObject. Without `version_id` an unversioned bucket loses the object for good
+/// and a versioned one gets a delete marker on top, with one just that version goes
+/// (unless object lock says otherwise). Deleting something that isnt there is not an error,
+/// unless `condition` (checked against the version being deleted) asks for it to exist.
+pub async fn delete_object(
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+    bypass_governance: bool,
+    condition: &WriteCondition,
+) -> Result<Deletion, WriteError> {
+    if object_path(bucket, key).is_none() {
+        return Ok(Deletion::default());
+    }
+    if let Some(id) = version_id {
+        return delete_version(bucket, key, id, bypass_governance, condition).await;
+    }
+    let marker_id = match versioning::status(bucket).await? {
+        Versioning::Unversioned => {
+            let _guard = key_lock(bucket, key).write().await;
+            let current = current_unlocked(bucket, key).await?;
+            condition.check(current.as_ref()).map_err(WriteError::Condition)?;
+            if let Some(current) = current {
+                remove_current_unlocked(bucket, &current).await?;
+            }
+            return Ok(Deletion::default());
+        }
+        Versioning::Enabled => Some(versioning::new_version_id()),
+        Versioning::Suspended => None,
+    };
+    let marker = ObjectMeta::delete_marker(key, marker_id);
+    replace_current(bucket, &marker, condition).await?;
+    Ok(Deletion {
+        version_id: Some(versioning::version_name(&marker).to_string()),
+        delete_marker: true,
+    })
+}
+
+async fn delete_version(
+    bucket: &str,
+    key: &str,
+    id: &str,
+    bypass_governance: bool,
+    condition: &WriteCondition,
+) -> Result<Deletion, WriteError> {
+    let mut deletion = Deletion {
+        version_id: Some(id.to_string()),
+        delete_marker: false,
+    };
+    let _guard = key_lock(bucket, key).write().await;
+    match current_unlocked(bucket, key).await? {
+        Some(current) if versioning::version_name(&current) == id => {
+            condition.check(Some(&current)).map_err(WriteError::Condition)?;
+            object_lock::check_removable(&current, bypass_governance).map_err(WriteError::Condition)?;
+            deletion.delete_marker = current.delete_marker;
+            // the newest noncurrent version takes its place
+            match versioning::list(bucket, key).await?.into_iter().next() {
+                Some(mut newest) => {
+                    versioning::promote(bucket, &mut newest).await?;
+                    let raw = serde_json::to_vec(&newest)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    write_atomic(&meta_path(bucket, key), &raw).await?;
+                    let name = versioning::version_name(&newest).to_string();
+                    versioning::remove(bucket, key, &name, true).await?;
+                    if !current.delete_marker
+                        && let Some(path) = current_data_path(bucket, &current)
+                    {
+                        remove_data(bucket, &path, current.data.is_none()).await?;
+                    }
+                }
+                None => remove_current_unlocked(bucket, &current).await?,
+            }
+        }
+        _ => {
+            let version = versioning::load(bucket, key, id).await?;
+            condition.check(version.as_ref()).map_err(WriteError::Condition)?;
+            if let Some(version) = version {
+                object_lock::check_removable(&version, bypass_governance)
+                    .map_err(WriteError::Condition)?;
+            }
+            if let Some(removed) = versioning::remove(bucket, key, id, false).await? {
+                deletion.delete_marker = removed.delete_marker;
+            }
+        }
+    }
+    Ok(deletion)
+}
+
+/// Rewrites the metadata of one version (the latest if `version_id` is `None`) in place,
+/// for the settings that can change after the fact like retention and legal holds.
+/// `update` sees the version as it is under the write lock and can still refuse.
+pub async fn update_version<F>(
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+    update: F,
+) -> Result<Option<ObjectMeta>, WriteError>
+where
+    F: FnOnce(&mut ObjectMeta) -> Result<(), ErrorCode>,
+{
+    let _guard = key_lock(bucket, key).write().await;
+    let current = current_unlocked(bucket, key).await?;
+    let (mut meta, is_current) = match (current, version_id) {
+        (Some(current), None) => (current, true),
+        (Some(current), Some(id)) if versioning::version_name(&current) == id => (current, true),
+        (None, None) => return Ok(None),
+        (_, Some(id)) => match versioning::load(bucket, key, id).await? {
+            Some(meta) => (meta, false),
+            None => return Ok(None),
+        },
+    };
+    update(&mut meta).map_err(WriteError::Condition)?;
+    match is_current {
+        true => {
+            let raw = serde_json::to_vec(&meta).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            write_atomic(&meta_path(bucket, key), &raw).await?;
+        }
+        false => versioning::rewrite(bucket, &meta).await?,
+    }
+    Ok(Some(meta))
+}
+
+async fn remove_current_unlocked(bucket: &str, current: &ObjectMeta) -> io::Result<()> {
+    remove_if_exists(&meta_path(bucket, &current.key)).await?;
+    if !current.delete_marker
+        && let Some(path) = current_data_path(bucket, current)
+    {
+        remove_data(bucket, &path, current.data.is_none()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_meta_roundtrip() {
+        let mut request = HeaderMap::new();
+        request.insert("content-type", "text/html; charset=utf-8".parse().unwrap());
+        request.insert("cache-control", "max-age=60".parse().unwrap());
+        request.insert("x-amz-meta-Author", "lumi".parse().unwrap());
+        request.insert("authorization", "AWS4-HMAC-SHA256 ...".parse().unwrap());
+        let meta = ContentMeta::from_headers(&request).unwrap();
+        assert_eq!(meta.user.get("author").map(String::as_str), Some("lumi"));
+
+        let mut response = HeaderMap::new();
+        meta.apply(&mut response);
+        assert_eq!(response["content-type"], "text/html; charset=utf-8");
+        assert_eq!(response["cache-control"], "max-age=60");
+        assert_eq!(response["x-amz-meta-author"], "lumi");
+        assert!(!response.contains_key("authorization"));
+
+        let mut empty = HeaderMap::new();
+        ContentMeta::default().apply(&mut empty);
+        assert_eq!(empty["content-type"], DEFAULT_CONTENT_TYPE);
+
+        request.insert("x-amz-meta-big", "x".repeat(MAX_USER_META_SIZE).parse().unwrap());
+        assert_eq!(ContentMeta::from_headers(&request), Err(ErrorCode::MetadataTooLarge));
+    }
+
+    #[tokio::test]
+    async fn test_sys_dir_is_not_a_bucket() {
+        assert!(!sys_dir().starts_with(data_dir()));
+        // even a system dir left inside the data dir, like older versions did
+        let old = data_dir().join(".lumi.sys");
+        fs::create_dir_all(&old).await.unwrap();
+        fs::write(old.join("sse-master.key"), "secret").await.unwrap();
+        assert!(!bucket_exists(".lumi.sys").await);
+        assert_eq!(object_path(".lumi.sys", "sse-master.key"), None);
+        assert!(!bucket_names().await.unwrap().contains(&".lumi.sys".to_string()));
+        fs::remove_dir_all(&old).await.unwrap();
+
+        for name in ["ab", ".hidden", "-dash", "dots..", "trailing.", "Upper", &"a".repeat(64)] {
+            assert!(!valid_bucket_name(name), "{}", name);
+        }
+        for name in ["abc", "my-bucket", "logs.example.com", "1bucket"] {
+            assert!(valid_bucket_name(name), "{}", name);
+        }
+    }
+
+    fn object(key: &str, version_id: Option<String>) -> ObjectMeta {
+        ObjectMeta {
+            delete_marker: false,
+            ..ObjectMeta::delete_marker(key, version_id)
+        }
+    }
+
+    async fn read_current(bucket: &str, key: &str) -> Option<Vec<u8>> {
+        let (_, file) = open_object(bucket, key, None).await.unwrap()?;
+        let mut data = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut file?, &mut data).await.unwrap();
+        Some(data)
+    }
+
+    async fn data_files(bucket: &str, key: &str) -> usize {
+        let dir = data_file(bucket, key, "x").parent().unwrap().to_path_buf();
+        match std::fs::read_dir(dir) {
+            Ok(entries) => entries.count(),
+            Err(_) => 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metadata_commits_a_write() {
+        let bucket = "commit-point";
+        fs::create_dir_all(data_dir().join(bucket).join("dir")).await.unwrap();
+        // a plain file from before lumi kept metadata
+        fs::write(data_dir().join(bucket).join("dir/k"), "old").await.unwrap();
+        assert_eq!(read_current(bucket, "dir/k").await.unwrap(), b"old");
+
+        let mut first = object("dir/k", None);
+        first.etag = "first".into();
+        write_object(bucket, &first, b"first", &WriteCondition::Always).await.unwrap();
+        assert_eq!(read_current(bucket, "dir/k").await.unwrap(), b"first");
+        assert!(!data_dir().join(bucket).join("dir").exists());
+        assert_eq!(data_files(bucket, "dir/k").await, 1);
+
+        // a write that loses its condition leaves nothing behind
+        let result = write_object(bucket, &object("dir/k", None), b"second", &WriteCondition::NotExists).await;
+        assert!(matches!(result, Err(WriteError::Condition(ErrorCode::PreconditionFailed))));
+        assert_eq!(read_current(bucket, "dir/k").await.unwrap(), b"first");
+        assert_eq!(data_files(bucket, "dir/k").await, 1);
+
+        let condition = WriteCondition::EtagMatches(vec!["first".into()]);
+        write_object(bucket, &object("dir/k", None), b"second", &condition).await.unwrap();
+        assert_eq!(read_current(bucket, "dir/k").await.unwrap(), b"second");
+        assert_eq!(data_files(bucket, "dir/k").await, 1);
+
+        delete_object(bucket, "dir/k", None, false, &WriteCondition::Always).await.unwrap();
+        assert!(read_current(bucket, "dir/k").await.is_none());
+        assert_eq!(data_files(bucket, "dir/k").await, 0);
+        fs::remove_dir_all(data_dir().join(bucket)).await.unwrap();
+        fs::remove_dir_all(bucket_sys_path(bucket)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_rechecks_what_was_seen() {
+        let bucket = "delete-unchanged";
+        fs::create_dir_all(data_dir().join(bucket)).await.unwrap();
+        write_object(bucket, &object("k", None), b"one", &WriteCondition::Always).await.unwrap();
+        let seen = stat_object(bucket, "k").await.unwrap().unwrap();
+        let unchanged = WriteCondition::Unchanged {
+            etag: seen.etag.clone(),
+            last_modified: seen.last_modified,
+        };
+        // replaced between the look and the delete, same etag or not
+        write_object(bucket, &object("k", None), b"two", &WriteCondition::Always).await.unwrap();
+        let result = delete_object(bucket, "k", None, false, &unchanged).await;
+        assert!(matches!(result, Err(WriteError::Condition(ErrorCode::PreconditionFailed))));
+        assert_eq!(read_current(bucket, "k").await.unwrap(), b"two");
+
+        let seen = stat_object(bucket, "k").await.unwrap().unwrap();
+        let unchanged = WriteCondition::Unchanged {
+            etag: seen.etag,
+            last_modified: seen.last_modified,
+        };
+        delete_object(bucket, "k", None, false, &unchanged).await.unwrap();
+        assert!(read_current(bucket, "k").await.is_none());
+        fs::remove_dir_all(data_dir().join(bucket)).await.unwrap();
+        fs::remove_dir_all(bucket_sys_path(bucket)).await.unwrap();
+    }
+
+    async fn versioned_bucket(bucket: &str, status: &str) {
+        fs::create_dir_all(data_dir().join(bucket)).await.unwrap();
+        set_versioning(bucket, status).await;
+    }
+
+    async fn set_versioning(bucket: &str, status: &str) {
+        let mut cfg = config::load(bucket).await.unwrap();
+        cfg.versioning = Some(crate::xml::VersioningConfiguration {
+            status: Some(status.into()),
+            mfa_delete: None,
+        });
+        config::save(bucket, &cfg).await.unwrap();
+    }
+
+    /// Writes `body` the way PutObject would in the bucket's current versioning state.
+    async fn put(bucket: &str, key: &str, body: &str) -> String {
+        let version_id = match versioning::status(bucket).await.unwrap() {
+            Versioning::Enabled => Some(versioning::new_version_id()),
+            _ => None,
+        };
+        let meta = object(key, version_id);
+        write_object(bucket, &meta, body.as_bytes(), &WriteCondition::Always).await.unwrap();
+        versioning::version_name(&meta).to_string()
+    }
+
+    async fn read_version(bucket: &str, key: &str, id: &str) -> Option<Vec<u8>> {
+        let (_, file) = open_object(bucket, key, Some(id)).await.unwrap()?;
+        let mut data = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut file?, &mut data).await.unwrap();
+        Some(data)
+    }
+
+    async fn version_names(bucket: &str, key: &str) -> Vec<String> {
+        let versions = object_versions(bucket, key).await.unwrap();
+        versions.iter().map(|v| versioning::version_name(v).to_string()).collect()
+    }
+
+    async fn cleanup(bucket: &str) {
+        fs::remove_dir_all(data_dir().join(bucket)).await.unwrap();
+        fs::remove_dir_all(bucket_sys_path(bucket)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_archives_the_current_version() {
+        let bucket = "ver-archive";
+        fs::create_dir_all(data_dir().join(bucket)).await.unwrap();
+        put(bucket, "k", "v0").await;
+        set_versioning(bucket, versioning::ENABLED).await;
+        let v1 = put(bucket, "k", "v1").await;
+        let v2 = put(bucket, "k", "v2").await;
+
+        assert_eq!(version_names(bucket, "k").await, [v2.as_str(), v1.as_str(), NULL_VERSION]);
+        assert_eq!(read_current(bucket, "k").await.unwrap(), b"v2");
+        assert_eq!(read_version(bucket, "k", &v2).await.unwrap(), b"v2");
+        assert_eq!(read_version(bucket, "k", &v1).await.unwrap(), b"v1");
+        assert_eq!(read_version(bucket, "k", NULL_VERSION).await.unwrap(), b"v0");
+        assert_eq!(data_files(bucket, "k").await, 3);
+        cleanup(bucket).await;
+    }
+
+    #[tokio::test]
+    async fn test_suspended_bucket_overwrites_the_null_version() {
+        let bucket = "ver-suspended";
+        versioned_bucket(bucket, versioning::ENABLED).await;
+        let v1 = put(bucket, "k", "v1").await;
+        set_versioning(bucket, versioning::SUSPENDED).await;
+        assert_eq!(put(bucket, "k", "n1").await, NULL_VERSION);
+        put(bucket, "k", "n2").await;
+        assert_eq!(version_names(bucket, "k").await, [NULL_VERSION, v1.as_str()]);
+        assert_eq!(read_current(bucket, "k").await.unwrap(), b"n2");
+        assert_eq!(data_files(bucket, "k").await, 2);
+
+        // a noncurrent null version goes too, there is only ever one
+        set_versioning(bucket, versioning::ENABLED).await;
+        let v3 = put(bucket, "k", "v3").await;
+        assert_eq!(version_names(bucket, "k").await, [v3.as_str(), NULL_VERSION, v1.as_str()]);
+        set_versioning(bucket, versioning::SUSPENDED).await;
+        put(bucket, "k", "n3").await;
+        assert_eq!(version_names(bucket, "k").await, [NULL_VERSION, v3.as_str(), v1.as_str()]);
+        assert_eq!(read_version(bucket, "k", NULL_VERSION).await.unwrap(), b"n3");
+        assert_eq!(data_files(bucket, "k").await, 3);
+        cleanup(bucket).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_markers() {
+        let bucket = "ver-markers";
+        versioned_bucket(bucket, versioning::ENABLED).await;
+        let v1 = put(bucket, "k", "v1").await;
+        let deletion = delete_object(bucket, "k", None, false, &WriteCondition::Always).await.unwrap();
+        assert!(deletion.delete_marker);
+        let marker = deletion.version_id.unwrap();
+        assert_eq!(version_names(bucket, "k").await, [marker.as_str(), v1.as_str()]);
+        assert!(stat_object(bucket, "k").await.unwrap().is_none());
+        let (meta, file) = open_object(bucket, "k", None).await.unwrap().unwrap();
+        assert!(meta.delete_marker && file.is_none());
+        assert!(list_keys(bucket, "").await.unwrap().contains("k"));
+        assert_eq!(read_version(bucket, "k", &v1).await.unwrap(), b"v1");
+
+        // deleting the marker brings the object back
+        let deletion = delete_object(bucket, "k", Some(&marker), false, &WriteCondition::Always).await.unwrap();
+        assert_eq!(deletion.version_id.as_deref(), Some(marker.as_str()));
+        assert!(deletion.delete_marker);
+        assert_eq!(read_current(bucket, "k").await.unwrap(), b"v1");
+
+        // suspended buckets get a null marker
+        set_versioning(bucket, versioning::SUSPENDED).await;
+        let deletion = delete_object(bucket, "k", None, false, &WriteCondition::Always).await.unwrap();
+        assert_eq!(deletion.version_id.as_deref(), Some(NULL_VERSION));
+        assert_eq!(version_names(bucket, "k").await, [NULL_VERSION, v1.as_str()]);
+        assert_eq!(data_files(bucket, "k").await, 1);
+        cleanup(bucket).await;
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_by_prefix() {
+        let bucket = "list-prefix";
+        let root = data_dir().join(bucket);
+        for key in ["docs/a", "docs/old/b", "img/c", "dogs"] {
+            let path = root.join(key);
+            fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+            fs::write(path, "legacy").await.unwrap();
+        }
+        put(bucket, "docs/new", "v1").await;
+        put(bucket, "img/d", "v1").await;
+
+        let keys = |prefix: &'static str| async move {
+            list_keys(bucket, prefix).await.unwrap().into_iter().collect::<Vec<_>>()
+        };
+        assert_eq!(keys("docs/").await, ["docs/a", "docs/new", "docs/old/b"]);
+        assert_eq!(keys("do").await, ["docs/a", "docs/new", "docs/old/b", "dogs"]);
+        assert_eq!(keys("docs/old/").await, ["docs/old/b"]);
+        assert_eq!(keys("img/d").await, ["img/d"]);
+        assert_eq!(keys("").await.len(), 6);
+        cleanup(bucket).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_version_promotes_the_newest_noncurrent() {
+        let bucket = "ver-promote";
+        versioned_bucket(bucket, versioning::ENABLED).await;
+        let v1 = put(bucket, "k", "v1").await;
+        let v2 = put(bucket, "k", "v2").await;
+        let v3 = put(bucket, "k", "v3").await;
+
+        delete_object(bucket, "k", Some(&v3), false, &WriteCondition::Always).await.unwrap();
+        assert_eq!(version_names(bucket, "k").await, [v2.as_str(), v1.as_str()]);
+        assert_eq!(read_current(bucket, "k").await.unwrap(), b"v2");
+        assert!(read_version(bucket, "k", &v3).await.is_none());
+        assert_eq!(data_files(bucket, "k").await, 2);
+
+        delete_object(bucket, "k", Some(&v1), false, &WriteCondition::Always).await.unwrap();
+        assert_eq!(version_names(bucket, "k").await, [v2.as_str()]);
+        assert_eq!(read_current(bucket, "k").await.unwrap(), b"v2");
+
+        delete_object(bucket, "k", Some(&v2), false, &WriteCondition::Always).await.unwrap();
+        assert!(version_names(bucket, "k").await.is_empty());
+        assert!(!list_keys(bucket, "").await.unwrap().contains("k"));
+        assert_eq!(data_files(bucket, "k").await, 0);
+        cleanup(bucket).await;
+    }
+}
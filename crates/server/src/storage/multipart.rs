@@ -0,0 +1,309 @@
+// in-progress multipart uploads, one dir per upload under the bucket's sys dir. parts sit
+// there as they come in (already encrypted if the upload is) and only get stitched into
+// the real object on complete, so a half done upload is never visible.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tokio::fs;
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::object_lock::Retention;
+use super::sse::ObjectEncryption;
+use super::{
+    bucket_sys_path, commit_object, tmp_path, write_atomic, ContentMeta, ObjectMeta, PartMeta,
+    WriteCondition, WriteError,
+};
+
+pub const MAX_PART_NUMBER: u32 = 10_000;
+/// every part but the last has to be at least this big
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+pub const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub key: String,
+    pub initiated: DateTime<Utc>,
+    /// decided at create time, every part is sealed with the same data key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<ObjectEncryption>,
+    /// object lock settings from the create request (or the bucket default back then)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub legal_hold: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// headers and user metadata from the create request, they end up on the object
+    #[serde(default)]
+    pub content: ContentMeta,
+}
+
+/// Upload ids end up in paths, anything that isnt our own hex is rejected outright.
+fn upload_dir(bucket: &str, upload_id: &str) -> Option<PathBuf> {
+    if upload_id.is_empty() || !upload_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(bucket_sys_path(bucket).join("uploads").join(upload_id))
+}
+
+fn part_meta_path(dir: &Path, number: u32) -> PathBuf {
+    dir.join(format!("{:05}.json", number))
+}
+
+/// Data file the part's metadata points at.
+fn part_data_path(dir: &Path, part: &PartMeta) -> PathBuf {
+    match &part.file {
+        Some(file) => dir.join(file),
+        None => dir.join(format!("{:05}.part", part.number)),
+    }
+}
+
+// UploadPart, UploadPartCopy, Complete and Abort of the same upload take one of these for
+// the whole request so a part can't be swapped out while complete is joining the parts and
+// two completes can't both commit. hashed like the key locks in the parent module.
+static UPLOAD_LOCKS: LazyLock<[Mutex<()>; 64]> = LazyLock::new(|| std::array::from_fn(|_| Mutex::new(())));
+
+pub async fn lock(bucket: &str, upload_id: &str) -> MutexGuard<'static, ()> {
+    let hash = Sha256::new()
+        .chain_update(bucket.as_bytes())
+        .chain_update([0])
+        .chain_update(upload_id.as_bytes())
+        .finalize();
+    UPLOAD_LOCKS[hash[0] as usize % UPLOAD_LOCKS.len()].lock().await
+}
+
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+pub async fn create(bucket: &str, upload: &Upload) -> io::Result<String> {
+    let upload_id = hex::encode(lumi_credentials::envelope::random_bytes::<16>());
+    let dir = upload_dir(bucket, &upload_id).unwrap();
+    let raw = serde_json::to_vec(upload).map_err(invalid_data)?;
+    write_atomic(&dir.join("upload.json"), &raw).await?;
+    Ok(upload_id)
+}
+
+/// `Ok(None)` if there is no such upload.
+pub async fn load(bucket: &str, upload_id: &str) -> io::Result<Option<Upload>> {
+    let Some(dir) = upload_dir(bucket, upload_id) else {
+        return Ok(None);
+    };
+    match fs::read(dir.join("upload.json")).await {
+        Ok(raw) => serde_json::from_slice(&raw).map(Some).map_err(invalid_data),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Every upload in progress in the bucket, by upload id.
+pub async fn list_uploads(bucket: &str) -> io::Result<Vec<(String, Upload)>> {
+    let mut uploads = Vec::new();
+    let mut entries = match fs::read_dir(bucket_sys_path(bucket).join("uploads")).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(uploads),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let upload_id = entry.file_name().to_string_lossy().to_string();
+        if let Some(upload) = load(bucket, &upload_id).await? {
+            uploads.push((upload_id, upload));
+        }
+    }
+    Ok(uploads)
+}
+
+/// Stores one part, replacing any earlier upload of the same part number.
+pub async fn write_part(bucket: &str, upload_id: &str, part: &PartMeta, payload: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path();
+    fs::create_dir_all(tmp.parent().unwrap()).await?;
+    fs::write(&tmp, payload).await?;
+    let result = commit_part(bucket, upload_id, part, &tmp).await;
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp).await;
+    }
+    result
+}
+
+/// Like `write_part` but moves an already written file into place, used by UploadPartCopy.
+/// Every write gets its own data file and the part's json is what switches to it, so the
+/// metadata never describes another write's data. Caller holds the upload's `lock`.
+pub async fn commit_part(bucket: &str, upload_id: &str, part: &PartMeta, data: &Path) -> io::Result<()> {
+    let dir = upload_dir(bucket, upload_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid upload id"))?;
+    // completed or aborted while the part was coming in, dont bring the dir back
+    fs::metadata(dir.join("upload.json")).await?;
+    let meta = part_meta_path(&dir, part.number);
+    let previous = match fs::read(&meta).await {
+        Ok(raw) => serde_json::from_slice::<PartMeta>(&raw).ok(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let mut part = part.clone();
+    let nonce = hex::encode(lumi_credentials::envelope::random_bytes::<8>());
+    part.file = Some(format!("{:05}.{}.part", part.number, nonce));
+    let path = part_data_path(&dir, &part);
+    let raw = serde_json::to_vec(&part).map_err(invalid_data)?;
+    fs::rename(data, &path).await?;
+    if let Err(e) = write_atomic(&meta, &raw).await {
+        let _ = fs::remove_file(&path).await;
+        return Err(e);
+    }
+    if let Some(previous) = previous {
+        let _ = fs::remove_file(part_data_path(&dir, &previous)).await;
+    }
+    Ok(())
+}
+
+/// Parts uploaded so far, by part number.
+pub async fn list_parts(bucket: &str, upload_id: &str) -> io::Result<Vec<PartMeta>> {
+    let Some(dir) = upload_dir(bucket, upload_id) else {
+        return Ok(Vec::new());
+    };
+    let mut parts = Vec::new();
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == "upload.json" || !name.ends_with(".json") {
+            continue;
+        }
+        let raw = fs::read(entry.path()).await?;
+        parts.push(serde_json::from_slice::<PartMeta>(&raw).map_err(invalid_data)?);
+    }
+    parts.sort_by_key(|p| p.number);
+    Ok(parts)
+}
+
+/// Concatenates the chosen parts into the object and drops the upload.
+/// `meta.parts` decides which part files are used and in what order, each part is read
+/// from the file its metadata names. If `condition` fails the upload is left alone so the
+/// client can retry or abort it. Caller holds the upload's `lock`.
+pub async fn complete(
+    bucket: &str,
+    upload_id: &str,
+    meta: &ObjectMeta,
+    condition: &WriteCondition,
+) -> Result<(), WriteError> {
+    let dir = upload_dir(bucket, upload_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid upload id"))?;
+    let tmp = tmp_path();
+    fs::create_dir_all(tmp.parent().unwrap()).await?;
+    let assembled = async {
+        let mut out = fs::File::create(&tmp).await?;
+        for part in &meta.parts {
+            let mut input = fs::File::open(part_data_path(&dir, part)).await?;
+            tokio::io::copy(&mut input, &mut out).await?;
+        }
+        out.sync_all().await
+    }
+    .await;
+    if let Err(e) = assembled {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+    // the file names only mean something inside the upload dir
+    let mut meta = meta.clone();
+    meta.parts.iter_mut().for_each(|part| part.file = None);
+    commit_object(bucket, &meta, &tmp, condition).await?;
+    fs::remove_dir_all(dir).await?;
+    Ok(())
+}
+
+pub async fn abort(bucket: &str, upload_id: &str) -> io::Result<()> {
+    match upload_dir(bucket, upload_id) {
+        Some(dir) => fs::remove_dir_all(dir).await,
+        None => Err(io::Error::from(io::ErrorKind::NotFound)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{data_dir, open_object};
+
+    fn part(number: u32, data: &[u8]) -> PartMeta {
+        PartMeta {
+            number,
+            size: data.len() as u64,
+            etag: format!("{:x}", md5::compute(data)),
+            last_modified: Utc::now(),
+            nonce_base: None,
+            file: None,
+        }
+    }
+
+    fn object(key: &str, parts: Vec<PartMeta>) -> ObjectMeta {
+        ObjectMeta {
+            size: parts.iter().map(|p| p.size).sum(),
+            delete_marker: false,
+            parts,
+            ..ObjectMeta::delete_marker(key, None)
+        }
+    }
+
+    async fn new_upload(bucket: &str, key: &str) -> String {
+        let upload = Upload {
+            key: key.to_string(),
+            initiated: Utc::now(),
+            encryption: None,
+            retention: None,
+            legal_hold: false,
+            tags: BTreeMap::new(),
+            content: ContentMeta::default(),
+        };
+        create(bucket, &upload).await.unwrap()
+    }
+
+    async fn read_current(bucket: &str, key: &str) -> Vec<u8> {
+        let (_, file) = open_object(bucket, key, None).await.unwrap().unwrap();
+        let mut data = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut file.unwrap(), &mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_reupload_during_complete() {
+        let bucket = "mpu-reupload";
+        fs::create_dir_all(data_dir().join(bucket)).await.unwrap();
+
+        // complete only ever joins the files named in the parts it checked
+        let upload_id = new_upload(bucket, "stale").await;
+        write_part(bucket, &upload_id, &part(1, b"first"), b"first").await.unwrap();
+        let checked = list_parts(bucket, &upload_id).await.unwrap();
+        write_part(bucket, &upload_id, &part(1, b"second"), b"second").await.unwrap();
+        let result = complete(bucket, &upload_id, &object("stale", checked), &WriteCondition::Always).await;
+        assert!(matches!(result, Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::NotFound));
+        let parts = list_parts(bucket, &upload_id).await.unwrap();
+        assert_eq!(parts.len(), 1);
+        complete(bucket, &upload_id, &object("stale", parts), &WriteCondition::Always).await.unwrap();
+        assert_eq!(read_current(bucket, "stale").await, b"second");
+
+        // and a re-upload that comes in while complete holds the lock waits it out
+        let upload_id = new_upload(bucket, "k").await;
+        write_part(bucket, &upload_id, &part(1, b"first"), b"first").await.unwrap();
+        let guard = lock(bucket, &upload_id).await;
+        let checked = list_parts(bucket, &upload_id).await.unwrap();
+        let reupload = tokio::spawn({
+            let upload_id = upload_id.clone();
+            async move {
+                let _guard = lock(bucket, &upload_id).await;
+                write_part(bucket, &upload_id, &part(1, b"second"), b"second").await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        complete(bucket, &upload_id, &object("k", checked), &WriteCondition::Always).await.unwrap();
+        drop(guard);
+        let e = reupload.await.unwrap().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert_eq!(read_current(bucket, "k").await, b"first");
+        assert!(load(bucket, &upload_id).await.unwrap().is_none());
+        assert!(!upload_dir(bucket, &upload_id).unwrap().exists());
+
+        fs::remove_dir_all(data_dir().join(bucket)).await.unwrap();
+        fs::remove_dir_all(bucket_sys_path(bucket)).await.unwrap();
+    }
+}
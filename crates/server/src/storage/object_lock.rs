@@ -0,0 +1,179 @@
+// WORM retention and legal holds. both live on the version they protect, in its
+// ObjectMeta, so they follow it when it becomes noncurrent. a locked version can still
+// be hidden behind a delete marker, it just cant be removed for good.
+use chrono::{DateTime, Duration, Months, Utc};
+use lumi_utils::errors::ErrorCode;
+use serde::{Deserialize, Serialize};
+
+use super::ObjectMeta;
+use crate::xml::ObjectLockConfiguration;
+
+pub const ENABLED: &str = "Enabled";
+pub const MODE_HEADER: &str = "x-amz-object-lock-mode";
+pub const RETAIN_UNTIL_HEADER: &str = "x-amz-object-lock-retain-until-date";
+pub const LEGAL_HOLD_HEADER: &str = "x-amz-object-lock-legal-hold";
+pub const BYPASS_GOVERNANCE_HEADER: &str = "x-amz-bypass-governance-retention";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RetentionMode {
+    /// removable early by identities allowed to bypass it
+    Governance,
+    /// nobody, root included, removes it before the date
+    Compliance,
+}
+
+impl RetentionMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "GOVERNANCE" => Some(RetentionMode::Governance),
+            "COMPLIANCE" => Some(RetentionMode::Compliance),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetentionMode::Governance => "GOVERNANCE",
+            RetentionMode::Compliance => "COMPLIANCE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Retention {
+    pub mode: RetentionMode,
+    pub retain_until: DateTime<Utc>,
+}
+
+impl Retention {
+    pub fn is_active(&self) -> bool {
+        self.retain_until > Utc::now()
+    }
+}
+
+pub fn is_enabled(config: Option<&ObjectLockConfiguration>) -> bool {
+    config.is_some_and(|c| c.object_lock_enabled.as_deref() == Some(ENABLED))
+}
+
+/// The retention a new object gets from the bucket's default rule, if it has one.
+pub fn default_retention(config: &ObjectLockConfiguration) -> Option<Retention> {
+    let default = &config.rule.as_ref()?.default_retention;
+    let mode = RetentionMode::parse(&default.mode)?;
+    let now = Utc::now();
+    let retain_until = match (default.days, default.years) {
+        (Some(days), None) => now + Duration::days(days.into()),
+        (None, Some(years)) => now.checked_add_months(Months::new(years * 12))?,
+        _ => return None,
+    };
+    Some(Retention { mode, retain_until })
+}
+
+/// Whether a version may be removed for good.
+pub fn check_removable(meta: &ObjectMeta, bypass_governance: bool) -> Result<(), ErrorCode> {
+    if meta.legal_hold {
+        return Err(ErrorCode::AccessDenied);
+    }
+    match meta.retention {
+        Some(retention) if retention.is_active() => match retention.mode {
+            RetentionMode::Governance if bypass_governance => Ok(()),
+            _ => Err(ErrorCode::AccessDenied),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Whether `new` may replace the retention a version has now. Extending is always fine,
+/// shortening or dropping it only for GOVERNANCE with a bypass.
+pub fn check_retention_change(
+    current: Option<&Retention>,
+    new: Option<&Retention>,
+    bypass_governance: bool,
+) -> Result<(), ErrorCode> {
+    let Some(current) = current.filter(|r| r.is_active()) else {
+        return Ok(());
+    };
+    let loosened = match new {
+        None => true,
+        Some(new) => new.retain_until < current.retain_until || new.mode != current.mode,
+    };
+    if !loosened {
+        return Ok(());
+    }
+    // GOVERNANCE -> COMPLIANCE only ever makes it stricter
+    if let Some(new) = new
+        && current.mode == RetentionMode::Governance
+        && new.mode == RetentionMode::Compliance
+        && new.retain_until >= current.retain_until
+    {
+        return Ok(());
+    }
+    match current.mode {
+        RetentionMode::Governance if bypass_governance => Ok(()),
+        _ => Err(ErrorCode::AccessDenied),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ContentMeta;
+
+    fn retention(mode: RetentionMode, days: i64) -> Retention {
+        Retention {
+            mode,
+            retain_until: Utc::now() + Duration::days(days),
+        }
+    }
+
+    #[test]
+    fn test_check_removable() {
+        let mut meta = ObjectMeta::delete_marker("k", None);
+        meta.delete_marker = false;
+        meta.content = ContentMeta::default();
+        assert_eq!(check_removable(&meta, false), Ok(()));
+
+        meta.retention = Some(retention(RetentionMode::Governance, 1));
+        assert_eq!(check_removable(&meta, false), Err(ErrorCode::AccessDenied));
+        assert_eq!(check_removable(&meta, true), Ok(()));
+
+        meta.retention = Some(retention(RetentionMode::Compliance, 1));
+        assert_eq!(check_removable(&meta, true), Err(ErrorCode::AccessDenied));
+        meta.retention = Some(retention(RetentionMode::Compliance, -1));
+        assert_eq!(check_removable(&meta, false), Ok(()));
+
+        meta.legal_hold = true;
+        assert_eq!(check_removable(&meta, true), Err(ErrorCode::AccessDenied));
+    }
+
+    #[test]
+    fn test_check_retention_change() {
+        let governance = retention(RetentionMode::Governance, 10);
+        let compliance = retention(RetentionMode::Compliance, 10);
+        let longer = Retention {
+            retain_until: governance.retain_until + Duration::days(1),
+            ..governance
+        };
+        let shorter = Retention {
+            retain_until: governance.retain_until - Duration::days(1),
+            ..governance
+        };
+        assert_eq!(check_retention_change(None, Some(&compliance), false), Ok(()));
+        assert_eq!(check_retention_change(Some(&governance), Some(&longer), false), Ok(()));
+        assert_eq!(
+            check_retention_change(Some(&governance), Some(&shorter), false),
+            Err(ErrorCode::AccessDenied)
+        );
+        assert_eq!(check_retention_change(Some(&governance), Some(&shorter), true), Ok(()));
+        assert_eq!(check_retention_change(Some(&governance), None, true), Ok(()));
+        assert_eq!(check_retention_change(Some(&governance), Some(&compliance), false), Ok(()));
+        assert_eq!(
+            check_retention_change(Some(&compliance), Some(&governance), true),
+            Err(ErrorCode::AccessDenied)
+        );
+        assert_eq!(
+            check_retention_change(Some(&compliance), None, true),
+            Err(ErrorCode::AccessDenied)
+        );
+    }
+}
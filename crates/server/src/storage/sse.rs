@@ -0,0 +1,546 @@
+// server side encryption for object data. every object gets a random data key which is
+// wrapped and kept in its metadata, the data itself is sealed in 64KiB AES-256-GCM chunks
+// so we can decrypt any byte range without touching the rest of the file.
+//
+// chunk nonce = nonce base xor (part number (u32, 0 for a single PUT) || chunk index (u64)).
+// a single PUT gets a data key of its own and an all zero base. the parts of a multipart
+// upload share the upload's key, so every part write gets a random base, otherwise
+// uploading the same part number again would reuse nonces. the last chunk of every part
+// is authenticated as "final" so a truncated file fails instead of reading short.
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use axum::{body::Bytes, http::HeaderMap};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::Stream;
+use lumi_credentials::envelope::{self, MasterKey, KEY_LEN};
+use lumi_utils::errors::ErrorCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+pub const CHUNK_SIZE: u64 = 64 * 1024;
+const TAG_LEN: u64 = 16;
+
+pub const SSE_HEADER: &str = "x-amz-server-side-encryption";
+pub const AES256: &str = "AES256";
+pub const AWS_KMS: &str = "aws:kms";
+pub const KMS_KEY_ID_HEADER: &str = "x-amz-server-side-encryption-aws-kms-key-id";
+/// SSE-C headers on the object itself, copies use the `x-amz-copy-source-` flavour
+pub const SSE_C_PREFIX: &str = "x-amz-server-side-encryption-customer-";
+pub const COPY_SOURCE_SSE_C_PREFIX: &str = "x-amz-copy-source-server-side-encryption-customer-";
+
+pub type DataKey = [u8; KEY_LEN];
+pub type NonceBase = [u8; 12];
+
+/// The base for data sealed under a key of its own.
+pub const OBJECT_NONCE_BASE: NonceBase = [0; 12];
+
+/// How an object's data key is protected, stored in its metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ObjectEncryption {
+    /// SSE-S3, data key wrapped by the server master key
+    SseS3 { wrapped_key: String },
+    /// SSE-C, data key wrapped by the customer's key. we only keep a salted hash of
+    /// that key to tell a wrong one apart from corrupted data
+    SseC {
+        salt: String,
+        fingerprint: String,
+        wrapped_key: String,
+    },
+    /// SSE-KMS, data key wrapped by a version of one of our own kms keys
+    Kms {
+        key_id: String,
+        key_version: u32,
+        wrapped_key: String,
+    },
+}
+
+impl ObjectEncryption {
+    /// Value for the `x-amz-server-side-encryption` response header.
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            ObjectEncryption::SseS3 { .. } | ObjectEncryption::SseC { .. } => AES256,
+            ObjectEncryption::Kms { .. } => AWS_KMS,
+        }
+    }
+
+    pub fn is_customer_key(&self) -> bool {
+        matches!(self, ObjectEncryption::SseC { .. })
+    }
+}
+
+/// A customer provided key from the SSE-C request headers. Lives for one request.
+pub struct CustomerKey {
+    key: DataKey,
+    /// base64 md5 of the key, echoed back in responses
+    pub key_md5: String,
+}
+
+impl CustomerKey {
+    /// Reads the `{prefix}algorithm`, `{prefix}key` and `{prefix}key-MD5` headers.
+    /// `Ok(None)` when none of them are there, any partial or bad set is an error.
+    pub fn from_headers(headers: &HeaderMap, prefix: &str) -> Result<Option<Self>, ErrorCode> {
+        let get = |name: &str| {
+            headers
+                .get(format!("{}{}", prefix, name))
+                .map(|v| v.to_str().map_err(|_| ErrorCode::InvalidArgument))
+                .transpose()
+        };
+        let (algorithm, key, key_md5) = (get("algorithm")?, get("key")?, get("key-md5")?);
+        if algorithm.is_none() && key.is_none() && key_md5.is_none() {
+            return Ok(None);
+        }
+        let (Some(algorithm), Some(key), Some(key_md5)) = (algorithm, key, key_md5) else {
+            return Err(ErrorCode::InvalidArgument);
+        };
+        if algorithm != AES256 {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        let key: DataKey = BASE64
+            .decode(key)
+            .ok()
+            .and_then(|k| k.try_into().ok())
+            .ok_or(ErrorCode::InvalidArgument)?;
+        if BASE64.encode(md5::compute(key).0) != key_md5 {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        Ok(Some(Self {
+            key,
+            key_md5: key_md5.to_string(),
+        }))
+    }
+
+    fn fingerprint(&self, salt: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(salt);
+        hasher.update(self.key);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Wraps `data_key` under this key for storing in the object metadata.
+    pub fn seal(&self, data_key: &DataKey) -> io::Result<ObjectEncryption> {
+        let salt = envelope::random_bytes::<16>();
+        let wrapped = envelope::encrypt(&self.key, data_key)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(ObjectEncryption::SseC {
+            salt: hex::encode(salt),
+            fingerprint: self.fingerprint(&salt),
+            wrapped_key: hex::encode(wrapped),
+        })
+    }
+
+    /// `Ok(None)` if this isnt the key the object was written with.
+    pub fn open(&self, encryption: &ObjectEncryption) -> io::Result<Option<DataKey>> {
+        let ObjectEncryption::SseC {
+            salt,
+            fingerprint,
+            wrapped_key,
+        } = encryption
+        else {
+            return Ok(None);
+        };
+        let salt = hex::decode(salt).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if self.fingerprint(&salt) != *fingerprint {
+            return Ok(None);
+        }
+        let wrapped =
+            hex::decode(wrapped_key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let data_key = envelope::decrypt(&self.key, &wrapped)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        data_key
+            .try_into()
+            .map(Some)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad wrapped data key"))
+    }
+}
+
+pub fn new_data_key() -> DataKey {
+    envelope::random_bytes::<KEY_LEN>()
+}
+
+/// A base for one write of a part under a shared key.
+pub fn new_nonce_base() -> NonceBase {
+    envelope::random_bytes::<12>()
+}
+
+pub fn wrap_data_key(master: &MasterKey, data_key: &DataKey) -> io::Result<String> {
+    envelope::wrap_key(master, data_key).map_err(|e| io::Error::other(e.to_string()))
+}
+
+pub fn unwrap_data_key(master: &MasterKey, wrapped: &str) -> io::Result<DataKey> {
+    envelope::unwrap_key(master, wrapped).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Loads the SSE master key, generating one on first start so SSE-S3 just works.
+pub fn load_or_create_master_key(path: &Path) -> io::Result<MasterKey> {
+    if path.exists() {
+        return MasterKey::from_file(path).map_err(|e| io::Error::other(e.to_string()));
+    }
+    let key = MasterKey::generate();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, key.to_hex().as_bytes())?;
+    println!("Generated SSE master key at {}", path.display());
+    Ok(key)
+}
+
+fn chunk_nonce(base: &NonceBase, part: u32, index: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&part.to_be_bytes());
+    nonce[4..].copy_from_slice(&index.to_be_bytes());
+    for (n, b) in nonce.iter_mut().zip(base) {
+        *n ^= b;
+    }
+    nonce
+}
+
+fn chunk_count(plain_len: u64) -> u64 {
+    plain_len.div_ceil(CHUNK_SIZE)
+}
+
+/// On-disk size of `plain_len` bytes once encrypted.
+pub fn encrypted_len(plain_len: u64) -> u64 {
+    plain_len + chunk_count(plain_len) * TAG_LEN
+}
+
+pub fn encrypt_part(
+    data_key: &DataKey,
+    part: u32,
+    nonce_base: &NonceBase,
+    plaintext: &[u8],
+) -> io::Result<Vec<u8>> {
+    let mut sealer = PartSealer::new(data_key, part, nonce_base, plaintext.len() as u64);
+    let mut out = Vec::with_capacity(encrypted_len(plaintext.len() as u64) as usize);
+    out.extend(sealer.update(plaintext)?);
+    out.extend(sealer.finish()?);
+    Ok(out)
+}
+
+/// Seals a part as its plaintext arrives, for data too big to hold in memory. The total
+/// length has to be known up front so the final chunk can be marked.
+pub struct PartSealer {
+    cipher: Aes256Gcm,
+    part: u32,
+    nonce_base: NonceBase,
+    chunks: u64,
+    index: u64,
+    /// plaintext bytes still expected
+    remaining: u64,
+    buf: Vec<u8>,
+}
+
+impl PartSealer {
+    pub fn new(data_key: &DataKey, part: u32, nonce_base: &NonceBase, plain_len: u64) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(data_key)),
+            part,
+            nonce_base: *nonce_base,
+            chunks: chunk_count(plain_len),
+            index: 0,
+            remaining: plain_len,
+            buf: Vec::new(),
+        }
+    }
+
+    fn seal(&mut self, len: usize, last: bool) -> io::Result<Vec<u8>> {
+        let chunk: Vec<u8> = self.buf.drain(..len).collect();
+        let sealed = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&chunk_nonce(&self.nonce_base, self.part, self.index)),
+                Payload {
+                    msg: &chunk,
+                    aad: &[last as u8],
+                },
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.index += 1;
+        Ok(sealed)
+    }
+
+    /// Returns whatever chunks are complete so far.
+    pub fn update(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.remaining = self
+            .remaining
+            .checked_sub(data.len() as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "plaintext longer than expected"))?;
+        self.buf.extend_from_slice(data);
+        let mut out = Vec::new();
+        // the last chunk waits for finish() even when it is already full
+        while self.buf.len() >= CHUNK_SIZE as usize && self.index + 1 < self.chunks {
+            out.extend(self.seal(CHUNK_SIZE as usize, false)?);
+        }
+        Ok(out)
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        if self.chunks == 0 && self.buf.is_empty() {
+            return Ok(Vec::new());
+        }
+        if self.remaining != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "plaintext shorter than expected"));
+        }
+        self.seal(self.buf.len(), true)
+    }
+}
+
+fn decrypt_chunk(data_key: &DataKey, chunk: &ChunkRead, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(data_key));
+    cipher
+        .decrypt(
+            Nonce::from_slice(&chunk_nonce(&chunk.nonce_base, chunk.part, chunk.index)),
+            Payload {
+                msg: sealed,
+                aad: &[chunk.last as u8],
+            },
+        )
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "object data failed authentication"))
+}
+
+/// One independently encrypted run of the object, a whole PUT or one multipart part.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub part: u32,
+    pub nonce_base: NonceBase,
+    pub plain_len: u64,
+}
+
+struct ChunkRead {
+    part: u32,
+    nonce_base: NonceBase,
+    index: u64,
+    last: bool,
+    file_offset: u64,
+    sealed_len: usize,
+    plain_start: u64,
+    plain_end: u64,
+}
+
+/// Finds the chunk holding plaintext offset `pos`.
+fn locate(segments: &[Segment], pos: u64) -> Option<ChunkRead> {
+    let mut plain_base = 0;
+    let mut file_base = 0;
+    for segment in segments {
+        if pos < plain_base + segment.plain_len {
+            let index = (pos - plain_base) / CHUNK_SIZE;
+            let plain_start = plain_base + index * CHUNK_SIZE;
+            let plain_end = (plain_start + CHUNK_SIZE).min(plain_base + segment.plain_len);
+            return Some(ChunkRead {
+                part: segment.part,
+                nonce_base: segment.nonce_base,
+                index,
+                last: index + 1 == chunk_count(segment.plain_len),
+                file_offset: file_base + index * (CHUNK_SIZE + TAG_LEN),
+                sealed_len: (plain_end - plain_start + TAG_LEN) as usize,
+                plain_start,
+                plain_end,
+            });
+        }
+        plain_base += segment.plain_len;
+        file_base += encrypted_len(segment.plain_len);
+    }
+    None
+}
+
+/// Streams plaintext bytes `start..end` of an encrypted object. Only the chunks that
+/// overlap the range are read off disk.
+pub fn decrypt_range(
+    file: File,
+    data_key: DataKey,
+    segments: Vec<Segment>,
+    start: u64,
+    end: u64,
+) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    futures_util::stream::try_unfold(
+        (file, segments, start),
+        move |(mut file, segments, pos)| async move {
+            if pos >= end {
+                return Ok(None);
+            }
+            let chunk = locate(&segments, pos).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "range past end of object")
+            })?;
+            file.seek(SeekFrom::Start(chunk.file_offset)).await?;
+            let mut sealed = vec![0u8; chunk.sealed_len];
+            file.read_exact(&mut sealed).await?;
+            let plain = decrypt_chunk(&data_key, &chunk, &sealed)?;
+            let from = (pos - chunk.plain_start) as usize;
+            let to = (end.min(chunk.plain_end) - chunk.plain_start) as usize;
+            Ok(Some((
+                Bytes::copy_from_slice(&plain[from..to]),
+                (file, segments, chunk.plain_end),
+            )))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    async fn read_range(
+        payload: &[u8],
+        key: DataKey,
+        segments: Vec<Segment>,
+        start: u64,
+        end: u64,
+    ) -> io::Result<Vec<u8>> {
+        let path = std::env::temp_dir().join(format!(
+            "lumi-sse-{}-{}",
+            std::process::id(),
+            hex::encode(envelope::random_bytes::<4>())
+        ));
+        tokio::fs::write(&path, payload).await.unwrap();
+        let file = File::open(&path).await.unwrap();
+        let out: io::Result<Vec<Bytes>> = decrypt_range(file, key, segments, start, end)
+            .try_collect()
+            .await;
+        let _ = tokio::fs::remove_file(&path).await;
+        Ok(out?.concat())
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_and_ranges() {
+        let key = new_data_key();
+        let plaintext: Vec<u8> = (0..(CHUNK_SIZE * 2 + 100)).map(|i| (i % 251) as u8).collect();
+        let payload = encrypt_part(&key, 0, &OBJECT_NONCE_BASE, &plaintext).unwrap();
+        assert_eq!(payload.len() as u64, encrypted_len(plaintext.len() as u64));
+        let segments = vec![Segment {
+            part: 0,
+            nonce_base: OBJECT_NONCE_BASE,
+            plain_len: plaintext.len() as u64,
+        }];
+        let len = plaintext.len() as u64;
+        assert_eq!(
+            read_range(&payload, key, segments.clone(), 0, len).await.unwrap(),
+            plaintext
+        );
+        let (start, end) = (CHUNK_SIZE - 10, CHUNK_SIZE * 2 + 5);
+        assert_eq!(
+            read_range(&payload, key, segments, start, end).await.unwrap(),
+            &plaintext[start as usize..end as usize]
+        );
+    }
+
+    #[test]
+    fn test_sealer_matches_encrypt_part() {
+        let key = new_data_key();
+        let base = new_nonce_base();
+        for len in [0, 10, CHUNK_SIZE as usize, CHUNK_SIZE as usize * 2 + 7] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i % 13) as u8).collect();
+            let mut sealer = PartSealer::new(&key, 3, &base, len as u64);
+            let mut out = Vec::new();
+            for piece in plaintext.chunks(1000) {
+                out.extend(sealer.update(piece).unwrap());
+            }
+            out.extend(sealer.finish().unwrap());
+            assert_eq!(out, encrypt_part(&key, 3, &base, &plaintext).unwrap());
+        }
+        let mut short = PartSealer::new(&key, 0, &OBJECT_NONCE_BASE, 100);
+        short.update(&[0; 50]).unwrap();
+        assert!(short.finish().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multiple_segments() {
+        let key = new_data_key();
+        let first = vec![1u8; CHUNK_SIZE as usize + 3];
+        let second = vec![2u8; 10];
+        let (base1, base2) = (new_nonce_base(), new_nonce_base());
+        let mut payload = encrypt_part(&key, 1, &base1, &first).unwrap();
+        payload.extend(encrypt_part(&key, 2, &base2, &second).unwrap());
+        let segments = vec![
+            Segment { part: 1, nonce_base: base1, plain_len: first.len() as u64 },
+            Segment { part: 2, nonce_base: base2, plain_len: second.len() as u64 },
+        ];
+        let out = read_range(&payload, key, segments, CHUNK_SIZE, CHUNK_SIZE + 8)
+            .await
+            .unwrap();
+        assert_eq!(out, [1, 1, 1, 2, 2, 2, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_part_gets_fresh_nonces() {
+        // a part number uploaded twice under the upload's one data key
+        let key = new_data_key();
+        let plaintext = vec![5u8; CHUNK_SIZE as usize + 1];
+        let (first, second) = (new_nonce_base(), new_nonce_base());
+        let a = encrypt_part(&key, 1, &first, &plaintext).unwrap();
+        let b = encrypt_part(&key, 1, &second, &plaintext).unwrap();
+        assert_ne!(a[..CHUNK_SIZE as usize], b[..CHUNK_SIZE as usize]);
+        assert_ne!(a[(CHUNK_SIZE + TAG_LEN) as usize..], b[(CHUNK_SIZE + TAG_LEN) as usize..]);
+
+        let len = plaintext.len() as u64;
+        let segment = |nonce_base| vec![Segment { part: 1, nonce_base, plain_len: len }];
+        assert_eq!(read_range(&b, key, segment(second), 0, len).await.unwrap(), plaintext);
+        assert!(read_range(&b, key, segment(first), 0, len).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tampering_and_wrong_key_fail() {
+        let key = new_data_key();
+        let mut payload = encrypt_part(&key, 0, &OBJECT_NONCE_BASE, b"hello world").unwrap();
+        let segments = vec![Segment { part: 0, nonce_base: OBJECT_NONCE_BASE, plain_len: 11 }];
+        assert!(read_range(&payload, new_data_key(), segments.clone(), 0, 11).await.is_err());
+        payload[3] ^= 1;
+        assert!(read_range(&payload, key, segments, 0, 11).await.is_err());
+    }
+
+    #[test]
+    fn test_customer_key() {
+        let raw = [9u8; 32];
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amz-server-side-encryption-customer-algorithm", "AES256".parse().unwrap());
+        headers.insert(
+            "x-amz-server-side-encryption-customer-key",
+            BASE64.encode(raw).parse().unwrap(),
+        );
+        headers.insert(
+            "x-amz-server-side-encryption-customer-key-md5",
+            BASE64.encode(md5::compute(raw).0).parse().unwrap(),
+        );
+        let customer = CustomerKey::from_headers(&headers, SSE_C_PREFIX).unwrap().unwrap();
+        let data_key = new_data_key();
+        let sealed = customer.seal(&data_key).unwrap();
+        assert_eq!(customer.open(&sealed).unwrap(), Some(data_key));
+        // the customer key itself never ends up in the metadata
+        assert!(!serde_json::to_string(&sealed).unwrap().contains(&hex::encode(raw)));
+
+        headers.insert(
+            "x-amz-server-side-encryption-customer-key",
+            BASE64.encode([8u8; 32]).parse().unwrap(),
+        );
+        assert!(CustomerKey::from_headers(&headers, SSE_C_PREFIX).is_err());
+        headers.insert(
+            "x-amz-server-side-encryption-customer-key-md5",
+            BASE64.encode(md5::compute([8u8; 32]).0).parse().unwrap(),
+        );
+        let wrong = CustomerKey::from_headers(&headers, SSE_C_PREFIX).unwrap().unwrap();
+        assert_eq!(wrong.open(&sealed).unwrap(), None);
+        headers.remove("x-amz-server-side-encryption-customer-key");
+        assert!(CustomerKey::from_headers(&headers, SSE_C_PREFIX).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_truncation_detected() {
+        let key = new_data_key();
+        let plaintext = vec![7u8; CHUNK_SIZE as usize * 2];
+        let payload = encrypt_part(&key, 0, &OBJECT_NONCE_BASE, &plaintext).unwrap();
+        // pretend the object only ever had its first chunk
+        let truncated = &payload[..(CHUNK_SIZE + TAG_LEN) as usize];
+        let segments = vec![Segment { part: 0, nonce_base: OBJECT_NONCE_BASE, plain_len: CHUNK_SIZE }];
+        assert!(read_range(truncated, key, segments, 0, CHUNK_SIZE).await.is_err());
+    }
+}
@@ -0,0 +1,91 @@
+// tags on objects and buckets. object tags live in the version's ObjectMeta like every
+// other setting, bucket tags in the bucket config.
+use lumi_credentials::aws_sigv4::percent_decode;
+use lumi_utils::errors::ErrorCode;
+use std::collections::BTreeMap;
+
+use crate::xml::Tag;
+
+pub const TAGGING_HEADER: &str = "x-amz-tagging";
+pub const TAGGING_COUNT_HEADER: &str = "x-amz-tagging-count";
+pub const TAGGING_DIRECTIVE_HEADER: &str = "x-amz-tagging-directive";
+pub const MAX_OBJECT_TAGS: usize = 10;
+pub const MAX_BUCKET_TAGS: usize = 50;
+pub const MAX_KEY_LEN: usize = 128;
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// Letters, digits, whitespace and `+ - = . _ : / @`, lengths counted in characters.
+fn valid_text(text: &str, max: usize) -> bool {
+    text.chars().count() <= max
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c.is_whitespace() || "+-=._:/@".contains(c))
+}
+
+/// Checks a tag set against S3's limits, `aws:` keys are reserved.
+pub fn validate(tags: Vec<Tag>, max_tags: usize) -> Result<BTreeMap<String, String>, ErrorCode> {
+    if tags.len() > max_tags {
+        return Err(ErrorCode::InvalidTag);
+    }
+    let mut map = BTreeMap::new();
+    for tag in tags {
+        if tag.key.is_empty()
+            || tag.key.starts_with("aws:")
+            || !valid_text(&tag.key, MAX_KEY_LEN)
+            || !valid_text(&tag.value, MAX_VALUE_LEN)
+        {
+            return Err(ErrorCode::InvalidTag);
+        }
+        // the same key twice is an error, not last one wins
+        if map.insert(tag.key, tag.value).is_some() {
+            return Err(ErrorCode::InvalidTag);
+        }
+    }
+    Ok(map)
+}
+
+/// `x-amz-tagging`, the tags url encoded like a query string (`a=1&b=2`).
+pub fn parse_header(value: &str) -> Result<BTreeMap<String, String>, ErrorCode> {
+    let decode = |s: &str| {
+        String::from_utf8(percent_decode(&s.replace('+', " "))).map_err(|_| ErrorCode::InvalidTag)
+    };
+    let mut tags = Vec::new();
+    for pair in value.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        tags.push(Tag {
+            key: decode(key)?,
+            value: decode(value)?,
+        });
+    }
+    validate(tags, MAX_OBJECT_TAGS)
+}
+
+pub fn to_tag_set(tags: &BTreeMap<String, String>) -> Vec<Tag> {
+    tags.iter()
+        .map(|(key, value)| Tag {
+            key: key.clone(),
+            value: value.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let tags = parse_header("project=lumi&team=storage%20eng&empty=").unwrap();
+        assert_eq!(tags["project"], "lumi");
+        assert_eq!(tags["team"], "storage eng");
+        assert_eq!(tags["empty"], "");
+        assert_eq!(parse_header(""), Ok(BTreeMap::new()));
+        assert_eq!(parse_header("a=1&a=2"), Err(ErrorCode::InvalidTag));
+        assert_eq!(parse_header("aws%3Aname=x"), Err(ErrorCode::InvalidTag));
+        assert_eq!(parse_header("bad%23key=x"), Err(ErrorCode::InvalidTag));
+        let eleven: Vec<String> = (0..11).map(|i| format!("k{}=v", i)).collect();
+        assert_eq!(parse_header(&eleven.join("&")), Err(ErrorCode::InvalidTag));
+        let long_key = "k".repeat(MAX_KEY_LEN + 1);
+        assert_eq!(parse_header(&format!("{}=v", long_key)), Err(ErrorCode::InvalidTag));
+    }
+}
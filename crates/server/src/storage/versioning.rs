@@ -0,0 +1,196 @@
+// object versions. the current version stays where it always was (meta under meta/),
+// whatever it replaces gets its meta moved to versions/{hash}/ under the bucket's sys dir
+// as {version id}.json, still pointing at the same data file. versions archived before
+// data files had their own names keep theirs next to it as {version id}.data. the null
+// version is kept under the name `null` like any other, delete markers only have the json.
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use tokio::fs;
+
+use super::{bucket_sys_path, config, data_file, link_data, remove_if_exists, write_atomic, ObjectMeta};
+
+pub const NULL_VERSION: &str = "null";
+pub const ENABLED: &str = "Enabled";
+pub const SUSPENDED: &str = "Suspended";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Versioning {
+    /// never had versioning turned on, writes just replace
+    Unversioned,
+    Enabled,
+    /// new writes get the null version, existing versions stay around
+    Suspended,
+}
+
+pub async fn status(bucket: &str) -> io::Result<Versioning> {
+    let cfg = config::load(bucket).await?;
+    Ok(match cfg.versioning.and_then(|v| v.status).as_deref() {
+        Some(ENABLED) => Versioning::Enabled,
+        Some(SUSPENDED) => Versioning::Suspended,
+        _ => Versioning::Unversioned,
+    })
+}
+
+/// Version ids sort by creation time, a nanosecond timestamp followed by some random
+/// bytes so two writes in the same nanosecond dont collide.
+pub fn new_version_id() -> String {
+    let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    format!(
+        "{:016x}{}",
+        nanos,
+        hex::encode(lumi_credentials::envelope::random_bytes::<8>())
+    )
+}
+
+/// Anything we could have handed out, version ids end up in paths.
+pub fn is_valid_version_id(id: &str) -> bool {
+    id == NULL_VERSION || (id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// The version id as clients see it.
+pub fn version_name(meta: &ObjectMeta) -> &str {
+    meta.version_id.as_deref().unwrap_or(NULL_VERSION)
+}
+
+fn versions_dir(bucket: &str, key: &str) -> PathBuf {
+    let hash = hex::encode(Sha256::digest(key.as_bytes()));
+    bucket_sys_path(bucket)
+        .join("versions")
+        .join(&hash[..2])
+        .join(hash)
+}
+
+fn version_paths(bucket: &str, key: &str, id: &str) -> io::Result<(PathBuf, PathBuf)> {
+    if !is_valid_version_id(id) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid version id"));
+    }
+    let dir = versions_dir(bucket, key);
+    Ok((dir.join(format!("{}.data", id)), dir.join(format!("{}.json", id))))
+}
+
+/// Where the data of a noncurrent version lives.
+pub(super) fn data_path(bucket: &str, meta: &ObjectMeta) -> io::Result<PathBuf> {
+    match &meta.data {
+        Some(id) => Ok(data_file(bucket, &meta.key, id)),
+        None => version_paths(bucket, &meta.key, version_name(meta)).map(|(data, _)| data),
+    }
+}
+
+/// Keeps the current version around as a noncurrent one. Its data file stays where it
+/// is, `data` says where the current version's bytes are when `meta` doesnt.
+pub(super) async fn archive(bucket: &str, meta: &ObjectMeta, data: Option<PathBuf>) -> io::Result<()> {
+    let (_, json) = version_paths(bucket, &meta.key, version_name(meta))?;
+    let mut meta = meta.clone();
+    if let Some(data) = data
+        && meta.data.is_none()
+        && !meta.delete_marker
+    {
+        meta.data = Some(link_data(bucket, &meta.key, &data).await?);
+    }
+    let raw = serde_json::to_vec(&meta).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::create_dir_all(versions_dir(bucket, &meta.key)).await?;
+    write_atomic(&json, &raw).await
+}
+
+/// Gives a noncurrent version the data file name it needs to become the current one.
+pub(super) async fn promote(bucket: &str, meta: &mut ObjectMeta) -> io::Result<()> {
+    if meta.data.is_none() && !meta.delete_marker {
+        let data = data_path(bucket, meta)?;
+        meta.data = Some(link_data(bucket, &meta.key, &data).await?);
+    }
+    Ok(())
+}
+
+/// Replaces the stored metadata of a noncurrent version, the data stays put.
+pub(super) async fn rewrite(bucket: &str, meta: &ObjectMeta) -> io::Result<()> {
+    let (_, json) = version_paths(bucket, &meta.key, version_name(meta))?;
+    let raw = serde_json::to_vec(meta).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_atomic(&json, &raw).await
+}
+
+/// `Ok(None)` if there is no such noncurrent version.
+pub(super) async fn load(bucket: &str, key: &str, id: &str) -> io::Result<Option<ObjectMeta>> {
+    let Ok((_, json)) = version_paths(bucket, key, id) else {
+        return Ok(None);
+    };
+    match fs::read(json).await {
+        Ok(raw) => serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Drops a noncurrent version for good, returns what it was. With `keep_data` only
+/// the version goes, for when its data file now belongs to the current version.
+pub(super) async fn remove(
+    bucket: &str,
+    key: &str,
+    id: &str,
+    keep_data: bool,
+) -> io::Result<Option<ObjectMeta>> {
+    let Some(meta) = load(bucket, key, id).await? else {
+        return Ok(None);
+    };
+    let (legacy, json) = version_paths(bucket, key, id)?;
+    // the json goes first, a version without it is just an unreferenced file
+    fs::remove_file(json).await?;
+    if !keep_data && !meta.delete_marker {
+        remove_if_exists(&data_path(bucket, &meta)?).await?;
+    }
+    // a legacy file a promoted version was linked from
+    remove_if_exists(&legacy).await?;
+    // the key dir goes once its last version does, never mind if something is left
+    let _ = fs::remove_dir(versions_dir(bucket, key)).await;
+    Ok(Some(meta))
+}
+
+/// Noncurrent versions of a key, newest first.
+pub async fn list(bucket: &str, key: &str) -> io::Result<Vec<ObjectMeta>> {
+    let mut versions = Vec::new();
+    let mut entries = match fs::read_dir(versions_dir(bucket, key)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(versions),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".json") {
+            continue;
+        }
+        let raw = fs::read(entry.path()).await?;
+        versions.push(
+            serde_json::from_slice::<ObjectMeta>(&raw)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        );
+    }
+    sort_newest_first(&mut versions);
+    Ok(versions)
+}
+
+pub fn sort_newest_first(versions: &mut [ObjectMeta]) {
+    versions.sort_by(|a, b| {
+        b.last_modified
+            .cmp(&a.last_modified)
+            .then_with(|| version_name(b).cmp(version_name(a)))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_ids() {
+        let first = new_version_id();
+        let second = new_version_id();
+        assert!(is_valid_version_id(&first));
+        assert!(first[..16] <= second[..16]);
+        assert!(is_valid_version_id(NULL_VERSION));
+        assert!(!is_valid_version_id("../../etc"));
+        assert!(!is_valid_version_id(""));
+    }
+}
@@ -0,0 +1,417 @@
+use axum::{Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use rustls::{
+    crypto::{aws_lc_rs, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    RootCertStore,
+};
+use std::{
+    error::Error,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use x509_parser::extensions::GeneralName;
+
+pub type TlsError = Box<dyn Error + Send + Sync>;
+
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CA bundle client certificates are verified against, `None` disables mTLS
+    pub client_ca: Option<PathBuf>,
+    /// reject handshakes without a client cert instead of falling back to SigV4
+    pub client_cert_required: bool,
+}
+
+/// Names from a verified client certificate, put on every request of that connection.
+/// Entries look like `subject:CN=svc,O=acme`, `cn:svc`, `dns:svc.internal`, `uri:spiffe://..`,
+/// `email:ops@acme.dev` and `ip:10.0.0.7`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    pub names: Vec<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let mut names = vec![format!("subject:{}", cert.subject())];
+        for cn in cert.subject().iter_common_name() {
+            if let Ok(cn) = cn.as_str() {
+                names.push(format!("cn:{}", cn));
+            }
+        }
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => names.push(format!("dns:{}", dns)),
+                    GeneralName::URI(uri) => names.push(format!("uri:{}", uri)),
+                    GeneralName::RFC822Name(email) => names.push(format!("email:{}", email)),
+                    GeneralName::IPAddress(ip) => {
+                        let ip = match ip.len() {
+                            4 => <[u8; 4]>::try_from(*ip).ok().map(std::net::IpAddr::from),
+                            16 => <[u8; 16]>::try_from(*ip).ok().map(std::net::IpAddr::from),
+                            _ => None,
+                        };
+                        if let Some(ip) = ip {
+                            names.push(format!("ip:{}", ip));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Some(Self { names })
+    }
+}
+
+/// Hands out whichever certificate was loaded last. Connections keep the key they
+/// handshook with, so swapping it only affects new handshakes and nothing gets dropped.
+#[derive(Debug)]
+pub struct CertReloader {
+    tls: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+    // raw pem of what is loaded right now, comparing content instead of mtimes also
+    // catches the symlink swaps kubernetes does for mounted secrets
+    loaded: RwLock<(Vec<u8>, Vec<u8>)>,
+}
+
+fn read_pem(tls: &TlsConfig) -> Result<(Vec<u8>, Vec<u8>), TlsError> {
+    let cert = std::fs::read(&tls.cert).map_err(|e| format!("{}: {}", tls.cert.display(), e))?;
+    let key = std::fs::read(&tls.key).map_err(|e| format!("{}: {}", tls.key.display(), e))?;
+    Ok((cert, key))
+}
+
+fn parse_certified_key(
+    cert: &[u8],
+    key: &[u8],
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, TlsError> {
+    let chain = CertificateDer::pem_slice_iter(cert).collect::<Result<Vec<_>, _>>()?;
+    if chain.is_empty() {
+        return Err("no certificates found in pem".into());
+    }
+    let key = PrivateKeyDer::from_pem_slice(key)?;
+    Ok(CertifiedKey::from_der(chain, key, provider)?)
+}
+
+impl CertReloader {
+    pub fn new(tls: TlsConfig) -> Result<Self, TlsError> {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let (cert, key) = read_pem(&tls)?;
+        let certified = parse_certified_key(&cert, &key, &provider)?;
+        Ok(Self {
+            tls,
+            provider,
+            current: RwLock::new(Arc::new(certified)),
+            loaded: RwLock::new((cert, key)),
+        })
+    }
+
+    /// Reloads the cert if either file changed. A half written pair (cert-manager
+    /// doesnt write both at once) just fails to parse and gets retried next tick.
+    pub fn reload_if_changed(&self) -> Result<bool, TlsError> {
+        let (cert, key) = read_pem(&self.tls)?;
+        {
+            let loaded = self.loaded.read().unwrap();
+            if loaded.0 == cert && loaded.1 == key {
+                return Ok(false);
+            }
+        }
+        let certified = parse_certified_key(&cert, &key, &self.provider)?;
+        *self.current.write().unwrap() = Arc::new(certified);
+        *self.loaded.write().unwrap() = (cert, key);
+        Ok(true)
+    }
+
+    pub fn watch(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => println!("Reloaded TLS certificate from {}", self.tls.cert.display()),
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Failed to reload TLS certificate, keeping the old one: {}", e),
+                }
+            }
+        });
+    }
+
+    pub fn server_config(self: Arc<Self>) -> Result<rustls::ServerConfig, TlsError> {
+        let builder = rustls::ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.tls.client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(ca)
+                    .map_err(|e| format!("{}: {}", ca.display(), e))?
+                {
+                    roots.add(cert?)?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(roots),
+                    self.provider.clone(),
+                );
+                let verifier = if self.tls.client_cert_required {
+                    verifier.build()?
+                } else {
+                    verifier.allow_unauthenticated().build()?
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(self);
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Same job as `axum::serve` but every connection goes through the TLS handshake first.
+/// hyper's auto builder picks HTTP/1.1 or HTTP/2 based on what ALPN negotiated.
+pub async fn serve(listener: TcpListener, app: Router, acceptor: TlsAcceptor) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    eprintln!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+                Err(_) => return,
+            };
+            // rustls already verified the chain, this is just reading names off the leaf
+            let client_cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|leaf| ClientCertificate::from_der(leaf));
+            let app = match client_cert {
+                Some(cert) => app.layer(Extension(cert)),
+                None => app,
+            };
+            let service = TowerToHyperService::new(app);
+            let _ = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, Issuer, KeyPair};
+    use rustls::{pki_types::ServerName, ClientConfig};
+    use std::net::SocketAddr;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_rustls::{client::TlsStream, TlsConnector};
+
+    type Pair = (String, String);
+
+    fn self_signed() -> Pair {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        (cert.cert.pem(), cert.signing_key.serialize_pem())
+    }
+
+    fn temp_tls(name: &str, server: &Pair) -> TlsConfig {
+        let dir = std::env::temp_dir().join(format!("lumi-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            client_ca: None,
+            client_cert_required: false,
+        };
+        write_pair(&tls, server);
+        tls
+    }
+
+    fn write_pair(tls: &TlsConfig, (cert, key): &Pair) {
+        std::fs::write(&tls.cert, cert).unwrap();
+        std::fs::write(&tls.key, key).unwrap();
+    }
+
+    async fn start(tls: TlsConfig, app: Router) -> (SocketAddr, Arc<CertReloader>) {
+        let reloader = Arc::new(CertReloader::new(tls).unwrap());
+        let acceptor = TlsAcceptor::from(Arc::new(reloader.clone().server_config().unwrap()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app, acceptor));
+        (addr, reloader)
+    }
+
+    async fn connect(
+        addr: SocketAddr,
+        trusted_pem: &str,
+        alpn: &[&[u8]],
+        client: Option<&Pair>,
+    ) -> std::io::Result<TlsStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(trusted_pem.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let mut config = match client {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    CertificateDer::pem_slice_iter(cert.as_bytes())
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap(),
+                    PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        let stream = TcpStream::connect(addr).await?;
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+    }
+
+    async fn http1_get(stream: &mut TlsStream<TcpStream>) -> String {
+        if stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .is_err()
+        {
+            return String::new();
+        }
+        let mut buf = vec![0u8; 1024];
+        let n = stream.read(&mut buf).await.unwrap_or(0);
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_alpn_and_hot_reload() {
+        let first = self_signed();
+        let tls = temp_tls("reload", &first);
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        let (addr, reloader) = start(tls.clone(), app).await;
+
+        let h2 = connect(addr, &first.0, &[b"h2", b"http/1.1"], None).await.unwrap();
+        assert_eq!(h2.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let mut old = connect(addr, &first.0, &[b"http/1.1"], None).await.unwrap();
+        assert_eq!(old.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert!(http1_get(&mut old).await.starts_with("HTTP/1.1 200"));
+
+        assert!(!reloader.reload_if_changed().unwrap());
+        let second = self_signed();
+        write_pair(&tls, &second);
+        assert!(reloader.reload_if_changed().unwrap());
+
+        assert!(connect(addr, &first.0, &[b"http/1.1"], None).await.is_err());
+        let mut new = connect(addr, &second.0, &[b"http/1.1"], None).await.unwrap();
+        assert!(http1_get(&mut new).await.starts_with("HTTP/1.1 200"));
+        // connection opened before the swap is still being served
+        assert!(http1_get(&mut old).await.starts_with("HTTP/1.1 200"));
+
+        // broken pem keeps the previous cert around
+        std::fs::write(&tls.key, "garbage").unwrap();
+        assert!(reloader.reload_if_changed().is_err());
+        assert!(connect(addr, &second.0, &[b"http/1.1"], None).await.is_ok());
+        let _ = std::fs::remove_dir_all(tls.cert.parent().unwrap());
+    }
+
+    fn client_ca() -> (String, Pair) {
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "lumi test ca");
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_pem = ca_params.self_signed(&ca_key).unwrap().pem();
+        let issuer = Issuer::new(ca_params, ca_key);
+
+        let mut params = CertificateParams::new(vec!["svc-b.internal".to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "svc-b");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &issuer).unwrap();
+        (ca_pem, (cert.pem(), key.serialize_pem()))
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_names() {
+        let server = self_signed();
+        let (ca_pem, client) = client_ca();
+        let mut tls = temp_tls("mtls", &server);
+        let ca_path = tls.cert.with_file_name("ca.pem");
+        std::fs::write(&ca_path, &ca_pem).unwrap();
+        tls.client_ca = Some(ca_path);
+
+        let app = Router::new().route(
+            "/",
+            get(|cert: Option<Extension<ClientCertificate>>| async move {
+                match cert {
+                    Some(Extension(cert)) => cert.names.join(" "),
+                    None => "anonymous".to_string(),
+                }
+            }),
+        );
+        let (addr, _) = start(tls.clone(), app.clone()).await;
+
+        let mut anonymous = connect(addr, &server.0, &[b"http/1.1"], None).await.unwrap();
+        assert!(http1_get(&mut anonymous).await.ends_with("anonymous"));
+
+        let mut authed = connect(addr, &server.0, &[b"http/1.1"], Some(&client)).await.unwrap();
+        let response = http1_get(&mut authed).await;
+        assert!(response.contains("cn:svc-b"));
+        assert!(response.contains("dns:svc-b.internal"));
+
+        // a cert from some other CA is refused outright
+        let stranger = self_signed();
+        if let Ok(mut refused) = connect(addr, &server.0, &[b"http/1.1"], Some(&stranger)).await {
+            assert!(!http1_get(&mut refused).await.starts_with("HTTP/1.1 200"));
+        }
+
+        tls.client_cert_required = true;
+        let (addr, _) = start(tls.clone(), app).await;
+        let mut required = connect(addr, &server.0, &[b"http/1.1"], None).await.unwrap();
+        assert!(!http1_get(&mut required).await.starts_with("HTTP/1.1 200"));
+        let mut authed = connect(addr, &server.0, &[b"http/1.1"], Some(&client)).await.unwrap();
+        assert!(http1_get(&mut authed).await.starts_with("HTTP/1.1 200"));
+        let _ = std::fs::remove_dir_all(tls.cert.parent().unwrap());
+    }
+}
@@ -0,0 +1,318 @@
+// static website hosting. buckets with a ?website configuration are served on their own
+// listener, anonymous and GET/HEAD only, with the bucket taken from the Host header:
+// `{bucket}.{website domain}` or, for custom domains, a bucket named like the host.
+// errors are html pages here, browsers are the clients and not the sdks.
+use axum::{
+    extract::{Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use lumi_credentials::aws_sigv4::{percent_decode, uri_encode};
+use lumi_utils::errors::ErrorCode;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::addressing;
+use crate::request_id::RequestId;
+use crate::routes::object::operations::{get_object, head_object};
+use crate::state::AppState;
+use crate::storage::{self, config, ObjectMeta, WEBSITE_REDIRECT_HEADER};
+use crate::xml::{RoutingRedirect, RoutingRule, WebsiteConfiguration};
+
+const PROTOCOLS: &[&str] = &["http", "https"];
+
+/// Either a RedirectAllRequestsTo with a host name and nothing else, or an index
+/// document suffix without `/`, a non-empty error document key if there is one, and
+/// routing rules that redirect with a 3xx, match on a 4xx/5xx and dont replace both the
+/// key and its prefix. Protocols are http or https.
+pub fn validate(config: &WebsiteConfiguration) -> Result<(), ErrorCode> {
+    if let Some(redirect) = &config.redirect_all_requests_to {
+        let alone = config.index_document.is_none()
+            && config.error_document.is_none()
+            && config.routing_rules.is_none();
+        if !alone || redirect.host_name.is_empty() {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        return check_protocol(redirect.protocol.as_deref());
+    }
+    match &config.index_document {
+        Some(index) if !index.suffix.is_empty() && !index.suffix.contains('/') => {}
+        _ => return Err(ErrorCode::InvalidArgument),
+    }
+    if let Some(error) = &config.error_document
+        && error.key.is_empty()
+    {
+        return Err(ErrorCode::InvalidArgument);
+    }
+    for rule in config.routing_rules.iter().flat_map(|r| &r.rules) {
+        let redirect = &rule.redirect;
+        if redirect.replace_key_with.is_some() && redirect.replace_key_prefix_with.is_some() {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        if let Some(code) = redirect.http_redirect_code
+            && !(300..400).contains(&code)
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        if let Some(code) = rule.condition.as_ref().and_then(|c| c.http_error_code_returned_equals)
+            && !(400..600).contains(&code)
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        check_protocol(redirect.protocol.as_deref())?;
+    }
+    Ok(())
+}
+
+fn check_protocol(protocol: Option<&str>) -> Result<(), ErrorCode> {
+    match protocol {
+        Some(p) if !PROTOCOLS.contains(&p) => Err(ErrorCode::InvalidArgument),
+        _ => Ok(()),
+    }
+}
+
+/// The bucket a website request is for, from its Host header.
+pub fn bucket_from_host(host: &str, domains: &[String]) -> Option<String> {
+    if let Some(bucket) = addressing::bucket_from_host(host, domains) {
+        return Some(bucket);
+    }
+    let host = addressing::host_name(host);
+    addressing::safe_bucket(&host).then_some(host)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The html version of an S3 error, HEAD gets the status alone.
+fn error_page(code: ErrorCode, bucket: &str, key: Option<&str>, parts: &Parts) -> Response {
+    let error = code.to_api_error();
+    let status = StatusCode::from_u16(error.http_status_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if parts.method == Method::HEAD {
+        return status.into_response();
+    }
+    let title = format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or_default());
+    let mut items = vec![
+        format!("<li>Code: {}</li>", escape_html(&error.code)),
+        format!("<li>Message: {}</li>", escape_html(&error.description)),
+        format!("<li>BucketName: {}</li>", escape_html(bucket)),
+    ];
+    if let Some(key) = key {
+        items.push(format!("<li>Key: {}</li>", escape_html(key)));
+    }
+    if let Some(request_id) = parts.extensions.get::<RequestId>() {
+        items.push(format!("<li>RequestId: {}</li>", request_id.id));
+        items.push(format!("<li>HostId: {}</li>", request_id.host_id));
+    }
+    let body = format!(
+        "<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<ul>\n{}\n</ul>\n<hr/>\n</body>\n</html>\n",
+        items.join("\n")
+    );
+    (status, [(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+fn redirect(status: StatusCode, location: &str) -> Response {
+    match HeaderValue::from_str(location) {
+        Ok(location) => (status, [(header::LOCATION, location)]).into_response(),
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+/// Keys go back into urls encoded, except for their slashes.
+fn encode_key(key: &str) -> String {
+    key.split('/').map(|s| uri_encode(s.as_bytes())).collect::<Vec<_>>().join("/")
+}
+
+/// Where a routing rule sends `key`, the host and protocol default to the request's.
+fn rule_location(rule: &RoutingRule, key: &str, host: &str) -> (StatusCode, String) {
+    let RoutingRedirect {
+        host_name,
+        http_redirect_code,
+        protocol,
+        replace_key_prefix_with,
+        replace_key_with,
+    } = &rule.redirect;
+    let prefix = rule.condition.as_ref().and_then(|c| c.key_prefix_equals.as_deref());
+    let key = match (replace_key_with, replace_key_prefix_with) {
+        (Some(with), _) => with.clone(),
+        (None, Some(with)) => format!("{}{}", with, &key[prefix.map_or(0, str::len)..]),
+        (None, None) => key.to_string(),
+    };
+    let location = format!(
+        "{}://{}/{}",
+        protocol.as_deref().unwrap_or("http"),
+        host_name.as_deref().unwrap_or(host),
+        encode_key(&key)
+    );
+    let status = http_redirect_code
+        .and_then(|c| StatusCode::from_u16(c).ok())
+        .unwrap_or(StatusCode::MOVED_PERMANENTLY);
+    (status, location)
+}
+
+/// First rule whose conditions all hold, `error` is the status the request ended in
+/// if it is already known to fail.
+fn find_rule<'a>(config: &'a WebsiteConfiguration, key: &str, error: Option<u16>) -> Option<&'a RoutingRule> {
+    config.routing_rules.iter().flat_map(|r| &r.rules).find(|rule| {
+        let condition = rule.condition.clone().unwrap_or_default();
+        condition.key_prefix_equals.as_deref().is_none_or(|p| key.starts_with(p))
+            && condition.http_error_code_returned_equals == error
+    })
+}
+
+/// The current version of a key, `None` for missing keys and delete markers.
+async fn lookup(bucket: &str, key: &str) -> Result<Option<ObjectMeta>, ErrorCode> {
+    if storage::object_path(bucket, key).is_none() {
+        return Ok(None);
+    }
+    storage::stat_object(bucket, key).await.map_err(|e| {
+        eprintln!("Failed to read object '{}/{}': {}", bucket, key, e);
+        ErrorCode::InternalError
+    })
+}
+
+/// Serves an object, or its redirect when it has one.
+async fn serve(state: &AppState, meta: &ObjectMeta, bucket: &str, method: &Method, headers: HeaderMap) -> Response {
+    if let Some(location) = meta.content.headers.get(WEBSITE_REDIRECT_HEADER) {
+        return redirect(StatusCode::MOVED_PERMANENTLY, location);
+    }
+    let (bucket, key, query) = (bucket.to_string(), meta.key.clone(), HashMap::new());
+    match *method {
+        Method::HEAD => head_object(state, bucket, key, &query, headers).await.into_response(),
+        _ => get_object(state, bucket, key, &query, headers).await.into_response(),
+    }
+}
+
+/// Every request on the website listener.
+pub async fn handler(State(state): State<Arc<AppState>>, request: Request) -> Response {
+    let host = addressing::request_host(&request).unwrap_or_default().to_string();
+    let (parts, _) = request.into_parts();
+    let method = parts.method.clone();
+    let Some(bucket) = bucket_from_host(&host, &state.website_domains) else {
+        return error_page(ErrorCode::NoSuchBucket, "", None, &parts);
+    };
+    if method != Method::GET && method != Method::HEAD {
+        return error_page(ErrorCode::MethodNotAllowed, &bucket, None, &parts);
+    }
+    if !storage::bucket_exists(&bucket).await {
+        return error_page(ErrorCode::NoSuchBucket, &bucket, None, &parts);
+    }
+    let website = match config::load(&bucket).await {
+        Ok(config::BucketConfig { website: Some(website), .. }) => website,
+        Ok(_) => return error_page(ErrorCode::NoSuchWebsiteConfiguration, &bucket, None, &parts),
+        Err(_) => return error_page(ErrorCode::InternalError, &bucket, None, &parts),
+    };
+    if let Some(to) = &website.redirect_all_requests_to {
+        let path = parts.uri.path_and_query().map_or("/", |pq| pq.as_str());
+        let protocol = to.protocol.as_deref().unwrap_or("http");
+        return redirect(StatusCode::MOVED_PERMANENTLY, &format!("{}://{}{}", protocol, to.host_name, path));
+    }
+
+    let path = String::from_utf8_lossy(&percent_decode(parts.uri.path())).into_owned();
+    let requested = path.trim_start_matches('/').to_string();
+    if let Some(rule) = find_rule(&website, &requested, None) {
+        let (status, location) = rule_location(rule, &requested, &host);
+        return redirect(status, &location);
+    }
+    let suffix = website.index_document.as_ref().map_or("index.html", |i| i.suffix.as_str());
+    let key = match requested.is_empty() || requested.ends_with('/') {
+        true => format!("{}{}", requested, suffix),
+        false => requested.clone(),
+    };
+    match lookup(&bucket, &key).await {
+        Ok(Some(meta)) => return serve(&state, &meta, &bucket, &method, parts.headers).await,
+        Ok(None) => {}
+        Err(code) => return error_page(code, &bucket, Some(&key), &parts),
+    }
+    // `/docs` when there is a `docs/index.html` is a redirect to the directory
+    if key == requested
+        && let Ok(Some(_)) = lookup(&bucket, &format!("{}/{}", requested, suffix)).await
+    {
+        return redirect(StatusCode::FOUND, &format!("/{}/", encode_key(&requested)));
+    }
+    if let Some(rule) = find_rule(&website, &requested, Some(404)) {
+        let (status, location) = rule_location(rule, &requested, &host);
+        return redirect(status, &location);
+    }
+    if let Some(error) = &website.error_document
+        && let Ok(Some(meta)) = lookup(&bucket, &error.key).await
+    {
+        // conditionals and ranges are about the page they asked for, not this one
+        let mut response = serve(&state, &meta, &bucket, &method, HeaderMap::new()).await;
+        if response.status() == StatusCode::OK {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+        }
+        return response;
+    }
+    error_page(ErrorCode::NoSuchKey, &bucket, Some(&key), &parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xml::{IndexDocument, RedirectAllRequestsTo, RoutingCondition, RoutingRules};
+
+    #[test]
+    fn test_bucket_from_host() {
+        let domains = vec!["web.lumi.local".to_string()];
+        assert_eq!(bucket_from_host("docs.web.lumi.local:8081", &domains), Some("docs".into()));
+        assert_eq!(bucket_from_host("Docs.Web.Lumi.Local", &domains), Some("docs".into()));
+        assert_eq!(bucket_from_host("www.example.com", &domains), Some("www.example.com".into()));
+        assert_eq!(bucket_from_host("..", &domains), None);
+        assert_eq!(bucket_from_host("", &domains), None);
+    }
+
+    #[test]
+    fn test_routing_rules() {
+        let rule = |prefix: &str, error: Option<u16>, redirect: RoutingRedirect| RoutingRule {
+            condition: Some(RoutingCondition {
+                key_prefix_equals: Some(prefix.to_string()),
+                http_error_code_returned_equals: error,
+            }),
+            redirect,
+        };
+        let config = WebsiteConfiguration {
+            index_document: Some(IndexDocument { suffix: "index.html".into() }),
+            routing_rules: Some(RoutingRules {
+                rules: vec![
+                    rule("docs/", None, RoutingRedirect {
+                        replace_key_prefix_with: Some("documents/".into()),
+                        ..Default::default()
+                    }),
+                    rule("", Some(404), RoutingRedirect {
+                        host_name: Some("fallback.test".into()),
+                        protocol: Some("https".into()),
+                        http_redirect_code: Some(302),
+                        replace_key_with: Some("404.html".into()),
+                        ..Default::default()
+                    }),
+                ],
+            }),
+            ..Default::default()
+        };
+        assert_eq!(validate(&config), Ok(()));
+        let found = find_rule(&config, "docs/a b.html", None).unwrap();
+        assert_eq!(
+            rule_location(found, "docs/a b.html", "site.test"),
+            (StatusCode::MOVED_PERMANENTLY, "http://site.test/documents/a%20b.html".to_string())
+        );
+        assert!(find_rule(&config, "other", None).is_none());
+        let found = find_rule(&config, "other", Some(404)).unwrap();
+        assert_eq!(
+            rule_location(found, "other", "site.test"),
+            (StatusCode::FOUND, "https://fallback.test/404.html".to_string())
+        );
+
+        let redirect_all = WebsiteConfiguration {
+            redirect_all_requests_to: Some(RedirectAllRequestsTo {
+                host_name: "example.com".into(),
+                protocol: None,
+            }),
+            ..config
+        };
+        assert_eq!(validate(&redirect_all), Err(ErrorCode::InvalidArgument));
+    }
+}
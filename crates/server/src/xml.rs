@@ -1,8 +1,20 @@
-use serde::Serialize;
-#[derive(Serialize)]
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use lumi_utils::errors::ErrorCode;
+use serde::{Deserialize, Serialize};
+
+/// Every S3 response document is in this namespace.
+pub const S3_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Owner {
+    #[serde(rename = "ID")]
     pub id: String,
+    #[serde(default)]
     pub display_name: String,
 }
 #[derive(Serialize)]
@@ -29,4 +41,743 @@ impl Default for Owner {
             display_name: "lumiserver".to_string(),
         }
     }
-}
\ No newline at end of file
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename = "ServerSideEncryptionConfiguration")]
+pub struct ServerSideEncryptionConfiguration {
+    #[serde(rename = "Rule", default)]
+    pub rules: Vec<ServerSideEncryptionRule>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServerSideEncryptionRule {
+    pub apply_server_side_encryption_by_default: Option<ApplyServerSideEncryptionByDefault>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_key_enabled: Option<bool>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyServerSideEncryptionByDefault {
+    #[serde(rename = "SSEAlgorithm")]
+    pub sse_algorithm: String,
+    #[serde(rename = "KMSMasterKeyID", skip_serializing_if = "Option::is_none")]
+    pub kms_master_key_id: Option<String>,
+}
+impl ServerSideEncryptionConfiguration {
+    /// The algorithm new objects get when the PUT doesnt ask for one.
+    pub fn default_algorithm(&self) -> Option<&ApplyServerSideEncryptionByDefault> {
+        self.rules
+            .iter()
+            .find_map(|r| r.apply_server_side_encryption_by_default.as_ref())
+    }
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename = "VersioningConfiguration")]
+pub struct VersioningConfiguration {
+    #[serde(rename = "Status", default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(rename = "MfaDelete", default, skip_serializing_if = "Option::is_none")]
+    pub mfa_delete: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename = "ObjectLockConfiguration", rename_all = "PascalCase")]
+pub struct ObjectLockConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_lock_enabled: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<ObjectLockRule>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectLockRule {
+    pub default_retention: DefaultRetention,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DefaultRetention {
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub years: Option<u32>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename = "LifecycleConfiguration")]
+pub struct LifecycleConfiguration {
+    #[serde(rename = "Rule", default)]
+    pub rules: Vec<LifecycleRule>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleRule {
+    #[serde(rename = "ID", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// how rules were scoped before Filter, still accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<LifecycleFilter>,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<LifecycleExpiration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noncurrent_version_expiration: Option<NoncurrentVersionExpiration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abort_incomplete_multipart_upload: Option<AbortIncompleteMultipartUpload>,
+}
+/// At most one of these, several conditions go inside `And`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<Tag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_size_greater_than: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_size_less_than: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub and: Option<LifecycleAnd>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleAnd {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(rename = "Tag", default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Tag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_size_greater_than: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_size_less_than: Option<u64>,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename = "CreateBucketConfiguration", rename_all = "PascalCase")]
+pub struct CreateBucketConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location_constraint: Option<String>,
+}
+/// Empty for us-east-1, that's how S3 has always answered for it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename = "LocationConstraint")]
+pub struct LocationConstraint {
+    #[serde(rename = "$text", default)]
+    pub region: String,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename = "WebsiteConfiguration", rename_all = "PascalCase")]
+pub struct WebsiteConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_document: Option<IndexDocument>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_document: Option<ErrorDocument>,
+    /// everything else is ignored when this is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_all_requests_to: Option<RedirectAllRequestsTo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing_rules: Option<RoutingRules>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct IndexDocument {
+    pub suffix: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorDocument {
+    pub key: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RedirectAllRequestsTo {
+    pub host_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoutingRules {
+    #[serde(rename = "RoutingRule", default)]
+    pub rules: Vec<RoutingRule>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<RoutingCondition>,
+    pub redirect: RoutingRedirect,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingCondition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_prefix_equals: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_error_code_returned_equals: Option<u16>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingRedirect {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_redirect_code: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace_key_prefix_with: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace_key_with: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename = "CORSConfiguration")]
+pub struct CorsConfiguration {
+    #[serde(rename = "CORSRule", default)]
+    pub rules: Vec<CorsRule>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CorsRule {
+    #[serde(rename = "ID", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "AllowedMethod", default)]
+    pub allowed_methods: Vec<String>,
+    #[serde(rename = "AllowedOrigin", default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(rename = "AllowedHeader", default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_headers: Vec<String>,
+    #[serde(rename = "ExposeHeader", default, skip_serializing_if = "Vec::is_empty")]
+    pub expose_headers: Vec<String>,
+    #[serde(rename = "MaxAgeSeconds", default, skip_serializing_if = "Option::is_none")]
+    pub max_age_seconds: Option<u32>,
+}
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename = "Tagging", rename_all = "PascalCase")]
+pub struct Tagging {
+    pub tag_set: TagSet,
+}
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TagSet {
+    #[serde(rename = "Tag", default)]
+    pub tags: Vec<Tag>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LifecycleExpiration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired_object_delete_marker: Option<bool>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NoncurrentVersionExpiration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noncurrent_days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newer_noncurrent_versions: Option<u32>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AbortIncompleteMultipartUpload {
+    pub days_after_initiation: u32,
+}
+/// Both empty means no retention, which is how GOVERNANCE gets lifted.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename = "Retention", rename_all = "PascalCase")]
+pub struct ObjectRetention {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain_until_date: Option<String>,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename = "LegalHold", rename_all = "PascalCase")]
+pub struct LegalHold {
+    pub status: String,
+}
+
+/// Serializes `value` with the xml declaration in front and the root element in the S3
+/// namespace, same as every S3 response body.
+pub fn to_xml<T: Serialize>(value: &T) -> Result<String, quick_xml::SeError> {
+    let xml = quick_xml::se::to_string(value)?;
+    Ok(format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}"#, with_namespace(&xml)))
+}
+
+/// Adds `xmlns` to the root element, its name runs up to the first space, `>` or `/`.
+fn with_namespace(xml: &str) -> String {
+    match xml.find([' ', '>', '/']) {
+        Some(end) if xml.starts_with('<') => {
+            format!(r#"{} xmlns="{}"{}"#, &xml[..end], S3_NAMESPACE, &xml[end..])
+        }
+        _ => xml.to_string(),
+    }
+}
+
+/// A 200 with `value` as the document, `headers` on top. Our own models only fail to
+/// serialize on a bug, so that is an InternalError.
+pub fn ok_response<T: Serialize>(value: &T, mut headers: HeaderMap) -> Response {
+    match to_xml(value) {
+        Ok(body) => {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+            (StatusCode::OK, headers, body).into_response()
+        }
+        Err(_) => ErrorCode::InternalError.into_response(),
+    }
+}
+
+/// Parses a request body, anything that doesnt fit the model is `MalformedXml`.
+pub fn from_xml<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, lumi_utils::errors::ErrorCode> {
+    let body = std::str::from_utf8(body).map_err(|_| lumi_utils::errors::ErrorCode::MalformedXml)?;
+    quick_xml::de::from_str(body).map_err(|_| lumi_utils::errors::ErrorCode::MalformedXml)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename = "InitiateMultipartUploadResult", rename_all = "PascalCase")]
+pub struct InitiateMultipartUploadResult {
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename = "CompleteMultipartUpload")]
+pub struct CompleteMultipartUpload {
+    #[serde(rename = "Part", default)]
+    pub parts: Vec<CompletedPart>,
+}
+#[derive(Debug, Deserialize)]
+pub struct CompletedPart {
+    #[serde(rename = "PartNumber")]
+    pub part_number: u32,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename = "CompleteMultipartUploadResult", rename_all = "PascalCase")]
+pub struct CompleteMultipartUploadResult {
+    pub location: String,
+    pub bucket: String,
+    pub key: String,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename = "ListPartsResult", rename_all = "PascalCase")]
+pub struct ListPartsResult {
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+    pub part_number_marker: u32,
+    pub next_part_number_marker: u32,
+    pub max_parts: u32,
+    pub is_truncated: bool,
+    #[serde(rename = "Part")]
+    pub parts: Vec<Part>,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Part {
+    pub part_number: u32,
+    pub last_modified: String,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    pub size: u64,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename = "CopyObjectResult", rename_all = "PascalCase")]
+pub struct CopyObjectResult {
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    pub last_modified: String,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename = "CopyPartResult", rename_all = "PascalCase")]
+pub struct CopyPartResult {
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    pub last_modified: String,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename = "Delete")]
+pub struct Delete {
+    #[serde(rename = "Quiet", default)]
+    pub quiet: bool,
+    #[serde(rename = "Object", default)]
+    pub objects: Vec<ObjectIdentifier>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectIdentifier {
+    pub key: String,
+    #[serde(default)]
+    pub version_id: Option<String>,
+}
+#[derive(Debug, Default, Serialize)]
+#[serde(rename = "DeleteResult")]
+pub struct DeleteResult {
+    #[serde(rename = "Deleted")]
+    pub deleted: Vec<DeletedObject>,
+    #[serde(rename = "Error")]
+    pub errors: Vec<DeleteError>,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeletedObject {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_marker: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_marker_version_id: Option<String>,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeleteError {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+    pub code: String,
+    pub message: String,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename = "ListVersionsResult", rename_all = "PascalCase")]
+pub struct ListVersionsResult {
+    pub name: String,
+    pub prefix: String,
+    pub key_marker: String,
+    pub version_id_marker: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_key_marker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_version_id_marker: Option<String>,
+    pub max_keys: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_type: Option<String>,
+    pub is_truncated: bool,
+    /// versions and delete markers interleaved, in listing order
+    #[serde(rename = "$value")]
+    pub entries: Vec<VersionEntry>,
+    #[serde(rename = "CommonPrefixes")]
+    pub common_prefixes: Vec<CommonPrefix>,
+}
+#[derive(Debug, Serialize)]
+pub enum VersionEntry {
+    Version(ObjectVersion),
+    DeleteMarker(DeleteMarkerEntry),
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectVersion {
+    pub key: String,
+    pub version_id: String,
+    pub is_latest: bool,
+    pub last_modified: String,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    pub size: u64,
+    pub owner: Owner,
+    pub storage_class: String,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeleteMarkerEntry {
+    pub key: String,
+    pub version_id: String,
+    pub is_latest: bool,
+    pub last_modified: String,
+    pub owner: Owner,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommonPrefix {
+    pub prefix: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename = "ListBucketResult", rename_all = "PascalCase")]
+pub struct ListBucketResult {
+    pub name: String,
+    pub prefix: String,
+    pub marker: String,
+    /// only sent with a delimiter, without one the last key is the next marker
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_marker: Option<String>,
+    pub max_keys: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_type: Option<String>,
+    pub is_truncated: bool,
+    #[serde(rename = "Contents")]
+    pub contents: Vec<ListedObject>,
+    #[serde(rename = "CommonPrefixes")]
+    pub common_prefixes: Vec<CommonPrefix>,
+}
+/// ListObjectsV2, same root element as v1.
+#[derive(Debug, Serialize)]
+#[serde(rename = "ListBucketResult", rename_all = "PascalCase")]
+pub struct ListBucketResultV2 {
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_after: Option<String>,
+    pub key_count: u32,
+    pub max_keys: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_type: Option<String>,
+    pub is_truncated: bool,
+    #[serde(rename = "Contents")]
+    pub contents: Vec<ListedObject>,
+    #[serde(rename = "CommonPrefixes")]
+    pub common_prefixes: Vec<CommonPrefix>,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ListedObject {
+    pub key: String,
+    pub last_modified: String,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    pub size: u64,
+    /// v2 only has it with `fetch-owner=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    pub storage_class: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "AccessControlPolicy", rename_all = "PascalCase")]
+pub struct AccessControlPolicy {
+    pub owner: Owner,
+    pub access_control_list: AccessControlList,
+}
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct AccessControlList {
+    #[serde(rename = "Grant", default)]
+    pub grants: Vec<Grant>,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Grant {
+    pub grantee: Grantee,
+    pub permission: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grantee {
+    #[serde(rename = "@xmlns:xsi", default = "xsi_namespace")]
+    pub xmlns_xsi: String,
+    /// `CanonicalUser`, `Group` or `AmazonCustomerByEmail`. the deserializer drops the
+    /// prefix, so it's read back as `@type`
+    #[serde(rename = "@xsi:type", alias = "@type")]
+    pub grantee_type: String,
+    #[serde(rename = "ID", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "DisplayName", default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(rename = "URI", default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    #[serde(rename = "EmailAddress", default, skip_serializing_if = "Option::is_none")]
+    pub email_address: Option<String>,
+}
+fn xsi_namespace() -> String {
+    XSI_NAMESPACE.to_string()
+}
+impl AccessControlPolicy {
+    /// The only ACL lumi has: the owner with full control.
+    pub fn private(owner: Owner) -> Self {
+        let grantee = Grantee {
+            xmlns_xsi: xsi_namespace(),
+            grantee_type: "CanonicalUser".into(),
+            id: Some(owner.id.clone()),
+            display_name: Some(owner.display_name.clone()),
+            uri: None,
+            email_address: None,
+        };
+        AccessControlPolicy {
+            owner,
+            access_control_list: AccessControlList {
+                grants: vec![Grant {
+                    grantee,
+                    permission: "FULL_CONTROL".into(),
+                }],
+            },
+        }
+    }
+}
+
+/// Timestamps the way S3 writes them in xml bodies, `2009-10-12T17:50:30.000Z`.
+pub fn timestamp(date: &chrono::DateTime<chrono::Utc>) -> String {
+    date.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a payload, writes it back out and parses that again, both writes have to
+    /// come out the same.
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(payload: &str) -> (T, String) {
+        let parsed: T = from_xml(payload.as_bytes()).unwrap();
+        let written = to_xml(&parsed).unwrap();
+        let reparsed: T = from_xml(written.as_bytes()).unwrap();
+        assert_eq!(to_xml(&reparsed).unwrap(), written);
+        (parsed, written)
+    }
+
+    // payloads below as S3 and the sdks send them, namespaces and whitespace included
+    #[test]
+    fn test_request_documents() {
+        let complete: CompleteMultipartUpload = from_xml(br#"<?xml version="1.0" encoding="UTF-8"?>
+<CompleteMultipartUpload xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Part><ETag>"a54357aff0632cce46d942af68356b38"</ETag><PartNumber>1</PartNumber></Part>
+  <Part><ETag>"0c78aef83f66abc1fa1e8477f296d394"</ETag><PartNumber>2</PartNumber></Part>
+</CompleteMultipartUpload>"#).unwrap();
+        assert_eq!(complete.parts.len(), 2);
+        assert_eq!(complete.parts[1].part_number, 2);
+        assert_eq!(complete.parts[0].etag, "\"a54357aff0632cce46d942af68356b38\"");
+
+        let delete: Delete = from_xml(br#"<Delete xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Quiet>true</Quiet>
+  <Object><Key>sample1.txt</Key></Object>
+  <Object><Key>sample2.txt</Key><VersionId>OYcLXagmS.WaD..oyH4KRguB95_YhLs7</VersionId></Object>
+</Delete>"#).unwrap();
+        assert!(delete.quiet);
+        assert_eq!(delete.objects[1].version_id.as_deref(), Some("OYcLXagmS.WaD..oyH4KRguB95_YhLs7"));
+
+        assert_eq!(from_xml::<Delete>(b"<Delete><Object>").unwrap_err(), lumi_utils::errors::ErrorCode::MalformedXml);
+    }
+
+    #[test]
+    fn test_configuration_round_trips() {
+        let (tagging, written) = round_trip::<Tagging>(r#"<Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <TagSet><Tag><Key>Project</Key><Value>Project One</Value></Tag><Tag><Key>User</Key><Value>jsmith</Value></Tag></TagSet>
+</Tagging>"#);
+        assert_eq!(tagging.tag_set.tags[1].value, "jsmith");
+        assert!(written.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><Tagging xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><TagSet>"#));
+
+        let (versioning, _) = round_trip::<VersioningConfiguration>(
+            r#"<VersioningConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Status>Enabled</Status></VersioningConfiguration>"#,
+        );
+        assert_eq!(versioning.status.as_deref(), Some("Enabled"));
+
+        let (cors, _) = round_trip::<CorsConfiguration>(r#"<CORSConfiguration>
+ <CORSRule>
+   <AllowedOrigin>http://www.example.com</AllowedOrigin>
+   <AllowedMethod>PUT</AllowedMethod>
+   <AllowedMethod>POST</AllowedMethod>
+   <AllowedMethod>DELETE</AllowedMethod>
+   <AllowedHeader>*</AllowedHeader>
+   <MaxAgeSeconds>3000</MaxAgeSeconds>
+   <ExposeHeader>x-amz-server-side-encryption</ExposeHeader>
+ </CORSRule>
+ <CORSRule>
+   <AllowedOrigin>*</AllowedOrigin>
+   <AllowedMethod>GET</AllowedMethod>
+ </CORSRule>
+</CORSConfiguration>"#);
+        assert_eq!(cors.rules[0].allowed_methods, ["PUT", "POST", "DELETE"]);
+        assert_eq!(cors.rules[0].max_age_seconds, Some(3000));
+        assert!(cors.rules[1].allowed_headers.is_empty());
+
+        let (lifecycle, _) = round_trip::<LifecycleConfiguration>(r#"<LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ID>id1</ID>
+    <Filter><And><Prefix>documents/</Prefix><Tag><Key>key1</Key><Value>value1</Value></Tag></And></Filter>
+    <Status>Enabled</Status>
+    <Expiration><Days>365</Days></Expiration>
+    <NoncurrentVersionExpiration><NoncurrentDays>30</NoncurrentDays></NoncurrentVersionExpiration>
+  </Rule>
+  <Rule>
+    <ID>abort</ID>
+    <Filter><Prefix></Prefix></Filter>
+    <Status>Disabled</Status>
+    <AbortIncompleteMultipartUpload><DaysAfterInitiation>7</DaysAfterInitiation></AbortIncompleteMultipartUpload>
+  </Rule>
+</LifecycleConfiguration>"#);
+        assert_eq!(lifecycle.rules.len(), 2);
+        let and = lifecycle.rules[0].filter.as_ref().unwrap().and.as_ref().unwrap();
+        assert_eq!(and.prefix.as_deref(), Some("documents/"));
+        assert_eq!(lifecycle.rules[1].abort_incomplete_multipart_upload.as_ref().unwrap().days_after_initiation, 7);
+
+        let (acl, written) = round_trip::<AccessControlPolicy>(r#"<?xml version="1.0" encoding="UTF-8"?>
+<AccessControlPolicy xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Owner><ID>75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a</ID><DisplayName>CustomersName@amazon.com</DisplayName></Owner>
+  <AccessControlList>
+    <Grant>
+      <Grantee xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="CanonicalUser">
+        <ID>75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a</ID>
+        <DisplayName>CustomersName@amazon.com</DisplayName>
+      </Grantee>
+      <Permission>FULL_CONTROL</Permission>
+    </Grant>
+  </AccessControlList>
+</AccessControlPolicy>"#);
+        assert_eq!(acl.access_control_list.grants[0].grantee.grantee_type, "CanonicalUser");
+        assert_eq!(acl, AccessControlPolicy::private(acl.owner.clone()));
+        assert!(written.contains(r#"<Grantee xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="CanonicalUser"><ID>"#));
+    }
+
+    #[test]
+    fn test_response_documents() {
+        let result = ListBucketResultV2 {
+            name: "bucket".into(),
+            prefix: String::new(),
+            continuation_token: None,
+            next_continuation_token: None,
+            start_after: None,
+            key_count: 1,
+            max_keys: 1000,
+            delimiter: None,
+            encoding_type: None,
+            is_truncated: false,
+            contents: vec![ListedObject {
+                key: "my-image.jpg".into(),
+                last_modified: "2009-10-12T17:50:30.000Z".into(),
+                etag: "\"fba9dede5f27731c9771645a39863328\"".into(),
+                size: 434234,
+                owner: Some(Owner::default()),
+                storage_class: "STANDARD".into(),
+            }],
+            common_prefixes: Vec::new(),
+        };
+        assert_eq!(
+            to_xml(&result).unwrap(),
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+                "<Name>bucket</Name><Prefix/><KeyCount>1</KeyCount><MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated>",
+                "<Contents><Key>my-image.jpg</Key><LastModified>2009-10-12T17:50:30.000Z</LastModified>",
+                "<ETag>\"fba9dede5f27731c9771645a39863328\"</ETag><Size>434234</Size>",
+                "<Owner><ID>lumiserver</ID><DisplayName>lumiserver</DisplayName></Owner>",
+                "<StorageClass>STANDARD</StorageClass></Contents>",
+                "</ListBucketResult>"
+            )
+        );
+        let location = to_xml(&LocationConstraint { region: String::new() }).unwrap();
+        assert!(location.ends_with(r#"<LocationConstraint xmlns="http://s3.amazonaws.com/doc/2006-03-01/"/>"#));
+    }
+
+    #[test]
+    fn test_ok_response() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amz-version-id", HeaderValue::from_static("null"));
+        let response = ok_response(&LocationConstraint { region: "eu-west-1".into() }, headers);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/xml");
+        assert_eq!(response.headers()["x-amz-version-id"], "null");
+    }
+}
@@ -3,7 +3,6 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct APIError {
@@ -13,6 +12,7 @@ pub struct APIError {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename = "Error")]
 pub struct RestErrorResponse {
     #[serde(rename = "Code")]
     pub code: String,
@@ -22,10 +22,29 @@ pub struct RestErrorResponse {
     pub resource: String,
     #[serde(rename = "RequestId")]
     pub request_id: String,
+    #[serde(rename = "HostId", default, skip_serializing_if = "String::is_empty")]
+    pub host_id: String,
     #[serde(rename = "Key", skip_serializing_if = "Option::is_none")]
     pub key: Option<String>,
     #[serde(rename = "BucketName", skip_serializing_if = "Option::is_none")]
     pub bucket_name: Option<String>,
+    /// the query parameter or header that was wrong
+    #[serde(rename = "ArgumentName", skip_serializing_if = "Option::is_none")]
+    pub argument_name: Option<String>,
+    #[serde(rename = "ArgumentValue", skip_serializing_if = "Option::is_none")]
+    pub argument_value: Option<String>,
+    /// the region the request should have been signed for
+    #[serde(rename = "Region", skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// SignatureDoesNotMatch details, only given out when the server is asked to
+    #[serde(rename = "AWSAccessKeyId", skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(rename = "SignatureProvided", skip_serializing_if = "Option::is_none")]
+    pub signature_provided: Option<String>,
+    #[serde(rename = "StringToSign", skip_serializing_if = "Option::is_none")]
+    pub string_to_sign: Option<String>,
+    #[serde(rename = "CanonicalRequest", skip_serializing_if = "Option::is_none")]
+    pub canonical_request: Option<String>,
     #[serde(skip)]
     pub status_code: StatusCode,
 }
@@ -53,6 +72,7 @@ pub enum ErrorCode {
     InvalidMaxDeleteObjects,
     InvalidPartNumberMarker,
     InvalidPart,
+    InvalidPartOrder,
     InvalidRange,
     InternalError,
     InvalidCopyDest,
@@ -96,9 +116,34 @@ pub enum ErrorCode {
     RequestBytesExceed,
     OwnershipControlsNotFoundError,
     NoSuchTagSet,
+    InvalidArgument,
+    ServerSideEncryptionConfigurationNotFound,
+    KmsKeyNotFound,
+    KmsKeyDisabled,
+    KmsKeyAlreadyExists,
+    MetadataTooLarge,
+    InvalidPartNumber,
+    NoSuchVersion,
+    InvalidBucketState,
+    ObjectLockConfigurationNotFound,
+    NoSuchObjectLockConfiguration,
+    MissingObjectLockConfiguration,
+    CorsForbidden,
+    NoSuchWebsiteConfiguration,
+    InvalidRedirectLocation,
+    WrongRegion,
+    IllegalLocationConstraint,
 }
 
 impl ErrorCode {
+    /// The error about one query parameter or header, named in the response.
+    pub fn argument(self, name: &str, value: &str) -> RestErrorResponse {
+        let mut err = RestErrorResponse::from(self);
+        err.argument_name = Some(name.to_string());
+        err.argument_value = Some(value.to_string());
+        err
+    }
+
     pub fn to_api_error(self) -> APIError {
         match self {
             ErrorCode::AccessDenied => APIError {
@@ -146,7 +191,42 @@ impl ErrorCode {
                 description: "The specified key does not exist.".into(),
                 http_status_code: 404,
             },
-            ErrorCode::InternalError => APIError {
+            ErrorCode::NoSuchUpload => APIError {
+                code: "NoSuchUpload".into(),
+                description: "The specified multipart upload does not exist. The upload ID might be invalid, or the multipart upload might have been aborted or completed.".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::InvalidPart => APIError {
+                code: "InvalidPart".into(),
+                description: "One or more of the specified parts could not be found. The part might not have been uploaded, or the specified entity tag might not have matched the part's entity tag.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidPartOrder => APIError {
+                code: "InvalidPartOrder".into(),
+                description: "The list of parts was not in ascending order. The parts list must be specified in order by part number.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::EntityTooSmall => APIError {
+                code: "EntityTooSmall".into(),
+                description: "Your proposed upload is smaller than the minimum allowed object size.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::EntityTooLarge => APIError {
+                code: "EntityTooLarge".into(),
+                description: "Your proposed upload exceeds the maximum allowed object size.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidDigest => APIError {
+                code: "InvalidDigest".into(),
+                description: "The Content-MD5 you specified is not valid.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidMaxDeleteObjects => APIError {
+                code: "InvalidArgument".into(),
+                description: "Argument objects can contain a list of up to 1000 keys".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::None | ErrorCode::InternalError => APIError {
                 code: "InternalError".into(),
                 description: "We encountered an internal error, please try again.".into(),
                 http_status_code: 500,
@@ -187,18 +267,306 @@ impl ErrorCode {
                 description: "The request was invalid.".into(),
                 http_status_code: 400,
             },
-            _ => APIError {
-                code: "InternalError".into(),
-                description: "An internal error occurred".into(),
-                http_status_code: 500,
+            ErrorCode::MalformedXml => APIError {
+                code: "MalformedXML".into(),
+                description: "The XML you provided was not well-formed or did not validate against our published schema.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidArgument => APIError {
+                code: "InvalidArgument".into(),
+                description: "Invalid Argument".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::ServerSideEncryptionConfigurationNotFound => APIError {
+                code: "ServerSideEncryptionConfigurationNotFoundError".into(),
+                description: "The server side encryption configuration was not found".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::KmsKeyNotFound => APIError {
+                code: "KMS.NotFoundException".into(),
+                description: "The specified KMS key does not exist.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::KmsKeyDisabled => APIError {
+                code: "KMS.DisabledException".into(),
+                description: "The specified KMS key is disabled.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::KmsKeyAlreadyExists => APIError {
+                code: "KMS.AlreadyExistsException".into(),
+                description: "A KMS key with that id already exists.".into(),
+                http_status_code: 409,
+            },
+            ErrorCode::MetadataTooLarge => APIError {
+                code: "MetadataTooLarge".into(),
+                description: "Your metadata headers exceed the maximum allowed metadata size.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidCopySource => APIError {
+                code: "InvalidArgument".into(),
+                description: "Copy Source must mention the source bucket and key: sourcebucket/sourcekey.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidCopyDest => APIError {
+                code: "InvalidRequest".into(),
+                description: "This copy request is illegal because it is trying to copy an object to itself without changing the object's metadata, storage class, website redirect location or encryption attributes.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::NoSuchVersion => APIError {
+                code: "NoSuchVersion".into(),
+                description: "The specified version does not exist.".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::PreconditionFailed => APIError {
+                code: "PreconditionFailed".into(),
+                description: "At least one of the pre-conditions you specified did not hold".into(),
+                http_status_code: 412,
+            },
+            ErrorCode::InvalidRange => APIError {
+                code: "InvalidRange".into(),
+                description: "The requested range is not satisfiable".into(),
+                http_status_code: 416,
+            },
+            ErrorCode::InvalidPartNumber => APIError {
+                code: "InvalidPartNumber".into(),
+                description: "The requested partnumber is not satisfiable".into(),
+                http_status_code: 416,
+            },
+            ErrorCode::InvalidTag => APIError {
+                code: "InvalidTag".into(),
+                description: "The tag provided was not a valid tag.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::NoSuchTagSet => APIError {
+                code: "NoSuchTagSet".into(),
+                description: "The TagSet does not exist".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::NoSuchCorsConfiguration => APIError {
+                code: "NoSuchCORSConfiguration".into(),
+                description: "The CORS configuration does not exist".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::CorsForbidden => APIError {
+                code: "AccessForbidden".into(),
+                description: "CORSResponse: This CORS request is not allowed. This is usually because the evalution of Origin, request method / Access-Control-Request-Method or Access-Control-Request-Headers are not whitelisted by the resource's CORS spec.".into(),
+                http_status_code: 403,
+            },
+            ErrorCode::NoSuchWebsiteConfiguration => APIError {
+                code: "NoSuchWebsiteConfiguration".into(),
+                description: "The specified bucket does not have a website configuration".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::InvalidRedirectLocation => APIError {
+                code: "InvalidRedirectLocation".into(),
+                description: "The website redirect location must have a prefix of 'http://' or 'https://' or '/'.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::WrongRegion => APIError {
+                code: "AuthorizationHeaderMalformed".into(),
+                description: "The authorization header is malformed; the region in the credential scope is wrong for this server.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::IllegalLocationConstraint => APIError {
+                code: "IllegalLocationConstraintException".into(),
+                description: "The location constraint is incompatible for the region specific endpoint this request was sent to.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::NoSuchLifecycleConfiguration => APIError {
+                code: "NoSuchLifecycleConfiguration".into(),
+                description: "The lifecycle configuration does not exist".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::InvalidBucketState => APIError {
+                code: "InvalidBucketState".into(),
+                description: "The request is not valid with the current state of the bucket.".into(),
+                http_status_code: 409,
+            },
+            ErrorCode::ObjectLockConfigurationNotFound => APIError {
+                code: "ObjectLockConfigurationNotFoundError".into(),
+                description: "Object Lock configuration does not exist for this bucket".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::NoSuchObjectLockConfiguration => APIError {
+                code: "NoSuchObjectLockConfiguration".into(),
+                description: "The specified object does not have a ObjectLock configuration".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::MissingObjectLockConfiguration => APIError {
+                code: "InvalidRequest".into(),
+                description: "Bucket is missing Object Lock Configuration".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::NoSuchBucketPolicy => APIError {
+                code: "NoSuchBucketPolicy".into(),
+                description: "The bucket policy does not exist".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::OwnershipControlsNotFoundError => APIError {
+                code: "OwnershipControlsNotFoundError".into(),
+                description: "The bucket ownership controls were not found".into(),
+                http_status_code: 404,
+            },
+            ErrorCode::InvalidMaxKeys => APIError {
+                code: "InvalidArgument".into(),
+                description: "Argument maxKeys must be an integer between 0 and 2147483647".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidMaxUploads => APIError {
+                code: "InvalidArgument".into(),
+                description: "Argument max-uploads must be an integer between 0 and 2147483647".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidMaxParts => APIError {
+                code: "InvalidArgument".into(),
+                description: "Argument max-parts must be an integer between 0 and 2147483647".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidPartNumberMarker => APIError {
+                code: "InvalidArgument".into(),
+                description: "Argument partNumberMarker must be an integer.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::AuthHeaderEmpty => APIError {
+                code: "InvalidArgument".into(),
+                description: "Authorization header is invalid -- one and only one ' ' (space) required.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::SignatureVersionNotSupported => APIError {
+                code: "InvalidRequest".into(),
+                description: "The authorization mechanism you have provided is not supported. Please use AWS4-HMAC-SHA256.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::AuthNotSetup => APIError {
+                code: "InvalidRequest".into(),
+                description: "Signature Version 4 authentication is not set up on this server.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MissingFields => APIError {
+                code: "MissingFields".into(),
+                description: "Missing fields in request.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MissingSignHeadersTag => APIError {
+                code: "InvalidArgument".into(),
+                description: "Signature header missing SignedHeaders field.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MissingSignTag => APIError {
+                code: "AccessDenied".into(),
+                description: "Signature header missing Signature field.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::UnsignedHeaders => APIError {
+                code: "AccessDenied".into(),
+                description: "There were headers present in the request which were not signed".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::ContentSha256Mismatch => APIError {
+                code: "XAmzContentSHA256Mismatch".into(),
+                description: "The provided 'x-amz-content-sha256' header does not match what was computed.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MissingDateHeader => APIError {
+                code: "AccessDenied".into(),
+                description: "AWS authentication requires a valid Date or x-amz-date header".into(),
+                http_status_code: 403,
+            },
+            ErrorCode::MalformedDate => APIError {
+                code: "MalformedDate".into(),
+                description: "Invalid date format header, expected to be in ISO8601, RFC1123 or RFC1123Z time format.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::RequestNotReadyYet => APIError {
+                code: "AccessDenied".into(),
+                description: "Request is not valid yet".into(),
+                http_status_code: 403,
+            },
+            ErrorCode::InvalidQueryParams => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "Query-string authentication version 4 requires the X-Amz-Algorithm, X-Amz-Credential, X-Amz-Signature, X-Amz-Date, X-Amz-SignedHeaders, and X-Amz-Expires parameters.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::InvalidQuerySignatureAlgo => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "X-Amz-Algorithm only supports \"AWS4-HMAC-SHA256\".".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MalformedPresignedDate => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "X-Amz-Date must be in the ISO8601 Long Format \"yyyyMMdd'T'HHmmss'Z'\"".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MalformedCredentialDate => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "Error parsing the X-Amz-Credential parameter; incorrect date format. This date in the credential must be in the format \"yyyyMMdd\".".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MalformedExpires => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "X-Amz-Expires should be a number".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::NegativeExpires => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "X-Amz-Expires must be non-negative".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::MaximumExpires => APIError {
+                code: "AuthorizationQueryParametersError".into(),
+                description: "X-Amz-Expires must be less than a week (in seconds) that is 604800".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::ExpiredPresignRequest => APIError {
+                code: "AccessDenied".into(),
+                description: "Request has expired".into(),
+                http_status_code: 403,
+            },
+            ErrorCode::MalformedPostRequest => APIError {
+                code: "MalformedPOSTRequest".into(),
+                description: "The body of your POST request is not well-formed multipart/form-data.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::PostFileRequired => APIError {
+                code: "InvalidArgument".into(),
+                description: "POST requires exactly one file upload per request.".into(),
+                http_status_code: 400,
+            },
+            ErrorCode::PostPolicyConditionInvalidFormat => APIError {
+                code: "AccessDenied".into(),
+                description: "Invalid according to Policy: Policy Condition failed".into(),
+                http_status_code: 403,
+            },
+            ErrorCode::ExistingObjectIsDirectory => APIError {
+                code: "ObjectExistsAsDirectory".into(),
+                description: "Object name already exists as a directory.".into(),
+                http_status_code: 409,
+            },
+            ErrorCode::ExistingObjectIsFile => APIError {
+                code: "ParentIsObject".into(),
+                description: "Object-prefix is already an object, please choose a different object-prefix name.".into(),
+                http_status_code: 409,
+            },
+            ErrorCode::TooManyRequest => APIError {
+                code: "SlowDown".into(),
+                description: "Please reduce your request rate.".into(),
+                http_status_code: 503,
             },
         }
     }
 }
 
+/// Handlers don't know the request id or always the resource, both are left empty and
+/// filled in by the server's request id middleware (see `RestErrorResponse::complete`).
+impl From<ErrorCode> for RestErrorResponse {
+    fn from(code: ErrorCode) -> Self {
+        RestErrorResponse::from_error_code(code, String::new(), String::new())
+    }
+}
+
 impl IntoResponse for ErrorCode {
     fn into_response(self) -> Response {
-        RestErrorResponse::from_error_code(self, "/".into(), "unknown".into()).into_response()
+        RestErrorResponse::from(self).into_response()
     }
 }
 
@@ -209,7 +577,10 @@ impl IntoResponse for RestErrorResponse {
         match quick_xml::se::to_string(&self) {
             Ok(xml) => {
                 let response = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}"#, xml);
-                (status, [(header::CONTENT_TYPE, "application/xml")], response).into_response()
+                let mut response = (status, [(header::CONTENT_TYPE, "application/xml")], response).into_response();
+                // kept so the body can be rendered again once the rest is known
+                response.extensions_mut().insert(self);
+                response
             }
             Err(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -220,6 +591,13 @@ impl IntoResponse for RestErrorResponse {
     }
 }
 
+/// Boxed where it'd make a `Result` too big to pass around.
+impl IntoResponse for Box<RestErrorResponse> {
+    fn into_response(self) -> Response {
+        (*self).into_response()
+    }
+}
+
 impl RestErrorResponse {
     pub fn from_error_code(
         error_code: ErrorCode,
@@ -232,8 +610,16 @@ impl RestErrorResponse {
             message: api_error.description,
             resource,
             request_id,
+            host_id: String::new(),
             key: None,
             bucket_name: None,
+            argument_name: None,
+            argument_value: None,
+            region: None,
+            access_key_id: None,
+            signature_provided: None,
+            string_to_sign: None,
+            canonical_request: None,
             status_code: StatusCode::from_u16(api_error.http_status_code)
                 .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
         }
@@ -264,7 +650,68 @@ impl RestErrorResponse {
         err
     }
 
+    /// Fills in what the handler that failed couldn't know. `path` is the decoded path
+    /// style request path, `/bucket/key`.
+    pub fn complete(self, request_id: String, host_id: String, path: &str) -> Self {
+        let path = path.trim_start_matches('/');
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        let mut err = match self.code.as_str() {
+            "NoSuchBucket" if self.bucket_name.is_none() && !bucket.is_empty() => {
+                Self::no_such_bucket(request_id.clone(), bucket.to_string())
+            }
+            "NoSuchKey" if self.key.is_none() && !key.is_empty() => {
+                Self::no_such_key(request_id.clone(), bucket.to_string(), key.to_string())
+            }
+            _ => self,
+        };
+        if err.resource.is_empty() {
+            err.resource = format!("/{}", path);
+        }
+        err.request_id = request_id;
+        err.host_id = host_id;
+        err
+    }
+
     pub fn internal_error(request_id: String) -> Self {
         Self::from_error_code(ErrorCode::InternalError, "/".into(), request_id)
     }
-}
\ No newline at end of file
+
+    /// SignatureDoesNotMatch with what the server signed, for clients to diff against theirs.
+    pub fn signature_mismatch(
+        access_key: String,
+        signature: String,
+        string_to_sign: String,
+        canonical_request: String,
+    ) -> Self {
+        let mut err = Self::from(ErrorCode::SignatureDoesNotMatch);
+        err.access_key_id = Some(access_key);
+        err.signature_provided = Some(signature);
+        err.string_to_sign = Some(string_to_sign);
+        err.canonical_request = Some(canonical_request);
+        err
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_document() {
+        let err = ErrorCode::InvalidMaxKeys.argument("max-keys", "lots");
+        let err = err.complete("4442587FB7D0A2F9".into(), "aG9zdA==".into(), "/photos");
+        assert_eq!(err.status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            quick_xml::se::to_string(&err).unwrap(),
+            concat!(
+                "<Error><Code>InvalidArgument</Code>",
+                "<Message>Argument maxKeys must be an integer between 0 and 2147483647</Message>",
+                "<Resource>/photos</Resource><RequestId>4442587FB7D0A2F9</RequestId><HostId>aG9zdA==</HostId>",
+                "<ArgumentName>max-keys</ArgumentName><ArgumentValue>lots</ArgumentValue></Error>"
+            )
+        );
+
+        assert_eq!(ErrorCode::TooManyRequest.to_api_error().http_status_code, 503);
+        assert_eq!(ErrorCode::ExpiredPresignRequest.to_api_error().code, "AccessDenied");
+        assert_eq!(ErrorCode::None.to_api_error().code, "InternalError");
+    }
+}
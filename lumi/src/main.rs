@@ -1,6 +1,18 @@
 use clap::{Parser, Subcommand};
-use lumi_server::http::start_http_server;
+use lumi_credentials::{
+    credentials::{generate_access_key, generate_secret_key, KeyError},
+    envelope::MasterKey,
+    store::CredentialStore,
+    DEFAULT_CREDENTIALS_PATH, MAX_LEG_ACCESS_KEY, MAX_LEG_SECRET_KEY,
+};
+use lumi_server::{
+    http::{start_http_server, ServerConfig, DEFAULT_REGION},
+    storage,
+    tls::TlsConfig,
+};
 use lumi_utils::update::fetch_latest_github_release;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::fs::create_dir_all;
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_PKG_VERSION"), about = "lumi CLI")]
@@ -9,29 +21,175 @@ struct Args {
     command: Commands,
 }
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     Serve {
         #[arg(long, default_value = "0.0.0.0")]
         host: String,
         #[arg(default_value = "8080")]
         port: u64,
+        /// region clients sign requests for and new buckets are created in
+        #[arg(long, default_value = DEFAULT_REGION)]
+        region: String,
+        /// put the string to sign and canonical request in SignatureDoesNotMatch errors
+        #[arg(long)]
+        debug_signatures: bool,
         #[arg(long, default_value = "*")]
         allowed_origin: String,
+        /// hex master key used to decrypt stored secrets (falls back to $lumi_master_key)
+        #[arg(long)]
+        master_key_file: Option<PathBuf>,
+        /// pem certificate chain, enables https. reloaded automatically when it changes
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// pem private key for --tls-cert
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+        /// CA bundle to verify client certificates against (mutual TLS)
+        #[arg(long, requires = "tls_cert")]
+        tls_client_ca: Option<PathBuf>,
+        /// refuse connections that dont present a client certificate
+        #[arg(long, requires = "tls_client_ca")]
+        tls_client_cert_required: bool,
+        /// json map of client certificate names (cn:, dns:, uri:, email:, ip:, subject:) to access keys
+        #[arg(long, requires = "tls_client_ca")]
+        tls_identity_map: Option<PathBuf>,
+        /// hex master key for SSE-S3, generated in ./.lumi.sys if not given
+        #[arg(long)]
+        sse_key_file: Option<PathBuf>,
+        /// access key allowed to bypass GOVERNANCE object lock retention besides root, repeatable
+        #[arg(long = "governance-bypass", value_name = "ACCESS_KEY")]
+        governance_bypass: Vec<String>,
+        /// seconds between lifecycle rule scans, 0 turns them off
+        #[arg(long, default_value = "3600")]
+        lifecycle_interval: u64,
+        /// base domain for virtual-hosted style requests (`bucket.{domain}/key`), repeatable
+        #[arg(long = "domain", value_name = "DOMAIN")]
+        domains: Vec<String>,
+        /// port for static website hosting, off unless given
+        #[arg(long)]
+        website_port: Option<u64>,
+        /// website domain, `{bucket}.{domain}` is served from that bucket. repeatable
+        #[arg(long = "website-domain", value_name = "DOMAIN", requires = "website_port")]
+        website_domains: Vec<String>,
+    },
+    Credentials {
+        #[command(subcommand)]
+        command: CredentialCommands,
     },
     Update,
 }
+#[derive(Subcommand, Debug)]
+enum CredentialCommands {
+    /// Print a new random master key
+    GenerateMasterKey,
+    /// Generate an access/secret key pair and persist it encrypted
+    Create {
+        #[arg(long)]
+        master_key_file: Option<PathBuf>,
+    },
+    /// Re-encrypt every stored secret under a new master key
+    Rekey {
+        #[arg(long)]
+        master_key_file: Option<PathBuf>,
+        #[arg(long)]
+        new_master_key_file: PathBuf,
+    },
+}
+
+fn open_store(master_key_file: Option<PathBuf>) -> Result<CredentialStore, KeyError> {
+    let master = MasterKey::load(master_key_file.as_deref())?.ok_or(KeyError::MissingMasterKey)?;
+    CredentialStore::open(DEFAULT_CREDENTIALS_PATH, master)
+}
+
+fn run_credentials(command: CredentialCommands) -> Result<(), KeyError> {
+    match command {
+        CredentialCommands::GenerateMasterKey => {
+            println!("{}", MasterKey::generate().to_hex());
+        }
+        CredentialCommands::Create { master_key_file } => {
+            let mut store = open_store(master_key_file)?;
+            let access_key = generate_access_key(MAX_LEG_ACCESS_KEY)?;
+            let secret_key = generate_secret_key(MAX_LEG_SECRET_KEY)?;
+            store.insert(&access_key, &secret_key)?;
+            store.save()?;
+            println!("access key: {}", access_key);
+            println!("secret key: {}", secret_key);
+        }
+        CredentialCommands::Rekey {
+            master_key_file,
+            new_master_key_file,
+        } => {
+            let mut store = open_store(master_key_file)?;
+            store.rekey(MasterKey::from_file(&new_master_key_file)?)?;
+            println!(
+                "\x1b[32mRe-encrypted {} secrets in {}\x1b[0m",
+                store.access_keys().count(),
+                store.path().display()
+            );
+        }
+    }
+    Ok(())
+}
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let data_dir = "./data";
     create_dir_all(data_dir).await?;
+    storage::migrate_sys_dir().await?;
     let args = Args::parse();
     match args.command {
         Commands::Serve {
             host,
             port,
+            region,
+            debug_signatures,
             allowed_origin,
+            master_key_file,
+            tls_cert,
+            tls_key,
+            tls_client_ca,
+            tls_client_cert_required,
+            tls_identity_map,
+            sse_key_file,
+            governance_bypass,
+            lifecycle_interval,
+            domains,
+            website_port,
+            website_domains,
         } => {
-            start_http_server(host, port, allowed_origin).await?;
+            let tls = match (tls_cert, tls_key) {
+                (Some(cert), Some(key)) => Some(TlsConfig {
+                    cert,
+                    key,
+                    client_ca: tls_client_ca,
+                    client_cert_required: tls_client_cert_required,
+                }),
+                _ => None,
+            };
+            start_http_server(ServerConfig {
+                host,
+                port,
+                region,
+                debug_signatures,
+                allowed_origins: allowed_origin,
+                master_key_file,
+                tls,
+                cert_identity_map: tls_identity_map,
+                sse_key_file,
+                governance_bypass,
+                lifecycle_interval: (lifecycle_interval > 0).then(|| Duration::from_secs(lifecycle_interval)),
+                domains,
+                website_port,
+                website_domains,
+            })
+            .await?;
+            Ok(())
+        }
+        Commands::Credentials { command } => {
+            if let Err(e) = run_credentials(command) {
+                println!("\x1b[31m{}\x1b[0m", e);
+                std::process::exit(1);
+            }
             Ok(())
         }
         Commands::Update => {